@@ -0,0 +1,28 @@
+// Times each stage of the pipeline separately on one generated expression:
+// `cargo bench --bench pipeline`.
+use std::hint::black_box;
+use std::rc::Rc;
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use simple_rust_calculator::{evaluate, parse, tokenize_all};
+
+// A long, flat sum of small products, e.g. `(1 * 2) + (2 * 3) + ...`. It stays shallow, so the
+// parser's nesting cap is never hit however many terms there are.
+fn large_expression(terms: usize) -> String {
+    (1..=terms).map(|term| format!("({} * {})", term % 10, term % 7)).collect::<Vec<_>>().join(" + ")
+}
+
+fn pipeline(c: &mut Criterion) {
+    let input = large_expression(1_000);
+    let tokens = tokenize_all(&input);
+    let ast = Rc::new(parse(tokens.clone()).unwrap());
+    // Time the successful path, not an early error.
+    evaluate(ast.clone()).expect("the benchmark expression evaluates");
+
+    c.bench_function("tokenize", |b| b.iter(|| tokenize_all(black_box(&input))));
+    c.bench_function("parse", |b| b.iter(|| parse(black_box(tokens.clone()))));
+    c.bench_function("evaluate", |b| b.iter(|| evaluate(black_box(ast.clone()))));
+}
+
+criterion_group!(benches, pipeline);
+criterion_main!(benches);
@@ -0,0 +1,1317 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashSet;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::mem;
+use std::rc::Rc;
+
+use crate::evaluator::BUILTINS;
+use crate::parser::{
+    operator_binding, Associativity, ASSIGNMENT_PRECEDENCE, COMPARISON_PRECEDENCE, CONDITIONAL_PRECEDENCE, POSTFIX_PRECEDENCE,
+    POWER_PRECEDENCE, PREFIX_PRECEDENCE,
+};
+use crate::tokenizer::Op;
+use crate::Integer;
+
+#[derive(Clone, PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum AstNode {
+    Add(Rc<AstNode>, Rc<AstNode>),
+    Subtract(Rc<AstNode>, Rc<AstNode>),
+    Multiply(Rc<AstNode>, Rc<AstNode>),
+    Divide(Rc<AstNode>, Rc<AstNode>),
+    FloorDivide(Rc<AstNode>, Rc<AstNode>),
+    Modulo(Rc<AstNode>, Rc<AstNode>),
+    Assign(Rc<AstNode>, Rc<AstNode>),
+    // `x := value` stores like `=` but evaluates to the value `x` held before.
+    AssignReturnOld(Rc<AstNode>, Rc<AstNode>),
+    ConstAssign(String, Rc<AstNode>),
+    Identifier(String),
+    IntLiteral(Integer),
+    FloatLiteral(f64),
+    FunctionDef(String, Vec<String>, Rc<AstNode>),
+    Call(String, Vec<Rc<AstNode>>),
+    Equal(Rc<AstNode>, Rc<AstNode>),
+    NotEqual(Rc<AstNode>, Rc<AstNode>),
+    Less(Rc<AstNode>, Rc<AstNode>),
+    LessEqual(Rc<AstNode>, Rc<AstNode>),
+    Greater(Rc<AstNode>, Rc<AstNode>),
+    GreaterEqual(Rc<AstNode>, Rc<AstNode>),
+    And(Rc<AstNode>, Rc<AstNode>),
+    Or(Rc<AstNode>, Rc<AstNode>),
+    Not(Rc<AstNode>),
+    // Prefix `-x`.
+    Negate(Rc<AstNode>),
+    Factorial(Rc<AstNode>),
+    // Postfix `x%`, meaning `x / 100`.
+    Percent(Rc<AstNode>),
+    BitAnd(Rc<AstNode>, Rc<AstNode>),
+    BitOr(Rc<AstNode>, Rc<AstNode>),
+    ShiftLeft(Rc<AstNode>, Rc<AstNode>),
+    ShiftRight(Rc<AstNode>, Rc<AstNode>),
+    Power(Rc<AstNode>, Rc<AstNode>),
+    Conditional(Rc<AstNode>, Rc<AstNode>, Rc<AstNode>),
+    Block(Vec<Rc<AstNode>>),
+    // `let a = 1, b = a + 1 in body`: the bindings, in order, and the body they are visible in.
+    Let(Vec<(String, Rc<AstNode>)>, Rc<AstNode>),
+    // A chained comparison `a < b <= c`: the comparisons `a < b` and `b <= c`, all of which must
+    // hold. Each one's left operand is the previous one's right, and is evaluated only there.
+    Chain(Vec<Rc<AstNode>>),
+    // The node starts at this character offset of the parsed input. Only `parse_located` builds
+    // these, around the nodes whose evaluation can fail, so runtime errors can point into the
+    // source. Otherwise transparent.
+    Located(usize, Rc<AstNode>),
+}
+
+// Float literals rule out deriving `Hash`. Floats hash by their bits, except that `0.0` and `-0.0`,
+// which compare equal, hash alike.
+impl Hash for AstNode {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        mem::discriminant(self).hash(state);
+        match self {
+            AstNode::Add(left, right)
+            | AstNode::Subtract(left, right)
+            | AstNode::Multiply(left, right)
+            | AstNode::Divide(left, right)
+            | AstNode::FloorDivide(left, right)
+            | AstNode::Modulo(left, right)
+            | AstNode::Assign(left, right)
+            | AstNode::AssignReturnOld(left, right)
+            | AstNode::Equal(left, right)
+            | AstNode::NotEqual(left, right)
+            | AstNode::Less(left, right)
+            | AstNode::LessEqual(left, right)
+            | AstNode::Greater(left, right)
+            | AstNode::GreaterEqual(left, right)
+            | AstNode::And(left, right)
+            | AstNode::Or(left, right)
+            | AstNode::BitAnd(left, right)
+            | AstNode::BitOr(left, right)
+            | AstNode::ShiftLeft(left, right)
+            | AstNode::ShiftRight(left, right)
+            | AstNode::Power(left, right) => {
+                left.hash(state);
+                right.hash(state);
+            },
+            AstNode::Not(operand) | AstNode::Negate(operand) | AstNode::Factorial(operand) | AstNode::Percent(operand) =>
+                operand.hash(state),
+            AstNode::Conditional(condition, then_branch, else_branch) => {
+                condition.hash(state);
+                then_branch.hash(state);
+                else_branch.hash(state);
+            },
+            AstNode::ConstAssign(name, value) => {
+                name.hash(state);
+                value.hash(state);
+            },
+            AstNode::Identifier(name) => name.hash(state),
+            AstNode::IntLiteral(value) => value.hash(state),
+            AstNode::FloatLiteral(value) => float_bits(*value).hash(state),
+            AstNode::FunctionDef(name, parameters, body) => {
+                name.hash(state);
+                parameters.hash(state);
+                body.hash(state);
+            },
+            AstNode::Call(name, arguments) => {
+                name.hash(state);
+                arguments.hash(state);
+            },
+            AstNode::Block(nodes) | AstNode::Chain(nodes) => nodes.hash(state),
+            AstNode::Let(bindings, body) => {
+                bindings.hash(state);
+                body.hash(state);
+            },
+            AstNode::Located(position, inner) => {
+                position.hash(state);
+                inner.hash(state);
+            },
+        }
+    }
+}
+
+pub(crate) fn float_bits(value: f64) -> u64 {
+    if value == 0.0 { 0 } else { value.to_bits() }
+}
+
+// A hash of the tree's structure, equal for trees that compare equal: `1+2` and `(1 + 2)` parse
+// to the same tree, so they hash alike. Stable within one build of the library, not across
+// versions.
+pub fn ast_hash(node: &AstNode) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    node.hash(&mut hasher);
+    hasher.finish()
+}
+
+// The operator and operands of a comparison node.
+pub(crate) fn comparison_parts(node: &AstNode) -> Option<(&'static str, &Rc<AstNode>, &Rc<AstNode>)> {
+    match node {
+        AstNode::Equal(left, right) => Some(("==", left, right)),
+        AstNode::NotEqual(left, right) => Some(("!=", left, right)),
+        AstNode::Less(left, right) => Some(("<", left, right)),
+        AstNode::LessEqual(left, right) => Some(("<=", left, right)),
+        AstNode::Greater(left, right) => Some((">", left, right)),
+        AstNode::GreaterEqual(left, right) => Some((">=", left, right)),
+        _ => None,
+    }
+}
+
+// The operator and operands of any binary operator node, comparisons included.
+pub(crate) fn binary_parts(node: &AstNode) -> Option<(Op, &Rc<AstNode>, &Rc<AstNode>)> {
+    match node {
+        AstNode::Add(left, right) => Some((Op::Plus, left, right)),
+        AstNode::Subtract(left, right) => Some((Op::Minus, left, right)),
+        AstNode::Multiply(left, right) => Some((Op::Star, left, right)),
+        AstNode::Divide(left, right) => Some((Op::Slash, left, right)),
+        AstNode::FloorDivide(left, right) => Some((Op::DoubleSlash, left, right)),
+        AstNode::Modulo(left, right) => Some((Op::Percent, left, right)),
+        AstNode::Equal(left, right) => Some((Op::Equal, left, right)),
+        AstNode::NotEqual(left, right) => Some((Op::NotEqual, left, right)),
+        AstNode::Less(left, right) => Some((Op::Less, left, right)),
+        AstNode::LessEqual(left, right) => Some((Op::LessEqual, left, right)),
+        AstNode::Greater(left, right) => Some((Op::Greater, left, right)),
+        AstNode::GreaterEqual(left, right) => Some((Op::GreaterEqual, left, right)),
+        AstNode::And(left, right) => Some((Op::AndAnd, left, right)),
+        AstNode::Or(left, right) => Some((Op::OrOr, left, right)),
+        AstNode::BitAnd(left, right) => Some((Op::Ampersand, left, right)),
+        AstNode::BitOr(left, right) => Some((Op::Pipe, left, right)),
+        AstNode::ShiftLeft(left, right) => Some((Op::ShiftLeft, left, right)),
+        AstNode::ShiftRight(left, right) => Some((Op::ShiftRight, left, right)),
+        AstNode::Power(left, right) => Some((Op::Caret, left, right)),
+        _ => None,
+    }
+}
+
+impl fmt::Display for AstNode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fn operand(f: &mut fmt::Formatter, node: &AstNode) -> fmt::Result {
+            match node {
+                AstNode::IntLiteral(_) | AstNode::FloatLiteral(_) | AstNode::Identifier(_) | AstNode::Call(..)
+                | AstNode::Block(_) =>
+                    write!(f, "{}", node),
+                AstNode::Located(_, inner) => operand(f, inner),
+                _ => write!(f, "({})", node),
+            }
+        }
+        fn binary(f: &mut fmt::Formatter, left: &AstNode, operator: &str, right: &AstNode) -> fmt::Result {
+            operand(f, left)?;
+            write!(f, " {} ", operator)?;
+            operand(f, right)
+        }
+        match self {
+            AstNode::Add(left, right) => binary(f, left, "+", right),
+            AstNode::Subtract(left, right) => binary(f, left, "-", right),
+            AstNode::Multiply(left, right) => binary(f, left, "*", right),
+            AstNode::Divide(left, right) => binary(f, left, "/", right),
+            AstNode::FloorDivide(left, right) => binary(f, left, "//", right),
+            AstNode::Modulo(left, right) => binary(f, left, "%", right),
+            AstNode::Equal(left, right) => binary(f, left, "==", right),
+            AstNode::NotEqual(left, right) => binary(f, left, "!=", right),
+            AstNode::Less(left, right) => binary(f, left, "<", right),
+            AstNode::LessEqual(left, right) => binary(f, left, "<=", right),
+            AstNode::Greater(left, right) => binary(f, left, ">", right),
+            AstNode::GreaterEqual(left, right) => binary(f, left, ">=", right),
+            AstNode::And(left, right) => binary(f, left, "&&", right),
+            AstNode::Or(left, right) => binary(f, left, "||", right),
+            AstNode::BitAnd(left, right) => binary(f, left, "&", right),
+            AstNode::BitOr(left, right) => binary(f, left, "|", right),
+            AstNode::ShiftLeft(left, right) => binary(f, left, "<<", right),
+            AstNode::ShiftRight(left, right) => binary(f, left, ">>", right),
+            AstNode::Power(left, right) => binary(f, left, "^", right),
+            AstNode::Not(operand_node) => {
+                write!(f, "!")?;
+                operand(f, operand_node)
+            },
+            AstNode::Negate(operand_node) => {
+                write!(f, "-")?;
+                operand(f, operand_node)
+            },
+            AstNode::Factorial(operand_node) => {
+                operand(f, operand_node)?;
+                write!(f, "!")
+            },
+            AstNode::Percent(operand_node) => {
+                operand(f, operand_node)?;
+                write!(f, "%")
+            },
+            AstNode::Conditional(condition, then_branch, else_branch) => {
+                operand(f, condition)?;
+                write!(f, " ? ")?;
+                operand(f, then_branch)?;
+                write!(f, " : ")?;
+                operand(f, else_branch)
+            },
+            AstNode::Assign(left, right) => write!(f, "{} = {}", left, right),
+            AstNode::AssignReturnOld(left, right) => write!(f, "{} := {}", left, right),
+            AstNode::ConstAssign(name, value) => write!(f, "const {} = {}", name, value),
+            AstNode::Identifier(identifier) => write!(f, "{}", identifier),
+            AstNode::IntLiteral(value) => write!(f, "{}", value),
+            // Debug formatting keeps the decimal point (`2.0`), so the literal reads back as a float.
+            AstNode::FloatLiteral(value) => write!(f, "{:?}", value),
+            AstNode::FunctionDef(name, parameters, body) =>
+                write!(f, "def {}({}) = {}", name, parameters.join(", "), body),
+            AstNode::Call(name, arguments) => {
+                write!(f, "{}(", name)?;
+                for (index, argument) in arguments.iter().enumerate() {
+                    if index > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", argument)?;
+                }
+                write!(f, ")")
+            },
+            AstNode::Block(statements) => {
+                write!(f, "{{ ")?;
+                for (index, statement) in statements.iter().enumerate() {
+                    if index > 0 {
+                        write!(f, "; ")?;
+                    }
+                    write!(f, "{}", statement)?;
+                }
+                write!(f, " }}")
+            },
+            AstNode::Let(bindings, body) => {
+                write!(f, "let ")?;
+                for (index, (name, value)) in bindings.iter().enumerate() {
+                    if index > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{} = ", name)?;
+                    operand(f, value)?;
+                }
+                write!(f, " in {}", body)
+            },
+            AstNode::Chain(comparisons) => {
+                for (index, comparison) in comparisons.iter().enumerate() {
+                    match comparison_parts(comparison) {
+                        Some((symbol, left, right)) => {
+                            if index == 0 {
+                                operand(f, left)?;
+                            }
+                            write!(f, " {} ", symbol)?;
+                            operand(f, right)?;
+                        },
+                        // Only hand-built chains hold anything else.
+                        None => {
+                            if index > 0 {
+                                write!(f, " && ")?;
+                            }
+                            operand(f, comparison)?;
+                        },
+                    }
+                }
+                Ok(())
+            },
+            AstNode::Located(_, inner) => write!(f, "{}", inner),
+        }
+    }
+}
+
+// Read-only traversal of an AST. Every per-variant method defaults to visiting the node's
+// children, so an implementation only overrides the variants it cares about; overriding `visit`
+// itself hooks every node.
+pub trait Visitor {
+    fn visit(&mut self, node: &AstNode) {
+        walk(self, node);
+    }
+
+    fn visit_add(&mut self, left: &AstNode, right: &AstNode) {
+        self.visit(left);
+        self.visit(right);
+    }
+
+    fn visit_subtract(&mut self, left: &AstNode, right: &AstNode) {
+        self.visit(left);
+        self.visit(right);
+    }
+
+    fn visit_multiply(&mut self, left: &AstNode, right: &AstNode) {
+        self.visit(left);
+        self.visit(right);
+    }
+
+    fn visit_divide(&mut self, left: &AstNode, right: &AstNode) {
+        self.visit(left);
+        self.visit(right);
+    }
+
+    fn visit_floor_divide(&mut self, left: &AstNode, right: &AstNode) {
+        self.visit(left);
+        self.visit(right);
+    }
+
+    fn visit_modulo(&mut self, left: &AstNode, right: &AstNode) {
+        self.visit(left);
+        self.visit(right);
+    }
+
+    fn visit_assign(&mut self, left: &AstNode, right: &AstNode) {
+        self.visit(left);
+        self.visit(right);
+    }
+
+    fn visit_assign_return_old(&mut self, left: &AstNode, right: &AstNode) {
+        self.visit(left);
+        self.visit(right);
+    }
+
+    fn visit_const_assign(&mut self, _name: &str, value: &AstNode) {
+        self.visit(value);
+    }
+
+    fn visit_equal(&mut self, left: &AstNode, right: &AstNode) {
+        self.visit(left);
+        self.visit(right);
+    }
+
+    fn visit_not_equal(&mut self, left: &AstNode, right: &AstNode) {
+        self.visit(left);
+        self.visit(right);
+    }
+
+    fn visit_less(&mut self, left: &AstNode, right: &AstNode) {
+        self.visit(left);
+        self.visit(right);
+    }
+
+    fn visit_less_equal(&mut self, left: &AstNode, right: &AstNode) {
+        self.visit(left);
+        self.visit(right);
+    }
+
+    fn visit_greater(&mut self, left: &AstNode, right: &AstNode) {
+        self.visit(left);
+        self.visit(right);
+    }
+
+    fn visit_greater_equal(&mut self, left: &AstNode, right: &AstNode) {
+        self.visit(left);
+        self.visit(right);
+    }
+
+    fn visit_and(&mut self, left: &AstNode, right: &AstNode) {
+        self.visit(left);
+        self.visit(right);
+    }
+
+    fn visit_or(&mut self, left: &AstNode, right: &AstNode) {
+        self.visit(left);
+        self.visit(right);
+    }
+
+    fn visit_bit_and(&mut self, left: &AstNode, right: &AstNode) {
+        self.visit(left);
+        self.visit(right);
+    }
+
+    fn visit_bit_or(&mut self, left: &AstNode, right: &AstNode) {
+        self.visit(left);
+        self.visit(right);
+    }
+
+    fn visit_shift_left(&mut self, left: &AstNode, right: &AstNode) {
+        self.visit(left);
+        self.visit(right);
+    }
+
+    fn visit_shift_right(&mut self, left: &AstNode, right: &AstNode) {
+        self.visit(left);
+        self.visit(right);
+    }
+
+    fn visit_power(&mut self, left: &AstNode, right: &AstNode) {
+        self.visit(left);
+        self.visit(right);
+    }
+
+    fn visit_not(&mut self, operand: &AstNode) {
+        self.visit(operand);
+    }
+
+    fn visit_negate(&mut self, operand: &AstNode) {
+        self.visit(operand);
+    }
+
+    fn visit_factorial(&mut self, operand: &AstNode) {
+        self.visit(operand);
+    }
+
+    fn visit_percent(&mut self, operand: &AstNode) {
+        self.visit(operand);
+    }
+
+    fn visit_conditional(&mut self, condition: &AstNode, then_branch: &AstNode, else_branch: &AstNode) {
+        self.visit(condition);
+        self.visit(then_branch);
+        self.visit(else_branch);
+    }
+
+    fn visit_block(&mut self, statements: &[Rc<AstNode>]) {
+        for statement in statements {
+            self.visit(statement);
+        }
+    }
+
+    fn visit_let(&mut self, bindings: &[(String, Rc<AstNode>)], body: &AstNode) {
+        for (_, value) in bindings {
+            self.visit(value);
+        }
+        self.visit(body);
+    }
+
+    // Visits the operands in evaluation order, each shared one once.
+    fn visit_chain(&mut self, comparisons: &[Rc<AstNode>]) {
+        for (index, comparison) in comparisons.iter().enumerate() {
+            match comparison_parts(comparison) {
+                Some((_, left, right)) => {
+                    if index == 0 {
+                        self.visit(left);
+                    }
+                    self.visit(right);
+                },
+                None => self.visit(comparison),
+            }
+        }
+    }
+
+    fn visit_located(&mut self, _position: usize, inner: &AstNode) {
+        self.visit(inner);
+    }
+
+    fn visit_identifier(&mut self, _identifier: &str) {}
+
+    fn visit_int_literal(&mut self, _value: &Integer) {}
+
+    fn visit_float_literal(&mut self, _value: f64) {}
+
+    fn visit_function_def(&mut self, _name: &str, _parameters: &[String], body: &AstNode) {
+        self.visit(body);
+    }
+
+    fn visit_call(&mut self, _name: &str, arguments: &[Rc<AstNode>]) {
+        for argument in arguments {
+            self.visit(argument);
+        }
+    }
+}
+
+// Dispatches a node to the visitor method for its variant.
+pub fn walk<V: Visitor + ?Sized>(visitor: &mut V, node: &AstNode) {
+    match node {
+        AstNode::Add(left, right) => visitor.visit_add(left, right),
+        AstNode::Subtract(left, right) => visitor.visit_subtract(left, right),
+        AstNode::Multiply(left, right) => visitor.visit_multiply(left, right),
+        AstNode::Divide(left, right) => visitor.visit_divide(left, right),
+        AstNode::FloorDivide(left, right) => visitor.visit_floor_divide(left, right),
+        AstNode::Modulo(left, right) => visitor.visit_modulo(left, right),
+        AstNode::Assign(left, right) => visitor.visit_assign(left, right),
+        AstNode::AssignReturnOld(left, right) => visitor.visit_assign_return_old(left, right),
+        AstNode::ConstAssign(name, value) => visitor.visit_const_assign(name, value),
+        AstNode::Equal(left, right) => visitor.visit_equal(left, right),
+        AstNode::NotEqual(left, right) => visitor.visit_not_equal(left, right),
+        AstNode::Less(left, right) => visitor.visit_less(left, right),
+        AstNode::LessEqual(left, right) => visitor.visit_less_equal(left, right),
+        AstNode::Greater(left, right) => visitor.visit_greater(left, right),
+        AstNode::GreaterEqual(left, right) => visitor.visit_greater_equal(left, right),
+        AstNode::And(left, right) => visitor.visit_and(left, right),
+        AstNode::Or(left, right) => visitor.visit_or(left, right),
+        AstNode::BitAnd(left, right) => visitor.visit_bit_and(left, right),
+        AstNode::BitOr(left, right) => visitor.visit_bit_or(left, right),
+        AstNode::ShiftLeft(left, right) => visitor.visit_shift_left(left, right),
+        AstNode::ShiftRight(left, right) => visitor.visit_shift_right(left, right),
+        AstNode::Power(left, right) => visitor.visit_power(left, right),
+        AstNode::Not(operand) => visitor.visit_not(operand),
+        AstNode::Negate(operand) => visitor.visit_negate(operand),
+        AstNode::Factorial(operand) => visitor.visit_factorial(operand),
+        AstNode::Percent(operand) => visitor.visit_percent(operand),
+        AstNode::Conditional(condition, then_branch, else_branch) =>
+            visitor.visit_conditional(condition, then_branch, else_branch),
+        AstNode::Block(statements) => visitor.visit_block(statements),
+        AstNode::Let(bindings, body) => visitor.visit_let(bindings, body),
+        AstNode::Chain(comparisons) => visitor.visit_chain(comparisons),
+        AstNode::Located(position, inner) => visitor.visit_located(*position, inner),
+        AstNode::Identifier(identifier) => visitor.visit_identifier(identifier),
+        AstNode::IntLiteral(value) => visitor.visit_int_literal(value),
+        AstNode::FloatLiteral(value) => visitor.visit_float_literal(*value),
+        AstNode::FunctionDef(name, parameters, body) => visitor.visit_function_def(name, parameters, body),
+        AstNode::Call(name, arguments) => visitor.visit_call(name, arguments),
+    }
+}
+
+// Names read, assigned or called anywhere in the tree, without duplicates, in order of first
+// appearance.
+pub fn collect_identifiers(node: &AstNode) -> Vec<String> {
+    struct Collector(Vec<String>);
+    impl Collector {
+        fn add(&mut self, name: &str) {
+            if !self.0.iter().any(|seen| seen == name) {
+                self.0.push(name.to_string());
+            }
+        }
+    }
+    impl Visitor for Collector {
+        fn visit_identifier(&mut self, identifier: &str) {
+            self.add(identifier);
+        }
+        fn visit_call(&mut self, name: &str, arguments: &[Rc<AstNode>]) {
+            self.add(name);
+            for argument in arguments {
+                self.visit(argument);
+            }
+        }
+    }
+    let mut collector = Collector(Vec::new());
+    collector.visit(node);
+    collector.0
+}
+
+// How many nodes the tree has, e.g. to turn away oversized input before evaluating it.
+pub fn node_count(node: &AstNode) -> usize {
+    struct Counter(usize);
+    impl Visitor for Counter {
+        fn visit(&mut self, node: &AstNode) {
+            self.0 += 1;
+            walk(self, node);
+        }
+    }
+    let mut counter = Counter(0);
+    counter.visit(node);
+    counter.0
+}
+
+// Whether the tree assigns, declares or defines anything anywhere, blocks and function bodies
+// included.
+pub(crate) fn contains_assignment(node: &AstNode) -> bool {
+    struct Finder(bool);
+    impl Visitor for Finder {
+        fn visit(&mut self, node: &AstNode) {
+            match node {
+                AstNode::Assign(..) | AstNode::AssignReturnOld(..) | AstNode::ConstAssign(..) | AstNode::FunctionDef(..) => self.0 = true,
+                _ => walk(self, node),
+            }
+        }
+    }
+    let mut finder = Finder(false);
+    finder.visit(node);
+    finder.0
+}
+
+// Whether the tree reads a variable anywhere. Calls don't count: the function itself is looked up
+// by name, but not as a variable read.
+pub(crate) fn reads_variables(node: &AstNode) -> bool {
+    struct Finder(bool);
+    impl Visitor for Finder {
+        fn visit_identifier(&mut self, _identifier: &str) {
+            self.0 = true;
+        }
+    }
+    let mut finder = Finder(false);
+    finder.visit(node);
+    finder.0
+}
+
+// Whether an assignment appears anywhere but as a statement of its own: the whole tree, a block's
+// statement, a `let` or function body, or the right-hand side of another assignment, so `a = b = 1`
+// is fine but `1 + (x = 2)` is not.
+pub(crate) fn has_embedded_assignment(node: &AstNode) -> bool {
+    struct Finder {
+        statement: bool,
+        found: bool,
+    }
+    impl Visitor for Finder {
+        fn visit(&mut self, node: &AstNode) {
+            let statement = mem::replace(&mut self.statement, false);
+            match node {
+                AstNode::Assign(_, value) | AstNode::AssignReturnOld(_, value) if statement => {
+                    self.statement = true;
+                    self.visit(value);
+                },
+                AstNode::Assign(..) | AstNode::AssignReturnOld(..) => self.found = true,
+                AstNode::Located(_, inner) => {
+                    self.statement = statement;
+                    self.visit(inner);
+                },
+                _ => walk(self, node),
+            }
+        }
+
+        fn visit_block(&mut self, statements: &[Rc<AstNode>]) {
+            for statement in statements {
+                self.statement = true;
+                self.visit(statement);
+            }
+        }
+
+        fn visit_let(&mut self, bindings: &[(String, Rc<AstNode>)], body: &AstNode) {
+            for (_, value) in bindings {
+                self.visit(value);
+            }
+            self.statement = true;
+            self.visit(body);
+        }
+
+        fn visit_function_def(&mut self, _name: &str, _parameters: &[String], body: &AstNode) {
+            self.statement = true;
+            self.visit(body);
+        }
+    }
+    let mut finder = Finder { statement: true, found: false };
+    finder.visit(node);
+    finder.found
+}
+
+// The number of nodes on the longest path from the root down to a leaf; a lone literal is 1 deep.
+pub fn max_depth(node: &AstNode) -> usize {
+    struct Depth {
+        current: usize,
+        max: usize,
+    }
+    impl Visitor for Depth {
+        fn visit(&mut self, node: &AstNode) {
+            self.current += 1;
+            self.max = self.max.max(self.current);
+            walk(self, node);
+            self.current -= 1;
+        }
+    }
+    let mut depth = Depth { current: 0, max: 0 };
+    depth.visit(node);
+    depth.max
+}
+
+// Names read or called before anything could have bound them, given the names already in
+// `defined` and the builtins, without duplicates, in order of first appearance. Nothing is
+// evaluated: assignments bind their target for whatever follows, and a definition binds its name
+// (and, inside its body, its parameters). Names bound on only one side of a `?:`, in the right
+// operand of `&&`/`||`, or first inside a block or function body do not count as bound afterwards.
+pub fn undefined_references(node: &AstNode, defined: &HashSet<String>) -> Vec<String> {
+    struct Checker {
+        defined: HashSet<String>,
+        undefined: Vec<String>,
+    }
+    impl Checker {
+        fn read(&mut self, name: &str) {
+            if !self.defined.contains(name) && !self.undefined.iter().any(|seen| seen == name) {
+                self.undefined.push(name.to_string());
+            }
+        }
+        // Visits the node, then forgets what it bound; returns the names bound by the end of it.
+        fn scoped(&mut self, node: &AstNode) -> HashSet<String> {
+            let outer = self.defined.clone();
+            self.visit(node);
+            std::mem::replace(&mut self.defined, outer)
+        }
+    }
+    impl Visitor for Checker {
+        fn visit_identifier(&mut self, identifier: &str) {
+            self.read(identifier);
+        }
+        fn visit_assign(&mut self, left: &AstNode, right: &AstNode) {
+            self.visit(right);
+            match left {
+                AstNode::Identifier(name) => {
+                    self.defined.insert(name.clone());
+                },
+                _ => self.visit(left),
+            }
+        }
+        fn visit_const_assign(&mut self, name: &str, value: &AstNode) {
+            self.visit(value);
+            self.defined.insert(name.to_string());
+        }
+        fn visit_and(&mut self, left: &AstNode, right: &AstNode) {
+            self.visit(left);
+            self.scoped(right);
+        }
+        fn visit_or(&mut self, left: &AstNode, right: &AstNode) {
+            self.visit(left);
+            self.scoped(right);
+        }
+        // Like `&&`, a chain may stop after its first comparison.
+        fn visit_chain(&mut self, comparisons: &[Rc<AstNode>]) {
+            let Some((first, rest)) = comparisons.split_first() else {
+                return;
+            };
+            self.visit(first);
+            let bound = self.defined.clone();
+            for comparison in rest {
+                match comparison_parts(comparison) {
+                    Some((_, _, right)) => self.visit(right),
+                    None => self.visit(comparison),
+                }
+            }
+            self.defined = bound;
+        }
+        fn visit_conditional(&mut self, condition: &AstNode, then_branch: &AstNode, else_branch: &AstNode) {
+            self.visit(condition);
+            let then_defined = self.scoped(then_branch);
+            let else_defined = self.scoped(else_branch);
+            self.defined = then_defined.intersection(&else_defined).cloned().collect();
+        }
+        fn visit_block(&mut self, statements: &[Rc<AstNode>]) {
+            let outer = self.defined.clone();
+            for statement in statements {
+                self.visit(statement);
+            }
+            self.defined = outer;
+        }
+        // Each binding is visible to the ones after it and to the body, but not past the `let`.
+        fn visit_let(&mut self, bindings: &[(String, Rc<AstNode>)], body: &AstNode) {
+            let outer = self.defined.clone();
+            for (name, value) in bindings {
+                self.visit(value);
+                self.defined.insert(name.clone());
+            }
+            self.visit(body);
+            self.defined = outer;
+        }
+        fn visit_function_def(&mut self, name: &str, parameters: &[String], body: &AstNode) {
+            self.defined.insert(name.to_string());
+            let outer = self.defined.clone();
+            self.defined.extend(parameters.iter().cloned());
+            self.visit(body);
+            self.defined = outer;
+        }
+        fn visit_call(&mut self, name: &str, arguments: &[Rc<AstNode>]) {
+            if !BUILTINS.contains(&name) {
+                self.read(name);
+            }
+            for argument in arguments {
+                self.visit(argument);
+            }
+        }
+    }
+    let mut checker = Checker { defined: defined.clone(), undefined: Vec::new() };
+    checker.visit(node);
+    checker.undefined
+}
+
+// A node's kind, with the payload of leaves and named nodes: `IntLiteral 3`, `Call f`, `Add`.
+pub(crate) fn label(node: &AstNode) -> String {
+    match node {
+        AstNode::Add(..) => "Add".to_string(),
+        AstNode::Subtract(..) => "Subtract".to_string(),
+        AstNode::Multiply(..) => "Multiply".to_string(),
+        AstNode::Divide(..) => "Divide".to_string(),
+        AstNode::FloorDivide(..) => "FloorDivide".to_string(),
+        AstNode::Modulo(..) => "Modulo".to_string(),
+        AstNode::Assign(..) => "Assign".to_string(),
+        AstNode::AssignReturnOld(..) => "AssignReturnOld".to_string(),
+        AstNode::ConstAssign(name, _) => format!("ConstAssign {}", name),
+        AstNode::Identifier(name) => format!("Identifier {}", name),
+        AstNode::IntLiteral(value) => format!("IntLiteral {}", value),
+        AstNode::FloatLiteral(value) => format!("FloatLiteral {:?}", value),
+        AstNode::FunctionDef(name, parameters, _) => format!("FunctionDef {}({})", name, parameters.join(", ")),
+        AstNode::Call(name, _) => format!("Call {}", name),
+        AstNode::Equal(..) => "Equal".to_string(),
+        AstNode::NotEqual(..) => "NotEqual".to_string(),
+        AstNode::Less(..) => "Less".to_string(),
+        AstNode::LessEqual(..) => "LessEqual".to_string(),
+        AstNode::Greater(..) => "Greater".to_string(),
+        AstNode::GreaterEqual(..) => "GreaterEqual".to_string(),
+        AstNode::And(..) => "And".to_string(),
+        AstNode::Or(..) => "Or".to_string(),
+        AstNode::Not(_) => "Not".to_string(),
+        AstNode::Negate(_) => "Negate".to_string(),
+        AstNode::Factorial(_) => "Factorial".to_string(),
+        AstNode::Percent(_) => "Percent".to_string(),
+        AstNode::BitAnd(..) => "BitAnd".to_string(),
+        AstNode::BitOr(..) => "BitOr".to_string(),
+        AstNode::ShiftLeft(..) => "ShiftLeft".to_string(),
+        AstNode::ShiftRight(..) => "ShiftRight".to_string(),
+        AstNode::Power(..) => "Power".to_string(),
+        AstNode::Conditional(..) => "Conditional".to_string(),
+        AstNode::Block(_) => "Block".to_string(),
+        AstNode::Let(bindings, _) => {
+            let names: Vec<&str> = bindings.iter().map(|(name, _)| name.as_str()).collect();
+            format!("Let {}", names.join(", "))
+        },
+        AstNode::Chain(comparisons) => {
+            let symbols: Vec<&str> = comparisons.iter()
+                .map(|comparison| comparison_parts(comparison).map_or("&&", |(symbol, _, _)| symbol))
+                .collect();
+            format!("Chain {}", symbols.join(" "))
+        },
+        AstNode::Located(position, _) => format!("Located {}", position),
+    }
+}
+
+// The tree one node per line, each child indented two spaces further than its parent and the root
+// `indent` levels in, each shown by its `label`.
+pub fn pretty_print_ast(node: &AstNode, indent: usize) -> String {
+    struct Printer {
+        depth: usize,
+        output: String,
+    }
+    impl Visitor for Printer {
+        fn visit(&mut self, node: &AstNode) {
+            if let AstNode::Located(_, inner) = node {
+                return self.visit(inner);
+            }
+            self.output.push_str(&format!("{}{}\n", "  ".repeat(self.depth), label(node)));
+            self.depth += 1;
+            walk(self, node);
+            self.depth -= 1;
+        }
+    }
+    let mut printer = Printer { depth: indent, output: String::new() };
+    printer.visit(node);
+    printer.output
+}
+
+// Infix source for the tree with only the parentheses the parser needs to rebuild it: unlike
+// `Display`, which wraps every compound operand, this prints `1 + 2 * 3` and `(1 + 2) * 3`. An
+// operand of equal precedence is wrapped on the side its operator does not associate to, as in
+// `a - (b - c)`, and a comparison inside another is always wrapped, since `a < b < c` would read
+// back as a chain.
+pub fn strip_parens(node: &AstNode) -> String {
+    const ATOM_PRECEDENCE: u8 = 14;
+    fn precedence(node: &AstNode) -> u8 {
+        match node {
+            AstNode::Located(_, inner) => precedence(inner),
+            AstNode::FunctionDef(..) | AstNode::ConstAssign(..) | AstNode::Let(..) => 0,
+            AstNode::Assign(..) | AstNode::AssignReturnOld(..) => ASSIGNMENT_PRECEDENCE,
+            AstNode::Conditional(..) => CONDITIONAL_PRECEDENCE,
+            AstNode::Chain(_) => COMPARISON_PRECEDENCE,
+            AstNode::Not(_) => PREFIX_PRECEDENCE,
+            AstNode::Negate(_) => POWER_PRECEDENCE,
+            AstNode::Factorial(_) | AstNode::Percent(_) => POSTFIX_PRECEDENCE,
+            _ => binary_parts(node)
+                .and_then(|(op, _, _)| operator_binding(op))
+                .map_or(ATOM_PRECEDENCE, |(precedence, _)| precedence),
+        }
+    }
+    // `node` as an operand that must bind at least as tightly as `minimum`.
+    fn operand(node: &AstNode, minimum: u8) -> String {
+        if precedence(node) < minimum {
+            format!("({})", strip_parens(node))
+        } else {
+            strip_parens(node)
+        }
+    }
+    fn list(nodes: &[Rc<AstNode>], separator: &str) -> String {
+        nodes.iter().map(|node| strip_parens(node)).collect::<Vec<_>>().join(separator)
+    }
+    match node {
+        AstNode::Located(_, inner) => strip_parens(inner),
+        AstNode::Not(inner) => format!("!{}", operand(inner, PREFIX_PRECEDENCE)),
+        AstNode::Negate(inner) => format!("-{}", operand(inner, POWER_PRECEDENCE)),
+        AstNode::Factorial(inner) => format!("{}!", operand(inner, POSTFIX_PRECEDENCE)),
+        AstNode::Percent(inner) => format!("{}%", operand(inner, POSTFIX_PRECEDENCE)),
+        AstNode::Conditional(condition, then_branch, else_branch) => format!(
+            "{} ? {} : {}",
+            operand(condition, CONDITIONAL_PRECEDENCE + 1),
+            strip_parens(then_branch),
+            operand(else_branch, CONDITIONAL_PRECEDENCE),
+        ),
+        AstNode::Assign(target, value) => format!("{} = {}", strip_parens(target), operand(value, ASSIGNMENT_PRECEDENCE)),
+        AstNode::AssignReturnOld(target, value) =>
+            format!("{} := {}", strip_parens(target), operand(value, ASSIGNMENT_PRECEDENCE)),
+        AstNode::ConstAssign(name, value) => format!("const {} = {}", name, strip_parens(value)),
+        AstNode::FunctionDef(name, parameters, body) =>
+            format!("def {}({}) = {}", name, parameters.join(", "), strip_parens(body)),
+        AstNode::Call(name, arguments) => format!("{}({})", name, list(arguments, ", ")),
+        AstNode::Block(statements) => format!("{{ {} }}", list(statements, "; ")),
+        AstNode::Let(bindings, body) => {
+            let bindings: Vec<String> = bindings.iter()
+                .map(|(name, value)| format!("{} = {}", name, operand(value, CONDITIONAL_PRECEDENCE)))
+                .collect();
+            format!("let {} in {}", bindings.join(", "), strip_parens(body))
+        },
+        AstNode::Chain(comparisons) => {
+            let mut text = String::new();
+            for (index, comparison) in comparisons.iter().enumerate() {
+                match comparison_parts(comparison) {
+                    Some((symbol, left, right)) => {
+                        if index == 0 {
+                            text.push_str(&operand(left, COMPARISON_PRECEDENCE + 1));
+                        }
+                        text.push_str(&format!(" {} {}", symbol, operand(right, COMPARISON_PRECEDENCE + 1)));
+                    },
+                    // Only hand-built chains hold anything else.
+                    None => {
+                        if index > 0 {
+                            text.push_str(" && ");
+                        }
+                        text.push_str(&operand(comparison, ATOM_PRECEDENCE));
+                    },
+                }
+            }
+            text
+        },
+        _ => match binary_parts(node) {
+            Some((op, left, right)) => {
+                let (precedence, associativity) = operator_binding(op).expect("every binary node has an operator");
+                let (left_minimum, right_minimum) = match associativity {
+                    _ if precedence == COMPARISON_PRECEDENCE => (precedence + 1, precedence + 1),
+                    Associativity::Left => (precedence, precedence + 1),
+                    Associativity::Right => (precedence + 1, precedence),
+                };
+                // A `-` straight after `%` would make the `%` a percent, as in `50% - 3`.
+                let right = match operand(right, right_minimum) {
+                    text if op == Op::Percent && text.starts_with('-') => format!("({})", text),
+                    text => text,
+                };
+                format!("{} {} {}", operand(left, left_minimum), op, right)
+            },
+            None => node.to_string(),
+        },
+    }
+}
+
+// A plain-English walkthrough of the tree, as in "Multiply the result of (add 1 and 2) by 3".
+// Numbers and names stand for themselves; every other operand is described in parentheses.
+pub(crate) fn describe_node(node: &AstNode) -> String {
+    // `node` in place of a value: bare if it is a number or a name, else its own description.
+    fn operand(node: &AstNode) -> String {
+        match node {
+            AstNode::Located(_, inner) => operand(inner),
+            AstNode::IntLiteral(_) | AstNode::FloatLiteral(_) | AstNode::Identifier(_) => node.to_string(),
+            _ => format!("the result of ({})", phrase(node)),
+        }
+    }
+    fn nested(node: &AstNode) -> String {
+        match node {
+            AstNode::Located(_, inner) => nested(inner),
+            AstNode::IntLiteral(_) | AstNode::FloatLiteral(_) | AstNode::Identifier(_) => node.to_string(),
+            _ => format!("({})", phrase(node)),
+        }
+    }
+    fn list(nodes: &[Rc<AstNode>]) -> String {
+        nodes.iter().map(|node| operand(node)).collect::<Vec<_>>().join(", ")
+    }
+    // A comparison as a statement that may or may not hold: `a < b` as "a is less than b".
+    fn relation(node: &AstNode) -> String {
+        let relation = match node {
+            AstNode::Equal(..) => "equals",
+            AstNode::NotEqual(..) => "differs from",
+            AstNode::Less(..) => "is less than",
+            AstNode::LessEqual(..) => "is at most",
+            AstNode::Greater(..) => "is greater than",
+            AstNode::GreaterEqual(..) => "is at least",
+            AstNode::Located(_, inner) => return relation(inner),
+            // Only hand-built chains hold anything else.
+            _ => return format!("{} holds", operand(node)),
+        };
+        let (_, left, right) = comparison_parts(node).expect("a comparison has two operands");
+        format!("{} {} {}", operand(left), relation, operand(right))
+    }
+    fn phrase(node: &AstNode) -> String {
+        match node {
+            AstNode::Add(left, right) => format!("add {} and {}", operand(left), operand(right)),
+            AstNode::Subtract(left, right) => format!("subtract {} from {}", operand(right), operand(left)),
+            AstNode::Multiply(left, right) => format!("multiply {} by {}", operand(left), operand(right)),
+            AstNode::Divide(left, right) => format!("divide {} by {}", operand(left), operand(right)),
+            AstNode::FloorDivide(left, right) => format!("divide {} by {}, rounding down", operand(left), operand(right)),
+            AstNode::Modulo(left, right) => format!("take the remainder of {} divided by {}", operand(left), operand(right)),
+            AstNode::Power(left, right) => format!("raise {} to the power of {}", operand(left), operand(right)),
+            AstNode::BitAnd(left, right) => format!("take the bitwise and of {} and {}", operand(left), operand(right)),
+            AstNode::BitOr(left, right) => format!("take the bitwise or of {} and {}", operand(left), operand(right)),
+            AstNode::ShiftLeft(left, right) => format!("shift {} left by {} bits", operand(left), operand(right)),
+            AstNode::ShiftRight(left, right) => format!("shift {} right by {} bits", operand(left), operand(right)),
+            AstNode::Equal(..) | AstNode::NotEqual(..) | AstNode::Less(..) | AstNode::LessEqual(..)
+            | AstNode::Greater(..) | AstNode::GreaterEqual(..) => format!("check whether {}", relation(node)),
+            AstNode::Chain(comparisons) => {
+                let relations: Vec<String> = comparisons.iter().map(|comparison| relation(comparison)).collect();
+                format!("check whether {}", relations.join(" and "))
+            },
+            AstNode::And(left, right) => format!("check whether both {} and {} are true", operand(left), operand(right)),
+            AstNode::Or(left, right) => format!("check whether {} or {} is true", operand(left), operand(right)),
+            AstNode::Not(inner) => format!("negate {}", operand(inner)),
+            AstNode::Negate(inner) => format!("take the negative of {}", operand(inner)),
+            AstNode::Factorial(inner) => format!("take the factorial of {}", operand(inner)),
+            AstNode::Percent(inner) => format!("take {} percent", operand(inner)),
+            AstNode::Conditional(condition, then_branch, else_branch) =>
+                format!("if {} then {}, otherwise {}", nested(condition), nested(then_branch), nested(else_branch)),
+            AstNode::Assign(target, value) => format!("assign to {} the value of {}", target, nested(value)),
+            AstNode::AssignReturnOld(target, value) =>
+                format!("assign to {} the value of {}, giving back its old value", target, nested(value)),
+            AstNode::ConstAssign(name, value) => format!("define the constant {} as {}", name, nested(value)),
+            AstNode::FunctionDef(name, parameters, body) =>
+                format!("define the function {}({}) as {}", name, parameters.join(", "), nested(body)),
+            AstNode::Call(name, arguments) if arguments.is_empty() => format!("call {} with no arguments", name),
+            AstNode::Call(name, arguments) => format!("call {} with {}", name, list(arguments)),
+            AstNode::Block(statements) => {
+                let steps: Vec<String> = statements.iter().map(|statement| nested(statement)).collect();
+                format!("do {}", steps.join(", then "))
+            },
+            AstNode::Let(bindings, body) => {
+                let bindings: Vec<String> = bindings.iter()
+                    .map(|(name, value)| format!("{} be {}", name, nested(value)))
+                    .collect();
+                format!("let {}, in {}", bindings.join(" and "), nested(body))
+            },
+            AstNode::Identifier(name) => format!("the value of {}", name),
+            AstNode::IntLiteral(_) | AstNode::FloatLiteral(_) => format!("the number {}", node),
+            AstNode::Located(_, inner) => phrase(inner),
+        }
+    }
+    let mut description = phrase(node);
+    if let Some(first) = description.get(..1) {
+        let capital = first.to_ascii_uppercase();
+        description.replace_range(..1, &capital);
+    }
+    description
+}
+
+#[cfg(feature = "serde")]
+pub fn ast_to_json(node: &AstNode) -> String {
+    serde_json::to_string(node).expect("an AST always serializes")
+}
+
+#[cfg(feature = "serde")]
+pub fn ast_from_json(json: &str) -> Result<AstNode, serde_json::Error> {
+    serde_json::from_str(json)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn int(value: i32) -> Rc<AstNode> {
+        Rc::new(AstNode::IntLiteral(value.into()))
+    }
+
+    #[test]
+    fn test_visitor() {
+        struct AddCounter(usize);
+        impl Visitor for AddCounter {
+            fn visit_add(&mut self, left: &AstNode, right: &AstNode) {
+                self.0 += 1;
+                self.visit(left);
+                self.visit(right);
+            }
+        }
+        // (1 + 2) * (3 + (4 - 5 + 6))
+        let node = AstNode::Multiply(
+            Rc::new(AstNode::Add(int(1), int(2))),
+            Rc::new(AstNode::Add(
+                int(3),
+                Rc::new(AstNode::Add(
+                    Rc::new(AstNode::Subtract(int(4), int(5))),
+                    int(6)
+                ))
+            ))
+        );
+        let mut counter = AddCounter(0);
+        counter.visit(&node);
+        assert_eq!(counter.0, 3);
+    }
+
+    #[test]
+    fn test_collect_identifiers() {
+        let node = AstNode::Assign(
+            Rc::new(AstNode::Identifier("x".to_string())),
+            Rc::new(AstNode::Add(
+                Rc::new(AstNode::Identifier("y".to_string())),
+                Rc::new(AstNode::Call("f".to_string(), vec![Rc::new(AstNode::Identifier("x".to_string()))]))
+            ))
+        );
+        assert_eq!(collect_identifiers(&node), vec!["x", "y", "f"]);
+        assert_eq!(collect_identifiers(&AstNode::IntLiteral(1.into())), Vec::<String>::new());
+    }
+
+    #[test]
+    fn test_node_count_and_max_depth() {
+        let node = crate::parse_str("(1+2)*3").unwrap();
+        assert_eq!(node_count(&node), 5);
+        assert_eq!(max_depth(&node), 3);
+        assert_eq!(node_count(&AstNode::IntLiteral(1.into())), 1);
+        assert_eq!(max_depth(&AstNode::IntLiteral(1.into())), 1);
+        let node = crate::parse_str("def f(a, b) = { a; f(b, 1 + a) }").unwrap();
+        assert_eq!(node_count(&node), 8);
+        assert_eq!(max_depth(&node), 5);
+    }
+
+    #[test]
+    fn test_undefined_references() {
+        let undefined = |input: &str, defined: &[&str]| {
+            let defined = defined.iter().map(|name| name.to_string()).collect();
+            undefined_references(&crate::parse_str(input).unwrap(), &defined)
+        };
+        assert_eq!(undefined("x + y * x", &["y"]), vec!["x"]);
+        assert_eq!(undefined("{ x = 1; y = x + z; f(y, w) }", &[]), vec!["z", "f", "w"]);
+        assert_eq!(undefined("{ { t = 1 }; t }", &[]), vec!["t"]);
+        assert_eq!(undefined("{ def sq(n) = n * n * k; sq(2) + n }", &[]), vec!["k", "n"]);
+        assert_eq!(undefined("{ c ? (a = 1) : (b = 2); a + b }", &["c"]), vec!["a", "b"]);
+        assert_eq!(undefined("{ c ? (a = 1) : (a = 2); a }", &["c"]), Vec::<String>::new());
+        assert_eq!(undefined("{ c && (a = 1); a }", &["c"]), vec!["a"]);
+        assert_eq!(undefined("x = x + 1", &[]), vec!["x"]);
+        assert_eq!(undefined("print(p)", &[]), vec!["p"]);
+        assert_eq!(undefined("1 < (a = 2) < a + b", &[]), vec!["b"]);
+        assert_eq!(undefined("{ 0 < 1 < (a = 1); a }", &[]), vec!["a"]);
+        assert_eq!(undefined("(let a = 1, b = a + c in a * b) + a", &[]), vec!["c", "a"]);
+    }
+
+    #[test]
+    fn test_structural_equality_and_hash() {
+        let parsed = |input| crate::parse_str(input).unwrap();
+        assert_eq!(parsed("1+2"), parsed("(1 + 2)"));
+        assert_eq!(parsed("f( x,y )*((3))"), parsed("f(x, y) * 3"));
+        assert_eq!(ast_hash(&parsed("1+2")), ast_hash(&parsed("((1 +\n 2))")));
+        assert_ne!(ast_hash(&parsed("1 + 2")), ast_hash(&parsed("2 + 1")));
+        assert_ne!(ast_hash(&parsed("(1 + 2) * 3")), ast_hash(&parsed("1 + 2 * 3")));
+        assert_ne!(ast_hash(&parsed("1 + 2")), ast_hash(&parsed("1 - 2")));
+        assert_ne!(ast_hash(&parsed("2")), ast_hash(&parsed("2.0")));
+        assert_eq!(ast_hash(&AstNode::FloatLiteral(0.0)), ast_hash(&AstNode::FloatLiteral(-0.0)));
+        let mut seen = HashSet::new();
+        assert!(seen.insert(ast_hash(&parsed("a * b"))));
+        assert!(!seen.insert(ast_hash(&parsed("(a)*(b)"))));
+    }
+
+    #[test]
+    fn test_pretty_print_ast() {
+        let node = crate::parse_str("x = (1 + 2.5) * f(y)").unwrap();
+        assert_eq!(pretty_print_ast(&node, 0), [
+            "Assign",
+            "  Identifier x",
+            "  Multiply",
+            "    Add",
+            "      IntLiteral 1",
+            "      FloatLiteral 2.5",
+            "    Call f",
+            "      Identifier y",
+            "",
+        ].join("\n"));
+        assert_eq!(pretty_print_ast(&AstNode::IntLiteral(7.into()), 2), "    IntLiteral 7\n");
+        let node = crate::parse_str("def g(a, b) = a").unwrap();
+        assert_eq!(pretty_print_ast(&node, 0), "FunctionDef g(a, b)\n  Identifier a\n");
+    }
+
+    #[test]
+    fn test_strip_parens() {
+        let node = AstNode::Multiply(Rc::new(AstNode::Add(int(1), int(2))), int(3));
+        assert_eq!(strip_parens(&node), "(1 + 2) * 3");
+        let node = AstNode::Add(int(1), Rc::new(AstNode::Multiply(int(2), int(3))));
+        assert_eq!(strip_parens(&node), "1 + 2 * 3");
+        let node = AstNode::Subtract(int(1), Rc::new(AstNode::Subtract(int(2), int(3))));
+        assert_eq!(strip_parens(&node), "1 - (2 - 3)");
+        let node = AstNode::Subtract(Rc::new(AstNode::Subtract(int(1), int(2))), int(3));
+        assert_eq!(strip_parens(&node), "1 - 2 - 3");
+        let node = AstNode::Power(Rc::new(AstNode::Power(int(2), int(3))), int(2));
+        assert_eq!(strip_parens(&node), "(2 ^ 3) ^ 2");
+        let node = AstNode::Less(Rc::new(AstNode::Less(int(1), int(2))), int(3));
+        assert_eq!(strip_parens(&node), "(1 < 2) < 3");
+
+        // Parsing the minimal text gives back the tree it was printed from.
+        for (input, expected) in [
+            ("((a * b)) + (c // d)", "a * b + c // d"),
+            ("2 ^ (3 ^ 2)", "2 ^ 3 ^ 2"),
+            ("(x & 1) == (y | 2)", "x & 1 == y | 2"),
+            ("(a < b) && (b < c) || !(d)", "a < b && b < c || !d"),
+            ("!(a && b)", "!(a && b)"),
+            ("(!a)!", "(!a)!"),
+            ("((3!)!) ^ (!x)", "3!! ^ !x"),
+            ("x = (y = (a ? (b ? 1 : 2) : (c ? 3 : 4)))", "x = y = a ? b ? 1 : 2 : c ? 3 : 4"),
+            ("((a ? b : c)) ? d : e", "(a ? b : c) ? d : e"),
+            ("f((1 + 2), {(a); (b * 2)}) * (g())", "f(1 + 2, { a; b * 2 }) * g()"),
+            ("def h(n) = ((n * (n + 1)) / 2)", "def h(n) = n * (n + 1) / 2"),
+            ("0 < (x + 1) <= (10 - y)", "0 < x + 1 <= 10 - y"),
+            ("(let a = (b ? 1 : 2), c = (a * 2) in (a + c)) * 2", "(let a = b ? 1 : 2, c = a * 2 in a + c) * 2"),
+        ] {
+            let tree = crate::parse_str(input).unwrap();
+            assert_eq!(strip_parens(&tree), expected, "{}", input);
+            assert_eq!(crate::parse_str(expected), Ok(tree), "{}", input);
+        }
+    }
+
+    type Constructor = fn(Rc<AstNode>, Rc<AstNode>) -> AstNode;
+    type UnaryConstructor = fn(Rc<AstNode>) -> AstNode;
+
+    // A small xorshift generator, so the property tests need no extra dependency and every
+    // failure reproduces from its seed.
+    struct Random(u64);
+
+    impl Random {
+        fn below(&mut self, bound: usize) -> usize {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            (self.0 % bound as u64) as usize
+        }
+
+        fn name(&mut self) -> String {
+            ["a", "b", "x", "y2", "_t"][self.below(5)].to_string()
+        }
+
+        // Any tree the parser can produce, apart from definitions and located nodes. Literals are
+        // non-negative, since `-1` reads back as a negation.
+        fn tree(&mut self, depth: usize) -> Rc<AstNode> {
+            const BINARY: [Constructor; 19] = [
+                AstNode::Add, AstNode::Subtract, AstNode::Multiply, AstNode::Divide, AstNode::FloorDivide,
+                AstNode::Modulo, AstNode::Equal, AstNode::NotEqual, AstNode::Less, AstNode::LessEqual, AstNode::Greater,
+                AstNode::GreaterEqual, AstNode::And, AstNode::Or, AstNode::BitAnd, AstNode::BitOr,
+                AstNode::ShiftLeft, AstNode::ShiftRight, AstNode::Power,
+            ];
+            const UNARY: [UnaryConstructor; 4] = [AstNode::Not, AstNode::Negate, AstNode::Factorial, AstNode::Percent];
+            let leaf = depth == 0 || self.below(4) == 0;
+            Rc::new(match if leaf { self.below(3) } else { 3 + self.below(10) } {
+                0 => AstNode::IntLiteral((self.below(1000) as i32).into()),
+                1 => AstNode::FloatLiteral(self.below(100) as f64 / 8.0),
+                2 => AstNode::Identifier(self.name()),
+                3..=5 => BINARY[self.below(BINARY.len())](self.tree(depth - 1), self.tree(depth - 1)),
+                6 | 7 => UNARY[self.below(UNARY.len())](self.tree(depth - 1)),
+                8 => AstNode::Conditional(self.tree(depth - 1), self.tree(depth - 1), self.tree(depth - 1)),
+                9 => AstNode::Assign(Rc::new(AstNode::Identifier(self.name())), self.tree(depth - 1)),
+                10 => {
+                    let count = self.below(3);
+                    AstNode::Call(self.name(), (0..count).map(|_| self.tree(depth - 1)).collect())
+                },
+                11 => {
+                    let count = 1 + self.below(2);
+                    let bindings = (0..count).map(|_| (self.name(), self.tree(depth - 1))).collect();
+                    if self.below(2) == 0 {
+                        AstNode::Let(bindings, self.tree(depth - 1))
+                    } else {
+                        AstNode::Block(bindings.into_iter().map(|(_, statement)| statement).collect())
+                    }
+                },
+                _ => {
+                    let mut left = self.tree(depth - 1);
+                    let comparisons = (0..2 + self.below(2)).map(|_| {
+                        let right = self.tree(depth - 1);
+                        let comparison = BINARY[6 + self.below(6)](left.clone(), right.clone());
+                        left = right;
+                        Rc::new(comparison)
+                    }).collect();
+                    AstNode::Chain(comparisons)
+                },
+            })
+        }
+    }
+
+    #[test]
+    fn test_printed_trees_parse_back() {
+        for seed in 1..=2000u64 {
+            let mut random = Random(seed.wrapping_mul(0x9e37_79b9_7f4a_7c15));
+            let tree = random.tree(4);
+            for text in [tree.to_string(), strip_parens(&tree)] {
+                assert_eq!(crate::parse_str(&text).as_ref(), Ok(&*tree), "seed {}: {}", seed, text);
+            }
+        }
+    }
+}
+
+#[cfg(all(test, feature = "serde"))]
+mod serde_tests {
+    use super::*;
+    use crate::{evaluate, parse_str, tokenize_all, Op, Token, Value};
+
+    #[test]
+    fn test_json_round_trip() {
+        let node = parse_str("(1+2)*3").unwrap();
+        let json = ast_to_json(&node);
+        let restored = ast_from_json(&json).unwrap();
+        assert_eq!(restored, node);
+        assert_eq!(evaluate(Rc::new(restored)), Ok(Value::Int(9.into())));
+        assert!(ast_from_json("{\"Nope\": []}").is_err());
+    }
+
+    #[test]
+    fn test_token_round_trip() {
+        let tokens = tokenize_all("def f(x) = x >= 2.5 || !x");
+        let json = serde_json::to_string(&tokens).unwrap();
+        assert_eq!(serde_json::from_str::<Vec<Token>>(&json).unwrap(), tokens);
+        assert_eq!(serde_json::to_string(&Token::Operator(Op::PlusAssign)).unwrap(), "{\"Operator\":\"PlusAssign\"}");
+        assert!(serde_json::from_str::<Token>("{\"Operator\": \"<>\"}").is_err());
+    }
+}
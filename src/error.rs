@@ -0,0 +1,142 @@
+use std::fmt;
+
+use crate::tokenizer::{Op, Token};
+use crate::Integer;
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum CalcError {
+    NotCallable(String),
+    ArityMismatch { name: String, expected: usize, found: usize },
+    TypeError(String),
+    UndefinedVariable(String),
+    EmptyInput,
+    DivisionByZero,
+    Overflow,
+    // A bignum power whose result would be too large to be worth computing, as `2 ^ 100000`.
+    PowerTooLarge,
+    RecursionLimit,
+    ShiftOutOfRange(Integer),
+    TooManyVariables(usize),
+    DomainError(String),
+    Io(String),
+    ReassignConst(String),
+    AssignmentForbidden,
+    EmbeddedAssignment,
+    StepLimitExceeded,
+    NotConstant,
+    // Positions are character offsets into the parsed input, when the parser was given one.
+    InvalidAssignTarget { position: Option<usize> },
+    UnexpectedToken { token: Token, position: Option<usize> },
+    MalformedNumber { text: String, position: Option<usize> },
+    InvalidCharacter { character: char, position: Option<usize> },
+    NestingTooDeep { position: Option<usize> },
+    // A `)` with no `(` open before it.
+    UnexpectedCloseParen { position: Option<usize> },
+    // An operator with an operand missing, as in `1 +` or `* 3`; `position` is the operator's.
+    ExpectedOperand { operator: Op, position: Option<usize> },
+    // Input left over after a complete expression, as the `2` in `1 2`.
+    TrailingTokens { position: Option<usize> },
+    // A parameter named twice in one definition, as in `def f(a, a) = a`; `position` is the second's.
+    DuplicateParameter { name: String, position: Option<usize> },
+    // A runtime error raised while evaluating the node at `position`, for trees from
+    // `parse_located`. Displays as the error itself.
+    Located { error: Box<CalcError>, position: usize },
+}
+
+impl CalcError {
+    pub fn position(&self) -> Option<usize> {
+        match self {
+            CalcError::InvalidAssignTarget { position }
+            | CalcError::UnexpectedToken { position, .. }
+            | CalcError::MalformedNumber { position, .. }
+            | CalcError::InvalidCharacter { position, .. }
+            | CalcError::NestingTooDeep { position }
+            | CalcError::UnexpectedCloseParen { position }
+            | CalcError::ExpectedOperand { position, .. }
+            | CalcError::TrailingTokens { position }
+            | CalcError::DuplicateParameter { position, .. } => *position,
+            CalcError::Located { position, .. } => Some(*position),
+            _ => None,
+        }
+    }
+
+    // Attributes the error to `position`, unless it already points somewhere more precise.
+    pub(crate) fn at(self, position: usize) -> CalcError {
+        match self.position() {
+            Some(_) => self,
+            None => CalcError::Located { error: Box::new(self), position },
+        }
+    }
+
+    // The error with any runtime position dropped.
+    pub fn unlocated(self) -> CalcError {
+        match self {
+            CalcError::Located { error, .. } => *error,
+            error => error,
+        }
+    }
+
+    // Echoes the line of `input` the error points at and underlines the offending spot with a `^`.
+    // Errors without a position render as just the message.
+    pub fn render(&self, input: &str) -> String {
+        let mut rendered = format!("error: {}", self);
+        let position = match self.position() {
+            Some(position) => position,
+            None => return rendered,
+        };
+        let mut line_start = 0;
+        for (number, raw_line) in input.split('\n').enumerate() {
+            let line = raw_line.strip_suffix('\r').unwrap_or(raw_line);
+            if position <= line_start + line.chars().count() {
+                // Keep tabs so the caret lines up with what the terminal shows above it.
+                let padding: String = line.chars().take(position - line_start)
+                    .map(|c| if c == '\t' { '\t' } else { ' ' })
+                    .collect();
+                let label = (number + 1).to_string();
+                let gutter = " ".repeat(label.len());
+                rendered.push_str(&format!("\n{} | {}\n{} | {}^", label, line, gutter, padding));
+                break;
+            }
+            line_start += raw_line.chars().count() + 1;
+        }
+        rendered
+    }
+}
+
+impl fmt::Display for CalcError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CalcError::NotCallable(name) => write!(f, "{} is not a function", name),
+            CalcError::ArityMismatch { name, expected, found } =>
+                write!(f, "{} expects {} argument(s) but got {}", name, expected, found),
+            CalcError::TypeError(message) => write!(f, "type error: {}", message),
+            CalcError::UndefinedVariable(name) => write!(f, "undefined variable {}", name),
+            CalcError::EmptyInput => write!(f, "empty input"),
+            CalcError::DivisionByZero => write!(f, "division by zero"),
+            CalcError::Overflow => write!(f, "integer overflow"),
+            CalcError::PowerTooLarge => write!(f, "result of ^ is too large"),
+            CalcError::RecursionLimit => write!(f, "evaluation nested too deeply"),
+            CalcError::ShiftOutOfRange(amount) => write!(f, "cannot shift by {} bits", amount),
+            CalcError::ReassignConst(name) => write!(f, "cannot reassign constant {}", name),
+            CalcError::AssignmentForbidden => write!(f, "assignments and definitions are not allowed here"),
+            CalcError::NotConstant => write!(f, "expression is not constant"),
+            CalcError::EmbeddedAssignment => write!(f, "an assignment must be a statement of its own"),
+            CalcError::StepLimitExceeded => write!(f, "evaluation took too many steps"),
+            CalcError::Io(message) => write!(f, "read error: {}", message),
+            CalcError::DomainError(message) => write!(f, "domain error: {}", message),
+            CalcError::TooManyVariables(limit) => write!(f, "too many variables (at most {} allowed)", limit),
+            CalcError::InvalidAssignTarget { .. } => write!(f, "only a variable can be assigned to"),
+            CalcError::UnexpectedToken { token, .. } => write!(f, "unexpected token {}", token),
+            CalcError::MalformedNumber { text, .. } => write!(f, "malformed number {}", text),
+            CalcError::InvalidCharacter { character, .. } => write!(f, "invalid character {}", character),
+            CalcError::NestingTooDeep { .. } => write!(f, "expression is nested too deeply"),
+            CalcError::UnexpectedCloseParen { .. } => write!(f, "unmatched )"),
+            CalcError::ExpectedOperand { operator, .. } => write!(f, "missing operand for {}", operator.symbol()),
+            CalcError::TrailingTokens { .. } => write!(f, "unexpected input after the expression"),
+            CalcError::DuplicateParameter { name, .. } => write!(f, "duplicate parameter {}", name),
+            CalcError::Located { error, .. } => write!(f, "{}", error),
+        }
+    }
+}
+
+impl std::error::Error for CalcError {}
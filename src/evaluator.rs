@@ -0,0 +1,1418 @@
+use std::cell::{Cell, RefCell};
+use std::collections::{HashMap, HashSet};
+use std::io::{self, Write};
+use std::rc::Rc;
+
+use crate::ast::{comparison_parts, label, AstNode};
+use crate::error::CalcError;
+use crate::parser::{parse_program, Constructor};
+use crate::tokenizer::LocaleOptions;
+use crate::value::{integer_to_f64, Function, Value};
+use crate::Integer;
+
+// What a value is, for type errors: `boolean true`, `function f`.
+fn describe_value(value: &Value) -> String {
+    match value {
+        Value::Int(_) | Value::Float(_) => format!("number {}", value),
+        Value::Bool(value) => format!("boolean {}", value),
+        Value::Function(function) => format!("function {}", function.name),
+    }
+}
+
+fn expect_float(value: Value) -> Result<f64, CalcError> {
+    match value {
+        Value::Int(value) => Ok(integer_to_f64(&value)),
+        Value::Float(value) => Ok(value),
+        value => Err(CalcError::TypeError(format!("expected a number but found {}", describe_value(&value)))),
+    }
+}
+
+pub(crate) fn expect_integer(value: Value) -> Result<Integer, CalcError> {
+    match value {
+        Value::Int(value) => Ok(value),
+        value => Err(CalcError::TypeError(format!("expected an integer but found {}", describe_value(&value)))),
+    }
+}
+
+// Looks up the callee and binds the already evaluated arguments in a fresh scope that sees the
+// caller's variables, so assignments inside the body never leak out of the call.
+fn call_scope(name: &str, arguments: Vec<Value>, environment: &HashMap<String, Value>) -> Result<(Rc<Function>, HashMap<String, Value>), CalcError> {
+    let function = match environment.get(name) {
+        Some(Value::Function(function)) => function.clone(),
+        Some(_) => return Err(CalcError::NotCallable(name.to_string())),
+        None => return Err(CalcError::UndefinedVariable(name.to_string())),
+    };
+    if function.parameters.len() != arguments.len() {
+        return Err(CalcError::ArityMismatch {
+            name: name.to_string(),
+            expected: function.parameters.len(),
+            found: arguments.len(),
+        });
+    }
+    let mut scope = environment.clone();
+    for (parameter, argument) in function.parameters.iter().zip(arguments) {
+        scope.insert(parameter.clone(), argument);
+    }
+    Ok((function, scope))
+}
+
+// Combines the already evaluated operands of a binary arithmetic or comparison node.
+// Two integers stay integers, except that an inexact `/` yields a float; a float on either side
+// makes the whole operation floating point, as does a negative exponent in `^`. Comparisons yield
+// booleans; `==` and `!=` with a float involved compare within `epsilon` (see `float_equal`).
+// Bitwise operators and shifts take integers only. Booleans only compare for equality with each
+// other; every other operator with a boolean operand is a type error.
+fn apply_binary(node: &AstNode, left: Value, right: Value, mode: ArithmeticMode, epsilon: f64) -> Result<Value, CalcError> {
+    match (left, right) {
+        (Value::Int(left), Value::Int(right)) => apply_int(node, left, right, mode),
+        (Value::Bool(left), Value::Bool(right)) if matches!(node, AstNode::Equal(..)) => Ok(Value::Bool(left == right)),
+        (Value::Bool(left), Value::Bool(right)) if matches!(node, AstNode::NotEqual(..)) => Ok(Value::Bool(left != right)),
+        (left, right) if matches!(node, AstNode::BitAnd(..) | AstNode::BitOr(..) | AstNode::ShiftLeft(..) | AstNode::ShiftRight(..)) => {
+            expect_float(left)?;
+            expect_float(right)?;
+            Err(CalcError::TypeError(format!("{} needs integer operands", node)))
+        },
+        (left, right) => Ok(apply_float(node, expect_float(left)?, expect_float(right)?, epsilon)),
+    }
+}
+
+fn apply_int(node: &AstNode, left: Integer, right: Integer, mode: ArithmeticMode) -> Result<Value, CalcError> {
+    Ok(match node {
+        AstNode::Add(..) => Value::Int(arithmetic(mode, "+", left, right)?),
+        AstNode::Subtract(..) => Value::Int(arithmetic(mode, "-", left, right)?),
+        AstNode::Multiply(..) => Value::Int(arithmetic(mode, "*", left, right)?),
+        AstNode::Divide(..) if checked("%", left.clone(), right.clone())? == Integer::default() =>
+            Value::Int(checked("/", left, right)?),
+        AstNode::Divide(..) => Value::Float(integer_to_f64(&left) / integer_to_f64(&right)),
+        AstNode::FloorDivide(..) => Value::Int(floor_divide(left, right)?),
+        AstNode::Modulo(..) => Value::Int(checked("%", left, right)?),
+        AstNode::Equal(..) => Value::from_bool(left == right),
+        AstNode::NotEqual(..) => Value::from_bool(left != right),
+        AstNode::Less(..) => Value::from_bool(left < right),
+        AstNode::LessEqual(..) => Value::from_bool(left <= right),
+        AstNode::Greater(..) => Value::from_bool(left > right),
+        AstNode::GreaterEqual(..) => Value::from_bool(left >= right),
+        AstNode::BitAnd(..) => Value::Int(left & right),
+        AstNode::BitOr(..) => Value::Int(left | right),
+        AstNode::ShiftLeft(..) => Value::Int(shift(true, left, right)?),
+        AstNode::ShiftRight(..) => Value::Int(shift(false, left, right)?),
+        AstNode::Power(..) => power(left, right)?,
+        _ => unreachable!("not a binary operator: {:?}", node),
+    })
+}
+
+// Shifting an i64 by its width or more has no meaning (Rust's own operators panic), so the
+// amount must lie in 0..64. `>>` keeps the sign, and bits shifted out of `<<` are dropped.
+#[cfg(not(feature = "bignum"))]
+fn shift(left_shift: bool, value: Integer, amount: Integer) -> Result<Integer, CalcError> {
+    match u32::try_from(amount) {
+        Ok(bits) if bits < Integer::BITS => Ok(if left_shift { value << bits } else { value >> bits }),
+        _ => Err(CalcError::ShiftOutOfRange(amount)),
+    }
+}
+
+// Big integers only grow with `<<`, so the amount is capped to keep results a few kilobytes.
+#[cfg(feature = "bignum")]
+const MAX_BIG_SHIFT: u32 = 1 << 16;
+
+#[cfg(feature = "bignum")]
+fn shift(left_shift: bool, value: Integer, amount: Integer) -> Result<Integer, CalcError> {
+    match num_traits::ToPrimitive::to_u32(&amount) {
+        Some(bits) if bits < MAX_BIG_SHIFT => Ok(if left_shift { value << bits } else { value >> bits }),
+        _ => Err(CalcError::ShiftOutOfRange(amount)),
+    }
+}
+
+// What `+`, `-` and `*` do when an integer result does not fit. Every other operation, division
+// and factorial included, reports overflow as an error whatever the mode.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum ArithmeticMode {
+    // Fail with `Overflow`.
+    #[default]
+    Checked,
+    // Wrap around in two's complement, so `MAX + 1` is `MIN`.
+    Wrapping,
+    // Clamp to the nearest representable integer, so `MAX + 1` is `MAX`.
+    Saturating,
+}
+
+#[cfg(not(feature = "bignum"))]
+fn arithmetic(mode: ArithmeticMode, operator: &str, left: Integer, right: Integer) -> Result<Integer, CalcError> {
+    Ok(match (mode, operator) {
+        (ArithmeticMode::Checked, _) => return checked(operator, left, right),
+        (ArithmeticMode::Wrapping, "+") => left.wrapping_add(right),
+        (ArithmeticMode::Wrapping, "-") => left.wrapping_sub(right),
+        (ArithmeticMode::Wrapping, _) => left.wrapping_mul(right),
+        (ArithmeticMode::Saturating, "+") => left.saturating_add(right),
+        (ArithmeticMode::Saturating, "-") => left.saturating_sub(right),
+        (ArithmeticMode::Saturating, _) => left.saturating_mul(right),
+    })
+}
+
+// Big integers never overflow, so every mode computes the same.
+#[cfg(feature = "bignum")]
+fn arithmetic(_mode: ArithmeticMode, operator: &str, left: Integer, right: Integer) -> Result<Integer, CalcError> {
+    checked(operator, left, right)
+}
+
+// Integer arithmetic that reports division by zero and overflow as errors instead of panicking.
+// `/` and `%` truncate toward zero, like Rust's own operators.
+fn checked(operator: &str, left: Integer, right: Integer) -> Result<Integer, CalcError> {
+    if matches!(operator, "/" | "%") && right == Integer::default() {
+        return Err(CalcError::DivisionByZero);
+    }
+    integer_arithmetic(operator, left, right).ok_or(CalcError::Overflow)
+}
+
+#[cfg(not(feature = "bignum"))]
+fn integer_arithmetic(operator: &str, left: Integer, right: Integer) -> Option<Integer> {
+    match operator {
+        "+" => left.checked_add(right),
+        "-" => left.checked_sub(right),
+        "*" => left.checked_mul(right),
+        "/" => left.checked_div(right),
+        _ => left.checked_rem(right),
+    }
+}
+
+// Big integers never overflow.
+#[cfg(feature = "bignum")]
+fn integer_arithmetic(operator: &str, left: Integer, right: Integer) -> Option<Integer> {
+    Some(match operator {
+        "+" => left + right,
+        "-" => left - right,
+        "*" => left * right,
+        "/" => left / right,
+        _ => left % right,
+    })
+}
+
+// Switches for how input is read and evaluated. The defaults are the behavior of
+// `interpret_with_environment`.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct EvalOptions {
+    // Reading a name that is not bound yields 0 instead of failing with `UndefinedVariable`, as
+    // an empty spreadsheet cell would. Calls to unbound names still fail.
+    pub undefined_as_zero: bool,
+    // An operand written straight after another multiplies it: `2(3 + 4)` is 14 and `3x` is
+    // `3 * x`.
+    pub implicit_mul: bool,
+    // Off, an assignment inside a larger expression, as in `1 + (x = 2)`, fails to parse with
+    // `EmbeddedAssignment`; one standing as a statement of its own is still fine.
+    pub allow_embedded_assignment: bool,
+    // The most nodes evaluation may visit before failing with `StepLimitExceeded`, function
+    // bodies included, so untrusted input cannot keep the host busy indefinitely. Unlimited
+    // when unset.
+    pub max_steps: Option<usize>,
+    // Remember the value of every subtree that neither reads, assigns nor calls anything, so a
+    // subtree shared by several parents (an `Rc` reached twice) is evaluated once.
+    pub memoize: bool,
+    // How numbers are written; see `LocaleOptions`.
+    pub locale: LocaleOptions,
+    // What `+`, `-` and `*` do on integer overflow.
+    pub mode: ArithmeticMode,
+    // The tolerance for float `==` and `!=`; zero makes them exact.
+    pub epsilon: f64,
+    // The most variables an assignment may leave in scope, `ans` aside; reassigning an existing
+    // name is always allowed. Unlimited when unset.
+    pub max_variables: Option<usize>,
+}
+
+impl Default for EvalOptions {
+    fn default() -> EvalOptions {
+        EvalOptions {
+            undefined_as_zero: false,
+            implicit_mul: false,
+            allow_embedded_assignment: true,
+            max_steps: None,
+            memoize: false,
+            locale: LocaleOptions::default(),
+            mode: ArithmeticMode::default(),
+            epsilon: DEFAULT_EPSILON,
+            max_variables: None,
+        }
+    }
+}
+
+// The default tolerance for `==` and `!=` on floats.
+pub const DEFAULT_EPSILON: f64 = 1e-9;
+
+// Float equality is deliberately not bitwise: rounding makes `0.1 + 0.2` differ from `0.3` in the
+// last bit, which a calculator user would never expect. Two floats are equal when they differ by
+// at most `epsilon`, scaled by the larger magnitude once that exceeds 1 so that big values get a
+// relative rather than an absolute tolerance. Equal infinities are equal and NaN equals nothing.
+// Integers always compare exactly; the tolerance applies only when a float is involved.
+fn float_equal(left: f64, right: f64, epsilon: f64) -> bool {
+    left == right || (left - right).abs() <= epsilon * left.abs().max(right.abs()).max(1.0)
+}
+
+fn apply_float(node: &AstNode, left: f64, right: f64, epsilon: f64) -> Value {
+    match node {
+        AstNode::Add(..) => Value::Float(left + right),
+        AstNode::Subtract(..) => Value::Float(left - right),
+        AstNode::Multiply(..) => Value::Float(left * right),
+        AstNode::Divide(..) => Value::Float(left / right),
+        AstNode::FloorDivide(..) => Value::Float((left / right).floor()),
+        AstNode::Modulo(..) => Value::Float(left % right),
+        AstNode::Equal(..) => Value::from_bool(float_equal(left, right, epsilon)),
+        AstNode::NotEqual(..) => Value::from_bool(!float_equal(left, right, epsilon)),
+        AstNode::Less(..) => Value::from_bool(left < right),
+        AstNode::LessEqual(..) => Value::from_bool(left <= right),
+        AstNode::Greater(..) => Value::from_bool(left > right),
+        AstNode::GreaterEqual(..) => Value::from_bool(left >= right),
+        AstNode::Power(..) => Value::Float(left.powf(right)),
+        _ => unreachable!("not a binary operator: {:?}", node),
+    }
+}
+
+// An integer raised to a non-negative integer stays an integer, so `2 ^ 3` is 8. A negative
+// exponent falls back to floating point, so `2 ^ -1` is 0.5, just like a float operand does.
+fn power(base: Integer, exponent: Integer) -> Result<Value, CalcError> {
+    if exponent < Integer::default() {
+        return Ok(Value::Float(integer_to_f64(&base).powf(integer_to_f64(&exponent))));
+    }
+    integer_power(base, exponent).map(Value::Int)
+}
+
+#[cfg(not(feature = "bignum"))]
+fn integer_power(base: Integer, exponent: Integer) -> Result<Integer, CalcError> {
+    match u32::try_from(exponent) {
+        Ok(exponent) => base.checked_pow(exponent).ok_or(CalcError::Overflow),
+        // Only the powers of 0, 1 and -1 stay this small, and those repeat with period 2.
+        Err(_) if base.unsigned_abs() <= 1 => Ok(base.pow(2 - (exponent % 2) as u32)),
+        Err(_) => Err(CalcError::Overflow),
+    }
+}
+
+// Big integers never overflow, so a power is refused with `PowerTooLarge` once its result would
+// need more than this many bits, keeping results a few kilobytes.
+#[cfg(feature = "bignum")]
+const MAX_BIG_POWER_BITS: u64 = 1 << 16;
+
+#[cfg(feature = "bignum")]
+fn integer_power(base: Integer, exponent: Integer) -> Result<Integer, CalcError> {
+    let small_base = base.magnitude() <= &num_bigint::BigUint::from(1u8);
+    match num_traits::ToPrimitive::to_u32(&exponent) {
+        Some(exponent) if small_base || base.bits().saturating_mul(exponent.into()) <= MAX_BIG_POWER_BITS =>
+            Ok(base.pow(exponent)),
+        _ if small_base => Ok(base.pow(if exponent % 2u8 == Integer::default() { 2 } else { 1 })),
+        _ => Err(CalcError::PowerTooLarge),
+    }
+}
+
+// Integer division rounds toward zero; floor division rounds toward negative infinity instead,
+// so `-7 // 2` is -4. The two differ only when the remainder's sign differs from the divisor's.
+fn floor_divide(left: Integer, right: Integer) -> Result<Integer, CalcError> {
+    let zero = Integer::default();
+    let remainder = checked("%", left.clone(), right.clone())?;
+    let quotient = checked("/", left, right.clone())?;
+    if remainder != zero && (remainder < zero) != (right < zero) {
+        checked("-", quotient, Integer::from(1u8))
+    } else {
+        Ok(quotient)
+    }
+}
+
+// n! for a non-negative integer n. The running product only grows, so it overflows exactly when
+// n! itself does not fit: a wider intermediate type would not admit any more inputs. It also
+// stops at the first overflow, however large n is.
+fn factorial(value: Value) -> Result<Value, CalcError> {
+    let n = match value {
+        Value::Int(n) => n,
+        value => {
+            expect_float(value)?;
+            return Err(CalcError::TypeError("factorial needs an integer operand".to_string()));
+        },
+    };
+    if n < Integer::default() {
+        return Err(CalcError::DomainError(format!("factorial of negative number {}", n)));
+    }
+    let mut product = Integer::from(1u8);
+    let mut factor = Integer::from(1u8);
+    while factor <= n {
+        product = checked("*", product, factor.clone())?;
+        factor = checked("+", factor, Integer::from(1u8))?;
+    }
+    Ok(Value::Int(product))
+}
+
+fn apply_unary(node: &AstNode, operand: Value) -> Result<Value, CalcError> {
+    match node {
+        AstNode::Not(_) => Ok(Value::from_bool(!truth(operand)?)),
+        AstNode::Negate(_) => negate(operand),
+        AstNode::Factorial(_) => factorial(operand),
+        AstNode::Percent(_) => percent(operand),
+        _ => unreachable!("not a unary operator: {}", node),
+    }
+}
+
+// The smallest integer has no negative, so negating it overflows like `0 - x` would.
+fn negate(value: Value) -> Result<Value, CalcError> {
+    match value {
+        Value::Int(n) => Ok(Value::Int(checked("-", Integer::default(), n)?)),
+        value => Ok(Value::Float(-expect_float(value)?)),
+    }
+}
+
+// Always a float, so `50%` is 0.5 rather than 0.
+fn percent(value: Value) -> Result<Value, CalcError> {
+    Ok(Value::Float(expect_float(value)? / 100.0))
+}
+
+// The magnitude of the smallest integer does not fit, so `abs` of it overflows like `0 - x` would.
+fn abs(value: Value) -> Result<Value, CalcError> {
+    match value {
+        Value::Int(n) if n < Integer::default() => Ok(Value::Int(checked("-", Integer::default(), n)?)),
+        value => Ok(match expect_float(value.clone())? {
+            float if float < 0.0 => Value::Float(-float),
+            _ => value,
+        }),
+    }
+}
+
+// -1, 0 or 1, always an integer; a float zero of either sign counts as 0.
+fn sign(value: Value) -> Result<Value, CalcError> {
+    let ordering = match value {
+        Value::Int(n) => n.cmp(&Integer::default()),
+        value => {
+            let float = expect_float(value)?;
+            float.partial_cmp(&0.0).ok_or_else(|| CalcError::DomainError("sign of NaN".to_string()))?
+        },
+    };
+    Ok(Value::Int(Integer::from(ordering as i8)))
+}
+
+// The mean of the arguments, always a float. Adding them up as floats means no integer total can
+// overflow on the way.
+fn average(arguments: Vec<Value>) -> Result<Value, CalcError> {
+    if arguments.is_empty() {
+        return Err(CalcError::ArityMismatch { name: "average".to_string(), expected: 1, found: 0 });
+    }
+    let count = arguments.len() as f64;
+    let total = arguments.into_iter().map(expect_float).sum::<Result<f64, CalcError>>()?;
+    Ok(Value::Float(total / count))
+}
+
+// Always non-negative, and 0 only when both are. The magnitude of the smallest integer does not
+// fit, so a result that would be it overflows.
+fn gcd(mut left: Integer, mut right: Integer) -> Result<Integer, CalcError> {
+    while right != Integer::default() {
+        let remainder = checked("%", left, right.clone())?;
+        left = right;
+        right = remainder;
+    }
+    match left < Integer::default() {
+        true => checked("-", Integer::default(), left),
+        false => Ok(left),
+    }
+}
+
+// Dividing by the gcd before multiplying keeps the intermediate no larger than the result, so
+// only a result that does not fit overflows.
+fn lcm(left: Integer, right: Integer) -> Result<Integer, CalcError> {
+    let divisor = gcd(left.clone(), right.clone())?;
+    if divisor == Integer::default() {
+        return Ok(divisor);
+    }
+    let product = checked("*", checked("/", left, divisor)?, right)?;
+    match product < Integer::default() {
+        true => checked("-", Integer::default(), product),
+        false => Ok(product),
+    }
+}
+
+// The arguments of a builtin taking exactly `N` of them.
+fn exactly<const N: usize>(name: &str, arguments: Vec<Value>) -> Result<[Value; N], CalcError> {
+    <[Value; N]>::try_from(arguments)
+        .map_err(|arguments| CalcError::ArityMismatch { name: name.to_string(), expected: N, found: arguments.len() })
+}
+
+// Conditions and the operands of `&&`, `||` and `!` must be booleans; no number stands in for one.
+fn truth(value: Value) -> Result<bool, CalcError> {
+    match value {
+        Value::Bool(value) => Ok(value),
+        value => Err(CalcError::TypeError(format!("expected a boolean but found {}", describe_value(&value)))),
+    }
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct SideEffect {
+    pub name: String,
+    pub old_value: Option<Value>,
+    pub new_value: Value,
+}
+
+// One step of a traced evaluation: an operator applied to its already evaluated operands, as in
+// `Add [1, 6] => 7`. Operands and results are reported in the order evaluation computes them, so
+// a subexpression's event comes before that of the operator using it.
+#[derive(Clone, PartialEq, Debug)]
+pub struct EvalEvent {
+    pub operation: String,
+    pub operands: Vec<Value>,
+    pub result: Value,
+}
+
+// The variable holding the previous result, as on a physical calculator. The interpreter sets it
+// rather than the user, so it does not count against a variable limit.
+pub(crate) const ANSWER: &str = "ans";
+
+// Stores a statement's value in `ans` for the next one to read, unless the program has made `ans`
+// a constant of its own.
+pub(crate) fn record_answer(environment: &mut HashMap<String, Value>, constants: &HashSet<String>, value: &Value) {
+    if !constants.contains(ANSWER) {
+        environment.insert(ANSWER.to_string(), value.clone());
+    }
+}
+
+// Evaluation keeps operators on a heap stack, but recurses on the native one for every call, block,
+// `let` and chain, to run it in its own scope. Counting those across nested evaluators caps the
+// stack used by the whole evaluation, so runaway recursion and overly deep scopes fail with
+// `RecursionLimit` instead of overflowing the stack. The cap leaves headroom on a 2 MiB thread
+// stack in an unoptimized build.
+const MAX_EVAL_DEPTH: usize = 256;
+
+// A function implemented by the embedding program, called with already evaluated arguments.
+pub type HostFunction = Box<dyn Fn(&[Value]) -> Result<Value, CalcError>>;
+
+pub type HostFunctions = HashMap<String, HostFunction>;
+
+// Functions every evaluation provides. A variable or host function of the same name shadows them.
+pub(crate) const BUILTINS: [&str; 20] = [
+    "print", "abs", "sign", "min", "max", "sum", "product", "average", "clamp", "gcd", "lcm",
+    "checked_add", "checked_sub", "checked_mul",
+    "wrapping_add", "wrapping_sub", "wrapping_mul",
+    "saturating_add", "saturating_sub", "saturating_mul",
+];
+
+// `checked_add`, `wrapping_mul` and the like: `+`, `-` or `*` in the named mode, whatever mode the
+// evaluation itself runs in.
+fn explicit_mode(name: &str) -> Option<(ArithmeticMode, Constructor)> {
+    let (mode, operation) = name.split_once('_')?;
+    let mode = match mode {
+        "checked" => ArithmeticMode::Checked,
+        "wrapping" => ArithmeticMode::Wrapping,
+        "saturating" => ArithmeticMode::Saturating,
+        _ => return None,
+    };
+    let operator: Constructor = match operation {
+        "add" => AstNode::Add,
+        "sub" => AstNode::Subtract,
+        "mul" => AstNode::Multiply,
+        _ => return None,
+    };
+    Some((mode, operator))
+}
+
+// Where the host keeps variables of its own, read by name. Evaluating against one never writes to
+// it: names the expression binds itself, such as `let` bindings and parameters, shadow it.
+pub trait VariableSource {
+    fn get(&self, name: &str) -> Option<Value>;
+}
+
+impl VariableSource for HashMap<String, Value> {
+    fn get(&self, name: &str) -> Option<Value> {
+        HashMap::get(self, name).cloned()
+    }
+}
+
+// Evaluation state: the variables in scope plus optional observers of what evaluation does.
+pub(crate) struct Evaluator<'a> {
+    environment: &'a mut HashMap<String, Value>,
+    side_effects: Option<&'a mut Vec<SideEffect>>,
+    // Inside a block, the names it and the blocks around it keep to themselves: `let` bindings and
+    // names first assigned there. Writes to them are not side effects. `None` outside any block.
+    locals: Option<HashSet<String>>,
+    // Where `print` writes; standard output when unset.
+    output: Option<&'a mut dyn Write>,
+    host_functions: Option<&'a HostFunctions>,
+    // Read for any variable not in `environment`.
+    variables: Option<&'a dyn VariableSource>,
+    // Names declared with `const`, which no later assignment may change.
+    constants: HashSet<String>,
+    options: EvalOptions,
+    // Told of every operator applied, when tracing.
+    tracer: Option<&'a mut dyn FnMut(EvalEvent)>,
+    // Calls, blocks, `let`s and chains being evaluated around this point, nested evaluators'
+    // included; see `MAX_EVAL_DEPTH`.
+    depth: usize,
+    // Nodes evaluated so far, shared with every nested evaluator so `EvalOptions::max_steps`
+    // bounds the whole evaluation.
+    steps: Rc<Cell<usize>>,
+    // Shared the same way, so pure subtrees of function bodies are remembered across calls.
+    memo: Rc<RefCell<Memo>>,
+}
+
+// The state behind `EvalOptions::memoize`. Results are keyed by node address, and each entry holds
+// on to its node so that the address cannot be reused while the entry exists. A subtree counts as
+// pure if `effects`, bumped by every variable read, assignment and call, is the same after
+// evaluating it as before.
+#[derive(Default)]
+struct Memo {
+    results: HashMap<*const AstNode, (Rc<AstNode>, Value)>,
+    effects: usize,
+}
+
+impl<'a> Evaluator<'a> {
+    pub(crate) fn new(environment: &'a mut HashMap<String, Value>) -> Evaluator<'a> {
+        Evaluator {
+            environment,
+            side_effects: None,
+            locals: None,
+            output: None,
+            host_functions: None,
+            variables: None,
+            constants: HashSet::new(),
+            options: EvalOptions::default(),
+            tracer: None,
+            depth: 0,
+            steps: Rc::new(Cell::new(0)),
+            memo: Rc::default(),
+        }
+    }
+
+    fn with_output(mut self, output: &'a mut dyn Write) -> Evaluator<'a> {
+        self.output = Some(output);
+        self
+    }
+
+    fn with_host_functions(mut self, host_functions: &'a HostFunctions) -> Evaluator<'a> {
+        self.host_functions = Some(host_functions);
+        self
+    }
+
+    // The evaluator for a function body or block running in `scope`, with this one's settings,
+    // observers and budgets.
+    fn nested<'b>(&'b mut self, scope: &'b mut HashMap<String, Value>) -> Evaluator<'b>
+    where
+        'a: 'b,
+    {
+        Evaluator {
+            environment: scope,
+            side_effects: self.side_effects.as_deref_mut(),
+            locals: self.locals.clone(),
+            output: self.output.as_deref_mut().map(|output| output as &mut dyn Write),
+            host_functions: self.host_functions,
+            variables: self.variables,
+            constants: self.constants.clone(),
+            options: self.options,
+            tracer: self.tracer.as_deref_mut().map(|tracer| tracer as &mut dyn FnMut(EvalEvent)),
+            depth: self.depth,
+            steps: self.steps.clone(),
+            memo: self.memo.clone(),
+        }
+    }
+
+    // Names in the environment shadow host functions; only a name with no binding at all is
+    // looked up among them.
+    fn call<F>(&mut self, name: &str, arguments: Vec<Value>, run: F) -> Result<Value, CalcError>
+    where
+        F: Fn(&mut Evaluator, Rc<AstNode>) -> Result<Value, CalcError>,
+    {
+        self.memo.borrow_mut().effects += 1;
+        if !self.environment.contains_key(name) {
+            if let Some(function) = self.host_functions.and_then(|functions| functions.get(name)) {
+                return function(&arguments);
+            }
+            if BUILTINS.contains(&name) {
+                return self.builtin(name, arguments);
+            }
+        }
+        let (function, mut scope) = call_scope(name, arguments, self.environment)?;
+        let mut inner = self.nested(&mut scope);
+        // The call's scope is dropped when it returns, so nothing assigned in it is a side effect.
+        inner.side_effects = None;
+        // Parameters shadow constants of the same name.
+        for parameter in &function.parameters {
+            inner.constants.remove(parameter);
+        }
+        // A position inside the body may refer to some earlier input, so errors from it are
+        // attributed to the call instead.
+        run(&mut inner, function.body.clone()).map_err(CalcError::unlocated)
+    }
+
+    // `print`, `abs` and `sign` take exactly one argument, `gcd`, `lcm` and the explicit-mode
+    // arithmetic two and `clamp` three; `min`, `max`, `sum`, `product` and `average` take any
+    // number but zero.
+    fn builtin(&mut self, name: &str, arguments: Vec<Value>) -> Result<Value, CalcError> {
+        if let Some((mode, operator)) = explicit_mode(name) {
+            let [left, right] = exactly(name, arguments)?;
+            let placeholder = Rc::new(AstNode::IntLiteral(Integer::default()));
+            return apply_binary(&operator(placeholder.clone(), placeholder), left, right, mode, self.options.epsilon);
+        }
+        if matches!(name, "min" | "max" | "sum" | "product") {
+            return self.reduce(name, arguments);
+        }
+        if name == "average" {
+            return average(arguments);
+        }
+        if name == "clamp" {
+            let [value, low, high] = exactly(name, arguments)?;
+            return self.clamp(value, low, high);
+        }
+        if matches!(name, "gcd" | "lcm") {
+            let [left, right] = exactly(name, arguments)?;
+            let (left, right) = (expect_integer(left)?, expect_integer(right)?);
+            let result = if name == "gcd" { gcd(left, right)? } else { lcm(left, right)? };
+            return Ok(Value::Int(result));
+        }
+        let [value] = exactly(name, arguments)?;
+        match name {
+            // print(x) writes x on a line of its own and returns it.
+            "print" => {
+                let written = match self.output.as_mut() {
+                    Some(output) => writeln!(output, "{}", value),
+                    None => writeln!(io::stdout(), "{}", value),
+                };
+                written.map_err(|error| CalcError::Io(error.to_string()))?;
+                Ok(value)
+            },
+            "abs" => abs(value),
+            "sign" => sign(value),
+            _ => unreachable!("not a builtin: {}", name),
+        }
+    }
+
+    // Folds the arguments with `+` or `*`, or keeps the least or greatest by `<`/`>`, so integers
+    // and floats mix just as they do in `a + b` or `a < b`. On a tie `min` and `max` keep the first.
+    fn reduce(&self, name: &str, arguments: Vec<Value>) -> Result<Value, CalcError> {
+        let placeholder = Rc::new(AstNode::IntLiteral(Integer::default()));
+        let operator = match name {
+            "sum" => AstNode::Add(placeholder.clone(), placeholder),
+            "product" => AstNode::Multiply(placeholder.clone(), placeholder),
+            "min" => AstNode::Less(placeholder.clone(), placeholder),
+            "max" => AstNode::Greater(placeholder.clone(), placeholder),
+            _ => unreachable!("not a builtin: {}", name),
+        };
+        let mut values = arguments.into_iter();
+        let mut result = values.next()
+            .ok_or_else(|| CalcError::ArityMismatch { name: name.to_string(), expected: 1, found: 0 })?;
+        expect_float(result.clone())?;
+        for value in values {
+            result = match operator {
+                AstNode::Add(..) | AstNode::Multiply(..) => apply_binary(&operator, result, value, self.options.mode, self.options.epsilon)?,
+                _ if truth(apply_binary(&operator, value.clone(), result.clone(), self.options.mode, self.options.epsilon)?)? => value,
+                _ => result,
+            };
+        }
+        Ok(result)
+    }
+
+    // `value` if it lies within `[low, high]`, else the bound it passes, compared by `<` as `min`
+    // and `max` do. Bounds the wrong way round are an error rather than an empty range.
+    fn clamp(&self, value: Value, low: Value, high: Value) -> Result<Value, CalcError> {
+        let placeholder = Rc::new(AstNode::IntLiteral(Integer::default()));
+        let less = AstNode::Less(placeholder.clone(), placeholder);
+        let below = |left: &Value, right: &Value| truth(apply_binary(&less, left.clone(), right.clone(), self.options.mode, self.options.epsilon)?);
+        if below(&high, &low)? {
+            return Err(CalcError::DomainError(format!("clamp to [{}, {}], whose lower bound is above its upper", low, high)));
+        }
+        Ok(if below(&value, &low)? {
+            low
+        } else if below(&high, &value)? {
+            high
+        } else {
+            value
+        })
+    }
+
+    // The cap counts every variable in scope where the assignment happens, including a function's
+    // parameters and a block's locals.
+    fn assign(&mut self, name: &str, value: Value) -> Result<(), CalcError> {
+        self.memo.borrow_mut().effects += 1;
+        if self.constants.contains(name) {
+            return Err(CalcError::ReassignConst(name.to_string()));
+        }
+        if let Some(limit) = self.options.max_variables {
+            let count = self.environment.len() - usize::from(self.environment.contains_key(ANSWER));
+            if count >= limit && !self.environment.contains_key(name) {
+                return Err(CalcError::TooManyVariables(limit));
+            }
+        }
+        if let Some(locals) = self.locals.as_mut().filter(|_| !self.environment.contains_key(name)) {
+            locals.insert(name.to_string());
+        }
+        let old_value = self.environment.insert(name.to_string(), value.clone());
+        let local = self.locals.as_ref().is_some_and(|locals| locals.contains(name));
+        if let Some(side_effects) = self.side_effects.as_mut().filter(|_| !local) {
+            side_effects.push(SideEffect { name: name.to_string(), old_value, new_value: value });
+        }
+        Ok(())
+    }
+
+    fn declare_constant(&mut self, name: &str, value: Value) -> Result<Value, CalcError> {
+        self.assign(name, value.clone())?;
+        self.constants.insert(name.to_string());
+        Ok(value)
+    }
+
+    // Runs a block's statements against a copy of the environment, so names first assigned inside
+    // stay local to the block, as do constants declared inside it. Assignments to names that
+    // already existed outside are reported as side effects when they happen and written back
+    // once the block finishes.
+    // `let` runs its body as such a block after binding each name in turn; the bound names
+    // shadow any outer ones and are never written back.
+    fn block<F>(&mut self, bindings: &[(String, Rc<AstNode>)], statements: &[Rc<AstNode>], run: F) -> Result<Value, CalcError>
+    where
+        F: Fn(&mut Evaluator, Rc<AstNode>) -> Result<Value, CalcError>,
+    {
+        let mut scope = self.environment.clone();
+        let mut inner = self.nested(&mut scope);
+        inner.locals.get_or_insert_with(HashSet::new);
+        for (name, value) in bindings {
+            let value = run(&mut inner, value.clone())?;
+            inner.constants.remove(name);
+            inner.environment.insert(name.clone(), value);
+            if let Some(locals) = inner.locals.as_mut() {
+                locals.insert(name.clone());
+            }
+        }
+        let mut value = Value::Int(Integer::default());
+        for statement in statements {
+            value = run(&mut inner, statement.clone())?;
+        }
+        // The checks and the record of each write were made as it happened.
+        for (name, new_value) in scope {
+            if !bindings.iter().any(|(bound, _)| *bound == name) && self.environment.contains_key(&name) {
+                self.environment.insert(name, new_value);
+            }
+        }
+        Ok(value)
+    }
+
+    // Compares left to right, stopping at the first comparison that fails, like the `&&` the
+    // chain stands for. Every operand is evaluated at most once.
+    fn chain<F>(&mut self, comparisons: &[Rc<AstNode>], run: F) -> Result<Value, CalcError>
+    where
+        F: Fn(&mut Evaluator, Rc<AstNode>) -> Result<Value, CalcError>,
+    {
+        let mut left = None;
+        for comparison in comparisons {
+            let (_, first, right) = comparison_parts(comparison)
+                .ok_or_else(|| CalcError::TypeError(format!("{} is not a comparison", comparison)))?;
+            let left_value = match left.take() {
+                Some(value) => value,
+                None => run(self, first.clone())?,
+            };
+            let right_value = run(self, right.clone())?;
+            if !truth(self.apply(comparison, left_value, right_value.clone())?)? {
+                return Ok(Value::from_bool(false));
+            }
+            left = Some(right_value);
+        }
+        Ok(Value::from_bool(true))
+    }
+
+    // Applies a binary operator, reporting it to the tracer if there is one. Operands are only
+    // copied for the tracer, so untraced evaluation pays nothing more than the check.
+    fn apply(&mut self, node: &AstNode, left: Value, right: Value) -> Result<Value, CalcError> {
+        let traced = self.tracer.is_some().then(|| vec![left.clone(), right.clone()]);
+        let result = apply_binary(node, left, right, self.options.mode, self.options.epsilon)?;
+        self.trace(node, traced, &result);
+        Ok(result)
+    }
+
+    fn trace(&mut self, node: &AstNode, operands: Option<Vec<Value>>, result: &Value) {
+        if let (Some(tracer), Some(operands)) = (self.tracer.as_mut(), operands) {
+            tracer(EvalEvent { operation: label(node), operands, result: result.clone() });
+        }
+    }
+
+    // Counts one more node evaluated against the step budget.
+    fn step(&self) -> Result<(), CalcError> {
+        let steps = self.steps.get() + 1;
+        self.steps.set(steps);
+        match self.options.max_steps {
+            Some(limit) if steps > limit => Err(CalcError::StepLimitExceeded),
+            _ => Ok(()),
+        }
+    }
+
+    fn read(&self, identifier: &str) -> Result<Value, CalcError> {
+        self.memo.borrow_mut().effects += 1;
+        let value = self.environment.get(identifier).cloned()
+            .or_else(|| self.variables.and_then(|variables| variables.get(identifier)));
+        match value {
+            Some(value) => Ok(value),
+            None if self.options.undefined_as_zero => Ok(Value::Int(Integer::default())),
+            None => Err(CalcError::UndefinedVariable(identifier.to_string())),
+        }
+    }
+
+    // Stores `value` in the existing variable `name` and returns what it held before.
+    fn exchange(&mut self, name: &str, value: Value) -> Result<Value, CalcError> {
+        let old_value = self.read(name)?;
+        self.assign(name, value)?;
+        Ok(old_value)
+    }
+
+    fn define_function(&mut self, name: &str, parameters: &[String], body: &Rc<AstNode>) -> Result<Value, CalcError> {
+        let function = Value::Function(Rc::new(Function {
+            name: name.to_string(),
+            parameters: parameters.to_vec(),
+            body: body.clone(),
+        }));
+        self.assign(name, function.clone())?;
+        Ok(function)
+    }
+
+    // Runs a program's statements in order, each one's value going to `ans`, and returns the last
+    // one's. Top-level `def`s run once up front, before any statement, so a call may come before
+    // the definition of a function it reaches: `def f(n) = g(n); f(1); def g(n) = n`. They then
+    // run again in their place, so each redefinition still takes effect where it is written; only
+    // that run is reported as a side effect.
+    fn run_program(&mut self, statements: &[Rc<AstNode>]) -> Result<Value, CalcError> {
+        let side_effects = self.side_effects.take();
+        let defined = statements.iter()
+            .filter(|statement| matches!(***statement, AstNode::FunctionDef(..)))
+            .try_for_each(|definition| self.evaluate(definition.clone()).map(drop));
+        self.side_effects = side_effects;
+        defined?;
+        let mut result = Value::Int(Integer::default());
+        for statement in statements {
+            result = self.evaluate(statement.clone())?;
+            record_answer(self.environment, &self.constants, &result);
+        }
+        Ok(result)
+    }
+
+    // Operators nest to any depth without recursing. Calls, blocks, `let`s and chains still recurse
+    // once each, to run in their own scope, and count against `MAX_EVAL_DEPTH`.
+    fn evaluate(&mut self, node: Rc<AstNode>) -> Result<Value, CalcError> {
+        if self.depth >= MAX_EVAL_DEPTH {
+            return Err(CalcError::RecursionLimit);
+        }
+        self.depth += 1;
+        let result = self.run(node);
+        self.depth -= 1;
+        result
+    }
+
+    // The work of each frame lives in `visit` and `finish`, so the frames of a call, block or `let`
+    // reentering here stay small.
+    fn run(&mut self, node: Rc<AstNode>) -> Result<Value, CalcError> {
+        let mut frames = vec![Frame::Visit(node)];
+        let mut values: Vec<Value> = Vec::new();
+        while let Some(frame) = frames.pop() {
+            let result = match frame {
+                Frame::Visit(node) => self.step().and_then(|()| self.visit(node, &mut frames, &mut values)),
+                Frame::Truth => truth(values.pop().unwrap()).map(|value| values.push(Value::from_bool(value))),
+                Frame::Apply(node) => self.finish(node, &mut frames, &mut values),
+                Frame::Locate(_) => Ok(()),
+                Frame::Remember(node, effects) => {
+                    self.remember(node, effects, values.last().unwrap());
+                    Ok(())
+                },
+            };
+            // Every `Locate` still on the stack belongs to a node whose evaluation this error
+            // interrupts; the innermost one's position is the most precise.
+            if let Err(error) = result {
+                let position = frames.iter().rev().find_map(|frame| match frame {
+                    Frame::Locate(position) => Some(*position),
+                    _ => None,
+                });
+                return Err(match position {
+                    Some(position) => error.at(position),
+                    None => error,
+                });
+            }
+        }
+        Ok(values.pop().unwrap())
+    }
+
+    // With `EvalOptions::memoize`, a node already evaluated comes straight from the memo. Any other
+    // node is remembered once its value is known, if evaluating it had no effects.
+    fn recall(&self, node: &Rc<AstNode>, frames: &mut Vec<Frame>) -> Option<Value> {
+        if !self.options.memoize {
+            return None;
+        }
+        let memo = self.memo.borrow();
+        if let Some((_, value)) = memo.results.get(&Rc::as_ptr(node)) {
+            return Some(value.clone());
+        }
+        frames.push(Frame::Remember(node.clone(), memo.effects));
+        None
+    }
+
+    fn remember(&self, node: Rc<AstNode>, effects: usize, value: &Value) {
+        let mut memo = self.memo.borrow_mut();
+        if memo.effects == effects {
+            memo.results.insert(Rc::as_ptr(&node), (node, value.clone()));
+        }
+    }
+
+    // Schedules a node's operands ahead of its `Apply`, or pushes its value if it has none to wait
+    // for.
+    fn visit(&mut self, node: Rc<AstNode>, frames: &mut Vec<Frame>, values: &mut Vec<Value>) -> Result<(), CalcError> {
+        if let Some(value) = self.recall(&node, frames) {
+            values.push(value);
+            return Ok(());
+        }
+        match *node {
+            AstNode::Add(ref left, ref right)
+            | AstNode::Subtract(ref left, ref right)
+            | AstNode::Multiply(ref left, ref right)
+            | AstNode::Divide(ref left, ref right)
+            | AstNode::FloorDivide(ref left, ref right)
+            | AstNode::Modulo(ref left, ref right)
+            | AstNode::Equal(ref left, ref right)
+            | AstNode::NotEqual(ref left, ref right)
+            | AstNode::Less(ref left, ref right)
+            | AstNode::LessEqual(ref left, ref right)
+            | AstNode::Greater(ref left, ref right)
+            | AstNode::GreaterEqual(ref left, ref right)
+            | AstNode::BitAnd(ref left, ref right)
+            | AstNode::BitOr(ref left, ref right)
+            | AstNode::ShiftLeft(ref left, ref right)
+            | AstNode::ShiftRight(ref left, ref right)
+            | AstNode::Power(ref left, ref right) => {
+                let (left, right) = (left.clone(), right.clone());
+                frames.push(Frame::Apply(node));
+                frames.push(Frame::Visit(right));
+                frames.push(Frame::Visit(left));
+            },
+            // Only the left operand is scheduled; Apply decides whether the right one runs.
+            AstNode::And(ref left, _)
+            | AstNode::Or(ref left, _)
+            | AstNode::Not(ref left)
+            | AstNode::Negate(ref left)
+            | AstNode::Factorial(ref left)
+            | AstNode::Percent(ref left)
+            | AstNode::Conditional(ref left, _, _) => {
+                let left = left.clone();
+                frames.push(Frame::Apply(node));
+                frames.push(Frame::Visit(left));
+            },
+            AstNode::Assign(ref left, ref right) | AstNode::AssignReturnOld(ref left, ref right) => {
+                assign_target(left)?;
+                let right = right.clone();
+                frames.push(Frame::Apply(node));
+                frames.push(Frame::Visit(right));
+            },
+            AstNode::ConstAssign(_, ref value) => {
+                let value = value.clone();
+                frames.push(Frame::Apply(node));
+                frames.push(Frame::Visit(value));
+            },
+            AstNode::Call(_, ref arguments) => {
+                let arguments = arguments.clone();
+                frames.push(Frame::Apply(node));
+                for argument in arguments.into_iter().rev() {
+                    frames.push(Frame::Visit(argument));
+                }
+            },
+            // Like calls, blocks, lets and chains recurse once each rather than per node.
+            AstNode::Block(_) | AstNode::Let(..) | AstNode::Chain(_) => values.push(self.scoped(&node)?),
+            // The inner node's value passes through the marker untouched.
+            AstNode::Located(position, ref inner) => {
+                let inner = inner.clone();
+                frames.push(Frame::Locate(position));
+                frames.push(Frame::Visit(inner));
+            },
+            AstNode::IntLiteral(ref value) => values.push(Value::Int(value.clone())),
+            AstNode::FloatLiteral(value) => values.push(Value::Float(value)),
+            AstNode::Identifier(ref identifier) => values.push(self.read(identifier)?),
+            AstNode::FunctionDef(ref name, ref parameters, ref body) =>
+                values.push(self.define_function(name, parameters, body)?),
+        }
+        Ok(())
+    }
+
+    fn scoped(&mut self, node: &AstNode) -> Result<Value, CalcError> {
+        match node {
+            AstNode::Block(statements) =>
+                self.block(&[], statements, |evaluator, statement| evaluator.evaluate(statement)),
+            AstNode::Let(bindings, body) =>
+                self.block(bindings, std::slice::from_ref(body), |evaluator, node| evaluator.evaluate(node)),
+            AstNode::Chain(comparisons) =>
+                self.chain(comparisons, |evaluator, operand| evaluator.evaluate(operand)),
+            _ => unreachable!("not a scope: {}", node),
+        }
+    }
+
+    // Applies a node to its operands' values on top of the stack, or schedules what it still
+    // needs: the right operand of `&&` and `||`, or the chosen branch of a conditional.
+    fn finish(&mut self, node: Rc<AstNode>, frames: &mut Vec<Frame>, values: &mut Vec<Value>) -> Result<(), CalcError> {
+        let value = match *node {
+            AstNode::Add(..) | AstNode::Subtract(..) | AstNode::Multiply(..) | AstNode::Divide(..)
+            | AstNode::FloorDivide(..) | AstNode::Modulo(..) | AstNode::Equal(..) | AstNode::NotEqual(..) | AstNode::Less(..) | AstNode::LessEqual(..)
+            | AstNode::Greater(..) | AstNode::GreaterEqual(..) | AstNode::BitAnd(..) | AstNode::BitOr(..)
+            | AstNode::ShiftLeft(..) | AstNode::ShiftRight(..) | AstNode::Power(..) => {
+                let right = values.pop().unwrap();
+                let left = values.pop().unwrap();
+                self.apply(&node, left, right)?
+            },
+            AstNode::And(_, ref right) | AstNode::Or(_, ref right) => {
+                let left = truth(values.pop().unwrap())?;
+                if left == matches!(*node, AstNode::Or(..)) {
+                    Value::from_bool(left)
+                } else {
+                    frames.push(Frame::Truth);
+                    frames.push(Frame::Visit(right.clone()));
+                    return Ok(());
+                }
+            },
+            AstNode::Not(_) | AstNode::Negate(_) | AstNode::Factorial(_) | AstNode::Percent(_) => {
+                let operand = values.pop().unwrap();
+                let traced = self.tracer.is_some().then(|| vec![operand.clone()]);
+                let result = apply_unary(&node, operand)?;
+                self.trace(&node, traced, &result);
+                result
+            },
+            // The chosen branch's value lands on the stack as this node's result.
+            AstNode::Conditional(_, ref then_branch, ref else_branch) => {
+                let branch = if truth(values.pop().unwrap())? { then_branch } else { else_branch };
+                frames.push(Frame::Visit(branch.clone()));
+                return Ok(());
+            },
+            AstNode::Assign(ref left, _) => {
+                let right = values.pop().unwrap();
+                self.assign(assign_target(left)?, right.clone())?;
+                right
+            },
+            AstNode::AssignReturnOld(ref left, _) => self.exchange(assign_target(left)?, values.pop().unwrap())?,
+            AstNode::ConstAssign(ref name, _) => self.declare_constant(name, values.pop().unwrap())?,
+            AstNode::Call(ref name, ref arguments) => {
+                let arguments = values.split_off(values.len() - arguments.len());
+                self.call(name, arguments, |evaluator, body| evaluator.evaluate(body))?
+            },
+            AstNode::IntLiteral(_) | AstNode::FloatLiteral(_) | AstNode::Identifier(_)
+            | AstNode::FunctionDef(..) | AstNode::Block(_) | AstNode::Let(..) | AstNode::Chain(_) | AstNode::Located(..) =>
+                unreachable!(),
+        };
+        values.push(value);
+        Ok(())
+    }
+}
+
+// The parser only builds assignments to identifiers, but hand-built trees may not.
+fn assign_target(node: &AstNode) -> Result<&str, CalcError> {
+    match node {
+        AstNode::Identifier(identifier) => Ok(identifier),
+        _ => Err(CalcError::InvalidAssignTarget { position: None }),
+    }
+}
+
+pub(crate) fn evaluate_expression(node: Rc<AstNode>, environment: &mut HashMap<String, Value>) -> Result<Value, CalcError> {
+    Evaluator::new(environment).evaluate(node)
+}
+
+pub(crate) fn evaluate_program(statements: &[Rc<AstNode>], environment: &mut HashMap<String, Value>) -> Result<Value, CalcError> {
+    Evaluator::new(environment).run_program(statements)
+}
+
+pub(crate) fn evaluate_with_output(node: Rc<AstNode>, environment: &mut HashMap<String, Value>, output: &mut dyn Write) -> Result<Value, CalcError> {
+    Evaluator::new(environment).with_output(output).evaluate(node)
+}
+
+// Evaluation for a `Calculator`, whose constants outlive each input just like its variables.
+pub(crate) fn evaluate_with_constants(
+    node: Rc<AstNode>,
+    environment: &mut HashMap<String, Value>,
+    constants: &mut HashSet<String>,
+    options: EvalOptions,
+) -> Result<Value, CalcError> {
+    let mut evaluator = Evaluator::new(environment);
+    evaluator.constants = std::mem::take(constants);
+    evaluator.options = options;
+    let result = evaluator.evaluate(node);
+    *constants = evaluator.constants;
+    result
+}
+
+pub(crate) fn evaluate_with_options(node: Rc<AstNode>, environment: &mut HashMap<String, Value>, options: EvalOptions) -> Result<Value, CalcError> {
+    let mut evaluator = Evaluator::new(environment);
+    evaluator.options = options;
+    evaluator.evaluate(node)
+}
+
+pub(crate) fn evaluate_with_host_functions(node: Rc<AstNode>, environment: &mut HashMap<String, Value>, functions: &HostFunctions) -> Result<Value, CalcError> {
+    Evaluator::new(environment).with_host_functions(functions).evaluate(node)
+}
+
+pub(crate) fn evaluate_with_source(node: Rc<AstNode>, variables: &dyn VariableSource) -> Result<Value, CalcError> {
+    let mut environment = HashMap::new();
+    let mut evaluator = Evaluator::new(&mut environment);
+    evaluator.variables = Some(variables);
+    evaluator.evaluate(node)
+}
+
+pub fn evaluate(node: Rc<AstNode>) -> Result<Value, CalcError> {
+    let mut environment = HashMap::new();
+    evaluate_expression(node, &mut environment)
+}
+
+// Like `evaluate`, but tells `tracer` of every operator applied, operands first: for `1 + 2 * 3`,
+// the multiplication and then the addition.
+pub fn evaluate_traced(node: Rc<AstNode>, tracer: &mut dyn FnMut(EvalEvent)) -> Result<Value, CalcError> {
+    let mut environment = HashMap::new();
+    let mut evaluator = Evaluator::new(&mut environment);
+    evaluator.tracer = Some(tracer);
+    evaluator.evaluate(node)
+}
+
+pub fn evaluate_with_mode(node: Rc<AstNode>, mode: ArithmeticMode) -> Result<Value, CalcError> {
+    let mut environment = HashMap::new();
+    let mut evaluator = Evaluator::new(&mut environment);
+    evaluator.options.mode = mode;
+    evaluator.evaluate(node)
+}
+
+// Pending work for an evaluation: either a node still to be evaluated, or a node whose operands
+// are already on the value stack and only need combining.
+enum Frame {
+    Visit(Rc<AstNode>),
+    Apply(Rc<AstNode>),
+    // Checks that the value on top of the stack is a boolean, for the right operand of && and ||.
+    Truth,
+    // Left when a `Located` node's operand finishes; an error raised before then is attributed to
+    // the position.
+    Locate(usize),
+    // Memoizes the value on top of the stack for the node, if the memo's effect count is still the
+    // one recorded here.
+    Remember(Rc<AstNode>, usize),
+}
+
+// `evaluate` under its older name: every evaluation now runs without recursing per node.
+pub fn evaluate_iterative(node: Rc<AstNode>) -> Result<Value, CalcError> {
+    evaluate(node)
+}
+
+// Total evaluation for arbitrary, possibly hand-built trees: every failure, including arithmetic
+// overflow and division by zero, comes back as an error. Operators nest to any depth; calls,
+// blocks, `let`s and chains nested too deeply fail with `RecursionLimit`.
+pub fn try_evaluate(node: Rc<AstNode>, environment: &mut HashMap<String, Value>) -> Result<Value, CalcError> {
+    evaluate_expression(node, environment)
+}
+
+// Runs the program against a copy of the environment and reports every assignment it would
+// make, in order, leaving the caller's environment untouched. The program runs just as
+// `interpret_program` would run it, so each statement's value goes to `ans`, which is not
+// reported.
+pub fn dry_run(program: &str, environment: &HashMap<String, Value>) -> Result<Vec<SideEffect>, CalcError> {
+    let statements: Vec<Rc<AstNode>> = parse_program(program)?.into_iter().map(Rc::new).collect();
+    let mut scratch = environment.clone();
+    let mut side_effects = Vec::new();
+    let mut evaluator = Evaluator::new(&mut scratch);
+    evaluator.side_effects = Some(&mut side_effects);
+    evaluator.run_program(&statements)?;
+    Ok(side_effects)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn int(value: i32) -> Rc<AstNode> {
+        Rc::new(AstNode::IntLiteral(value.into()))
+    }
+
+    #[test]
+    fn test_int_literal() {
+        let node = int(1);
+        assert_eq!(evaluate(node), Ok(Value::Int(1.into())));
+    }
+
+    #[test]
+    fn test_add() {
+        let node = Rc::new(AstNode::Add(int(1), int(2)));
+        assert_eq!(evaluate(node), Ok(Value::Int(3.into())));
+    }
+
+    #[test]
+    fn test_subtract() {
+        let node = Rc::new(AstNode::Subtract(int(1), int(2)));
+        assert_eq!(evaluate(node), Ok(Value::Int((-1).into())));
+    }
+
+    #[test]
+    fn test_multiply() {
+        let node = Rc::new(AstNode::Multiply(int(2), int(3)));
+        assert_eq!(evaluate(node), Ok(Value::Int(6.into())));
+    }
+
+    #[test]
+    fn test_divide() {
+        let node = Rc::new(AstNode::Divide(int(6), int(2)));
+        assert_eq!(evaluate(node), Ok(Value::Int(3.into())));
+    }
+
+    #[test]
+    fn test_floor_divide_rounds_toward_negative_infinity() {
+        let floor_divide = |left, right| Rc::new(AstNode::FloorDivide(int(left), int(right)));
+        assert_eq!(evaluate(floor_divide(7, 2)), Ok(Value::Int(3.into())));
+        assert_eq!(evaluate(floor_divide(-7, 2)), Ok(Value::Int((-4).into())));
+        assert_eq!(evaluate(floor_divide(7, -2)), Ok(Value::Int((-4).into())));
+        assert_eq!(evaluate(floor_divide(-7, -2)), Ok(Value::Int(3.into())));
+        assert_eq!(evaluate(floor_divide(-8, 2)), Ok(Value::Int((-4).into())));
+        assert_eq!(evaluate_iterative(floor_divide(-7, 2)), Ok(Value::Int((-4).into())));
+    }
+
+    #[test]
+    // (1 + 2) * (3 - 6) = -9
+    fn test_complex_expression() {
+        let node = Rc::new(AstNode::Multiply(
+            Rc::new(AstNode::Add(int(1), int(2))),
+            Rc::new(AstNode::Subtract(int(3), int(6)))
+        ));
+        assert_eq!(evaluate(node), Ok(Value::Int((-9).into())));
+    }
+
+    #[test]
+    fn test_evaluate_iterative() {
+        let node = Rc::new(AstNode::Multiply(
+            Rc::new(AstNode::Add(int(1), int(2))),
+            Rc::new(AstNode::Subtract(int(3), int(6)))
+        ));
+        assert_eq!(evaluate_iterative(node), Ok(Value::Int((-9).into())));
+    }
+
+    #[test]
+    fn test_try_evaluate_pathological_trees() {
+        let mut environment = HashMap::new();
+        let assign_to_literal = Rc::new(AstNode::Assign(int(1), int(2)));
+        assert_eq!(try_evaluate(assign_to_literal.clone(), &mut environment), Err(CalcError::InvalidAssignTarget { position: None }));
+        assert_eq!(evaluate(assign_to_literal), Err(CalcError::InvalidAssignTarget { position: None }));
+        let missing = Rc::new(AstNode::Identifier("missing".to_string()));
+        assert_eq!(try_evaluate(missing, &mut environment), Err(CalcError::UndefinedVariable("missing".to_string())));
+        assert_eq!(try_evaluate(Rc::new(AstNode::Divide(int(1), int(0))), &mut environment), Err(CalcError::DivisionByZero));
+        assert_eq!(try_evaluate(Rc::new(AstNode::FloorDivide(int(1), int(0))), &mut environment), Err(CalcError::DivisionByZero));
+        assert_eq!(try_evaluate(Rc::new(AstNode::Call("f".to_string(), vec![])), &mut environment), Err(CalcError::UndefinedVariable("f".to_string())));
+        // def f() = f(), then f()
+        let call = Rc::new(AstNode::Call("f".to_string(), vec![]));
+        try_evaluate(Rc::new(AstNode::FunctionDef("f".to_string(), vec![], call.clone())), &mut environment).unwrap();
+        assert_eq!(try_evaluate(call.clone(), &mut environment), Err(CalcError::RecursionLimit));
+        assert_eq!(evaluate_expression(call, &mut environment), Err(CalcError::RecursionLimit));
+        // Located nodes nest without recursing, and errors take the innermost position.
+        let mut located = int(1);
+        let mut failing = Rc::new(AstNode::Divide(int(1), int(0)));
+        for position in 0..200_000 {
+            located = Rc::new(AstNode::Located(position, located));
+            failing = Rc::new(AstNode::Located(position, failing));
+        }
+        assert_eq!(try_evaluate(located.clone(), &mut environment), Ok(Value::Int(1.into())));
+        assert_eq!(try_evaluate(failing.clone(), &mut environment), Err(CalcError::Located { error: Box::new(CalcError::DivisionByZero), position: 0 }));
+        for mut node in [located, failing] {
+            while let Ok(AstNode::Located(_, inner)) = Rc::try_unwrap(node) {
+                node = inner;
+            }
+        }
+    }
+
+    #[test]
+    #[cfg(not(feature = "bignum"))]
+    fn test_arithmetic_modes() {
+        let max = Rc::new(AstNode::IntLiteral(Integer::MAX));
+        let min = Rc::new(AstNode::IntLiteral(Integer::MIN));
+        assert_eq!(try_evaluate(Rc::new(AstNode::Add(max.clone(), int(1))), &mut HashMap::new()), Err(CalcError::Overflow));
+        assert_eq!(try_evaluate(Rc::new(AstNode::Divide(min.clone(), int(-1))), &mut HashMap::new()), Err(CalcError::Overflow));
+        let evaluate_all = |node: Rc<AstNode>| [ArithmeticMode::Checked, ArithmeticMode::Wrapping, ArithmeticMode::Saturating]
+            .map(|mode| evaluate_with_mode(node.clone(), mode));
+        assert_eq!(evaluate_all(Rc::new(AstNode::Add(max.clone(), int(1)))),
+            [Err(CalcError::Overflow), Ok(Value::Int(Integer::MIN)), Ok(Value::Int(Integer::MAX))]);
+        assert_eq!(evaluate_all(Rc::new(AstNode::Subtract(min.clone(), int(1)))),
+            [Err(CalcError::Overflow), Ok(Value::Int(Integer::MAX)), Ok(Value::Int(Integer::MIN))]);
+        assert_eq!(evaluate_all(Rc::new(AstNode::Multiply(max.clone(), int(2)))),
+            [Err(CalcError::Overflow), Ok(Value::Int((-2).into())), Ok(Value::Int(Integer::MAX))]);
+        assert_eq!(evaluate_all(Rc::new(AstNode::Add(Rc::new(AstNode::IntLiteral(Integer::MAX - 1)), int(1)))), [Ok(Value::Int(Integer::MAX)), Ok(Value::Int(Integer::MAX)), Ok(Value::Int(Integer::MAX))]);
+        // Division keeps failing in every mode.
+        assert_eq!(evaluate_with_mode(Rc::new(AstNode::Divide(min, int(-1))), ArithmeticMode::Saturating), Err(CalcError::Overflow));
+    }
+
+    #[test]
+    fn test_evaluate_traced() {
+        let event = |operation: &str, operands: [i32; 2], result: i32| EvalEvent {
+            operation: operation.to_string(),
+            operands: operands.map(|operand| Value::Int(operand.into())).to_vec(),
+            result: Value::Int(result.into()),
+        };
+        let mut events = Vec::new();
+        let value = evaluate_traced(Rc::new(crate::parse_str("1 + 2 * 3").unwrap()), &mut |event| events.push(event));
+        assert_eq!(value, Ok(Value::Int(7.into())));
+        assert_eq!(events, vec![event("Multiply", [2, 3], 6), event("Add", [1, 6], 7)]);
+
+        let mut events = Vec::new();
+        let value = evaluate_traced(Rc::new(crate::parse_str("{ def f(n) = n * 2; f(3)! < 1 + 1 }").unwrap()), &mut |event| events.push(event));
+        assert_eq!(value, Ok(Value::Bool(false)));
+        let operations: Vec<&str> = events.iter().map(|event| event.operation.as_str()).collect();
+        assert_eq!(operations, ["Multiply", "Factorial", "Add", "Less"]);
+        assert_eq!(events[1].result, Value::Int(720.into()));
+    }
+
+    #[test]
+    fn test_memoize_shared_subtrees() {
+        let operations = |node: &Rc<AstNode>, environment: &mut HashMap<String, Value>, memoize| {
+            let mut operations = Vec::new();
+            let mut tracer = |event: EvalEvent| operations.push(event.operation);
+            let mut evaluator = Evaluator::new(environment);
+            evaluator.options.memoize = memoize;
+            evaluator.tracer = Some(&mut tracer);
+            let value = evaluator.evaluate(node.clone());
+            (value, operations)
+        };
+        let shared = Rc::new(AstNode::Multiply(int(6), int(7)));
+        let node = Rc::new(AstNode::Subtract(Rc::new(AstNode::Add(shared.clone(), shared.clone())), shared));
+        assert_eq!(operations(&node, &mut HashMap::new(), false), (Ok(Value::Int(42.into())), ["Multiply", "Multiply", "Add", "Multiply", "Subtract"].map(String::from).to_vec()));
+        assert_eq!(operations(&node, &mut HashMap::new(), true), (Ok(Value::Int(42.into())), ["Multiply", "Add", "Subtract"].map(String::from).to_vec()));
+
+        // Subtrees that read or assign are evaluated every time.
+        let mut environment = HashMap::from([("x".to_string(), Value::Int(1.into()))]);
+        let increment = Rc::new(AstNode::Assign(
+            Rc::new(AstNode::Identifier("x".to_string())),
+            Rc::new(AstNode::Add(Rc::new(AstNode::Identifier("x".to_string())), int(1))),
+        ));
+        let node = Rc::new(AstNode::Multiply(increment.clone(), increment));
+        assert_eq!(operations(&node, &mut environment, true), (Ok(Value::Int(6.into())), ["Add", "Add", "Multiply"].map(String::from).to_vec()));
+        assert_eq!(environment.get("x"), Some(&Value::Int(3.into())));
+    }
+
+    #[test]
+    // 1 + 1 + ... + 1 nested deeply enough that recursing per node would overflow the stack
+    fn test_evaluate_iterative_deep_expression() {
+        let depth: i32 = 1_000_000;
+        let mut node = int(1);
+        for _ in 1..depth {
+            node = Rc::new(AstNode::Add(node, int(1)));
+        }
+        assert_eq!(evaluate_iterative(node.clone()), Ok(Value::Int(depth.into())));
+        // Dropping the chain would recurse just as deeply, so unlink it one level at a time.
+        while let Ok(AstNode::Add(left, _)) = Rc::try_unwrap(node) {
+            node = left;
+        }
+    }
+
+    #[test]
+    // Test threads have 2 MiB stacks, so each of these would overflow without the depth limit.
+    fn test_evaluation_depth_limit() {
+        let mut environment = HashMap::new();
+        let deep_body = format!("def f(n) = n == 0 ? 0 : 1 + {}f(n - 1){}", "(1 + ".repeat(60), ")".repeat(60));
+        let f = |n: usize| Rc::new(crate::parse_str(&format!("f({})", n)).unwrap());
+        evaluate_expression(Rc::new(crate::parse_str(&deep_body).unwrap()), &mut environment).unwrap();
+        assert_eq!(evaluate_expression(f(1000), &mut environment), Err(CalcError::RecursionLimit));
+        assert_eq!(try_evaluate(f(1000), &mut environment), Err(CalcError::RecursionLimit));
+        assert_eq!(evaluate_expression(f(3), &mut environment), Ok(Value::Int(183.into())));
+        evaluate_expression(Rc::new(crate::parse_str("def f(n) = n == 0 ? 0 : 1 + (f(n - 1) * 1)").unwrap()), &mut environment).unwrap();
+        assert_eq!(evaluate_expression(f(1000), &mut environment), Err(CalcError::RecursionLimit));
+        let memoize = EvalOptions { memoize: true, ..EvalOptions::default() };
+        assert_eq!(evaluate_with_options(f(1000), &mut environment, memoize), Err(CalcError::RecursionLimit));
+        assert_eq!(evaluate_expression(f(250), &mut environment), Ok(Value::Int(250.into())));
+        assert_eq!(evaluate_with_options(f(250), &mut environment, memoize), Ok(Value::Int(250.into())));
+
+        // Only scopes count: operators nest as deeply as they like.
+        let mut node = int(1);
+        for _ in 0..100_000 {
+            node = Rc::new(AstNode::Add(node, int(1)));
+        }
+        assert_eq!(evaluate(node.clone()), Ok(Value::Int(100_001.into())));
+        while let Ok(AstNode::Add(left, _)) = Rc::try_unwrap(node) {
+            node = left;
+        }
+        let mut node = int(1);
+        for _ in 0..100_000 {
+            node = Rc::new(AstNode::Block(vec![node]));
+        }
+        assert_eq!(try_evaluate(node.clone(), &mut HashMap::new()), Err(CalcError::RecursionLimit));
+        while let Ok(AstNode::Block(mut statements)) = Rc::try_unwrap(node) {
+            node = statements.pop().unwrap();
+        }
+    }
+}
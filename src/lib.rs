@@ -0,0 +1,1297 @@
+#![cfg_attr(not(feature = "bignum"), allow(clippy::clone_on_copy))]
+
+use std::collections::{HashMap, HashSet};
+use std::io::{BufRead, Write};
+use std::rc::Rc;
+
+// The integer type used for literals, variables and arithmetic throughout the calculator.
+// The `bignum` feature swaps it for an arbitrary-precision integer, so nothing may rely on it
+// being `Copy` (hence the explicit clones).
+#[cfg(not(feature = "bignum"))]
+pub type Integer = i64;
+#[cfg(feature = "bignum")]
+pub type Integer = num_bigint::BigInt;
+
+mod ast;
+mod error;
+mod evaluator;
+mod parser;
+mod rpn;
+mod simplify;
+mod tokenizer;
+mod value;
+
+pub use ast::{
+    ast_hash, collect_identifiers, max_depth, node_count, pretty_print_ast, strip_parens,
+    undefined_references, walk, AstNode, Visitor,
+};
+#[cfg(feature = "serde")]
+pub use ast::{ast_from_json, ast_to_json};
+pub use error::CalcError;
+pub use evaluator::{
+    dry_run, evaluate, evaluate_iterative, evaluate_traced, evaluate_with_mode, try_evaluate,
+    ArithmeticMode, EvalEvent, EvalOptions, HostFunction, HostFunctions, SideEffect, VariableSource,
+    DEFAULT_EPSILON,
+};
+pub use parser::{
+    lint, operator_table, parse, parse_all_errors, parse_located, parse_str, parse_tokens,
+    parse_with_max_depth, Associativity, OperatorInfo, ParseCache, Warning, DEFAULT_MAX_DEPTH,
+};
+pub use rpn::{eval_rpn, to_rpn, RpnToken};
+pub use simplify::simplify;
+pub use tokenizer::{
+    tokenize_all, tokenize_with_options, tokenize_with_positions, LocaleOptions, Op, Token,
+    Tokenizer, TokenizerImpl, TokenizerOptions,
+};
+pub use value::{Function, Value};
+
+use ast::{contains_assignment, describe_node, reads_variables};
+use evaluator::{
+    evaluate_expression, evaluate_program, evaluate_with_constants, evaluate_with_host_functions,
+    evaluate_with_options, evaluate_with_output, evaluate_with_source, expect_integer, record_answer,
+};
+use parser::{parse_program, parse_with_options};
+
+pub fn interpret(input: &str) -> Result<Value, CalcError> {
+    let ast = parse_str(input)?;
+    evaluate(Rc::new(ast))
+}
+
+// Explains what `input` computes in plain English, without evaluating it: `(1 + 2) * 3` is
+// "Multiply the result of (add 1 and 2) by 3".
+pub fn describe(input: &str) -> Result<String, CalcError> {
+    Ok(describe_node(&parse_str(input)?))
+}
+
+pub fn interpret_with_environment(input: &str, environment: &mut HashMap<String, Value>) -> Result<Value, CalcError> {
+    let ast = parse_str(input)?;
+    evaluate_expression(Rc::new(ast), environment)
+}
+
+// For one-off evaluations: the variables come as name-value pairs, copied into an environment that
+// the expression may change but that is dropped afterwards.
+pub fn interpret_with_vars(input: &str, vars: &[(&str, Value)]) -> Result<Value, CalcError> {
+    let mut environment = vars.iter().map(|(name, value)| (name.to_string(), value.clone())).collect();
+    interpret_with_environment(input, &mut environment)
+}
+
+// Like `interpret_with_environment`, but runtime errors carry the position in `input` of the
+// read, call or operator that raised them, as `CalcError::Located`.
+pub fn interpret_located(input: &str, environment: &mut HashMap<String, Value>) -> Result<Value, CalcError> {
+    let ast = parse_located(input)?;
+    evaluate_expression(Rc::new(ast), environment)
+}
+
+// Like `interpret_with_environment`, but `print` writes to `output` instead of standard output.
+pub fn interpret_with_output(input: &str, environment: &mut HashMap<String, Value>, output: &mut dyn Write) -> Result<Value, CalcError> {
+    let ast = parse_str(input)?;
+    evaluate_with_output(Rc::new(ast), environment, output)
+}
+
+// Like `interpret_with_environment`, but calls to names that are not bound in the environment
+// go to the given host functions.
+pub fn interpret_with_host_functions(input: &str, environment: &mut HashMap<String, Value>, functions: &HostFunctions) -> Result<Value, CalcError> {
+    let ast = parse_str(input)?;
+    evaluate_with_host_functions(Rc::new(ast), environment, functions)
+}
+
+// Like `interpret_with_environment`, with the leniency the options ask for.
+pub fn interpret_with_options(input: &str, environment: &mut HashMap<String, Value>, options: EvalOptions) -> Result<Value, CalcError> {
+    let ast = parse_with_options(input, options)?;
+    evaluate_with_options(Rc::new(ast), environment, options)
+}
+
+// Runs a `;`-separated program in a fresh environment and returns the last statement's value
+// together with the variables it leaves behind, `ans` among them.
+pub fn interpret_program(input: &str) -> Result<(Value, HashMap<String, Value>), CalcError> {
+    let mut environment = HashMap::new();
+    let value = interpret_program_with_environment(input, &mut environment, false)?;
+    Ok((value, environment))
+}
+
+// A copy of the environment to restore later; function values share their bodies with the
+// original rather than copying them.
+pub fn snapshot(environment: &HashMap<String, Value>) -> HashMap<String, Value> {
+    environment.clone()
+}
+
+// Runs a `;`-separated program against `environment`, each statement's value going to `ans`;
+// top-level `def`s run up front as well, so a call may come before its definition. Statements
+// before a failing one keep their effects, unless the run is `transactional`: then a failure
+// restores the environment to how it was before the program started.
+pub fn interpret_program_with_environment(input: &str, environment: &mut HashMap<String, Value>, transactional: bool) -> Result<Value, CalcError> {
+    let statements: Vec<Rc<AstNode>> = parse_program(input)?.into_iter().map(Rc::new).collect();
+    let saved = if transactional { Some(snapshot(environment)) } else { None };
+    let result = evaluate_program(&statements, environment);
+    if let (Err(_), Some(saved)) = (&result, saved) {
+        *environment = saved;
+    }
+    result
+}
+
+// Streams a script line by line, running each line's statements against `environment`, and
+// returns the value of the last one. Blank lines and lines starting with `#` are skipped. As in
+// a program, each statement's value goes to `ans`.
+pub fn interpret_reader<R: BufRead>(reader: R, environment: &mut HashMap<String, Value>) -> Result<Value, CalcError> {
+    let mut constants = HashSet::new();
+    let mut result = None;
+    for line in reader.lines() {
+        let line = line.map_err(|error| CalcError::Io(error.to_string()))?;
+        let statement = line.trim();
+        if statement.is_empty() || statement.starts_with('#') {
+            continue;
+        }
+        for statement in parse_program(statement)? {
+            let value = evaluate_with_constants(Rc::new(statement), environment, &mut constants, EvalOptions::default())?;
+            record_answer(environment, &constants, &value);
+            result = Some(value);
+        }
+    }
+    result.ok_or(CalcError::EmptyInput)
+}
+
+// Runs pasted multi-line text in a fresh environment, one line after another, and returns the
+// last statement's value. Newlines end statements just as `;` does, and `;` can still separate
+// several statements within a line: `a = 1; b = 2\nc = a + b` is three statements. Nothing
+// continues across a line break, so an expression cannot be split over two lines.
+pub fn interpret_lines(input: &str) -> Result<Value, CalcError> {
+    interpret_reader(input.as_bytes(), &mut HashMap::new())
+}
+
+// For untrusted input: reads `vars` but may not change them or add any, so an expression that
+// assigns, declares a constant or defines a function fails with `AssignmentForbidden` before it
+// runs.
+pub fn interpret_readonly(input: &str, vars: &HashMap<String, Value>) -> Result<Value, CalcError> {
+    let ast = parse_str(input)?;
+    if contains_assignment(&ast) {
+        return Err(CalcError::AssignmentForbidden);
+    }
+    evaluate_expression(Rc::new(ast), &mut vars.clone())
+}
+
+// For constants from configuration and the like: an expression that reads or assigns any variable
+// fails with `NotConstant` before it runs, so no variable state is ever involved. The result must
+// be an integer.
+pub fn eval_const(input: &str) -> Result<Integer, CalcError> {
+    let ast = parse_str(input)?;
+    if reads_variables(&ast) || contains_assignment(&ast) {
+        return Err(CalcError::NotConstant);
+    }
+    expect_integer(evaluate(Rc::new(ast))?)
+}
+
+// Evaluates against variables the host keeps in its own form, read through `variables`. Like
+// `interpret_readonly`, an expression that would assign fails with `AssignmentForbidden`; `let`
+// bindings are still allowed, since they live only as long as their body.
+pub fn interpret_with_source(input: &str, variables: &dyn VariableSource) -> Result<Value, CalcError> {
+    let ast = parse_str(input)?;
+    if contains_assignment(&ast) {
+        return Err(CalcError::AssignmentForbidden);
+    }
+    evaluate_with_source(Rc::new(ast), variables)
+}
+
+// Runs the expressions one after another in a shared environment, each reading the previous one's
+// value as `ans`, and returns the last one's value.
+pub fn interpret_expressions(inputs: Vec<&str>) -> Result<Value, CalcError> {
+    Ok(interpret_all(&inputs)?.pop().unwrap_or(Value::Int(Integer::default())))
+}
+
+// Like `interpret_expressions`, but keeps every expression's value, in order. Constants declared
+// by one input stay constant for the rest, as in a `Calculator`.
+pub fn interpret_all(inputs: &[&str]) -> Result<Vec<Value>, CalcError> {
+    let mut environment = HashMap::new();
+    let mut constants = HashSet::new();
+    let mut values = Vec::with_capacity(inputs.len());
+    for input in inputs {
+        let ast = parse_str(input)?;
+        let value = evaluate_with_constants(Rc::new(ast), &mut environment, &mut constants, EvalOptions::default())?;
+        record_answer(&mut environment, &constants, &value);
+        values.push(value);
+    }
+    Ok(values)
+}
+
+#[derive(Default)]
+pub struct Calculator {
+    environment: HashMap<String, Value>,
+    constants: HashSet<String>,
+    options: EvalOptions,
+}
+
+impl Calculator {
+    pub fn new() -> Calculator {
+        Calculator::default()
+    }
+
+    // The settings below combine, as in `Calculator::new().with_max_variables(8).with_epsilon(0.0)`.
+
+    // For untrusted input: an assignment that would leave more than `limit` variables fails with
+    // `TooManyVariables` instead. Variables added with `load` are not checked.
+    pub fn with_max_variables(mut self, limit: usize) -> Calculator {
+        self.options.max_variables = Some(limit);
+        self
+    }
+
+    // `+`, `-` and `*` wrap or saturate on overflow instead of failing.
+    pub fn with_arithmetic_mode(mut self, mode: ArithmeticMode) -> Calculator {
+        self.options.mode = mode;
+        self
+    }
+
+    // Float `==` and `!=` use `epsilon` instead of `DEFAULT_EPSILON`; zero makes them exact.
+    pub fn with_epsilon(mut self, epsilon: f64) -> Calculator {
+        self.options.epsilon = epsilon;
+        self
+    }
+
+    // Constants declared with `const` stay constant for the calculator's whole lifetime. A
+    // successful input's value becomes `ans` for the next.
+    pub fn interpret(&mut self, input: &str) -> Result<Value, CalcError> {
+        let ast = parse_str(input)?;
+        let value = evaluate_with_constants(Rc::new(ast), &mut self.environment, &mut self.constants, self.options)?;
+        record_answer(&mut self.environment, &self.constants, &value);
+        Ok(value)
+    }
+
+    // Bulk-inserts variables, e.g. when seeding from a config file; existing variables are
+    // overwritten, but a constant among the names fails with `ReassignConst` and nothing is loaded.
+    pub fn load(&mut self, vars: impl IntoIterator<Item = (String, Integer)>) -> Result<(), CalcError> {
+        let vars: Vec<(String, Integer)> = vars.into_iter().collect();
+        if let Some((name, _)) = vars.iter().find(|(name, _)| self.constants.contains(name)) {
+            return Err(CalcError::ReassignConst(name.clone()));
+        }
+        self.environment.extend(vars.into_iter().map(|(name, value)| (name, Value::Int(value))));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_interpret_long_sum() {
+        let sum = vec!["1"; 1000].join(" + ");
+        assert_eq!(interpret(&sum), Ok(Value::Int(1000.into())));
+        assert_eq!(Calculator::new().interpret(&sum), Ok(Value::Int(1000.into())));
+        assert_eq!(interpret_expressions(vec!["def f(n) = n == 0 ? 0 : 1 + f(n - 1)", "f(200)"]), Ok(Value::Int(200.into())));
+    }
+
+    #[test]
+    fn test_interpret() {
+        assert_eq!(interpret("(1 + 2) * (3 - 6)"), Ok(Value::Int((-9).into())));
+        assert_eq!(interpret("(1 + 3) * (4 * 2)"), Ok(Value::Int(32.into())));
+        assert_eq!(interpret("x = (1 + 3) * (4 * 2)"), Ok(Value::Int(32.into())));
+    }
+
+    #[test]
+    fn test_leading_zeros_are_decimal() {
+        assert_eq!(interpret("0123"), Ok(Value::Int(123.into())));
+        assert_eq!(interpret("0o17"), Ok(Value::Int(15.into())));
+        assert_eq!(interpret("010 + 0o10"), Ok(Value::Int(18.into())));
+        assert_eq!(interpret("0o9"), Err(CalcError::MalformedNumber { text: "0o9".to_string(), position: Some(0) }));
+    }
+
+    #[test]
+    fn test_unary_plus() {
+        assert_eq!(interpret("+5"), Ok(Value::Int(5.into())));
+        assert_eq!(interpret("3 + +4"), Ok(Value::Int(7.into())));
+        assert_eq!(interpret("2 * +(1 + 2)"), Ok(Value::Int(6.into())));
+        assert_eq!(interpret("+2 * +2.5"), Ok(Value::Float(5.0)));
+        assert_eq!(interpret("!+(1 > 2)"), Ok(Value::Bool(true)));
+        assert_eq!(interpret("50% + 3"), Ok(Value::Float(3.5)));
+        assert_eq!(parse_str("+x"), parse_str("x"));
+        assert_eq!(interpret("+"), Err(CalcError::ExpectedOperand { operator: Op::Plus, position: Some(0) }));
+        assert_eq!(interpret("1 + + "), Err(CalcError::ExpectedOperand { operator: Op::Plus, position: Some(4) }));
+    }
+
+    #[test]
+    fn test_unary_minus() {
+        assert_eq!(interpret("-5"), Ok(Value::Int((-5).into())));
+        assert_eq!(interpret("3 + -4"), Ok(Value::Int((-1).into())));
+        assert_eq!(interpret("3 - -4"), Ok(Value::Int(7.into())));
+        assert_eq!(interpret("--5"), Ok(Value::Int(5.into())));
+        assert_eq!(interpret("-2.5 * -2"), Ok(Value::Float(5.0)));
+        assert_eq!(interpret("sign(-3)"), Ok(Value::Int((-1).into())));
+        assert_eq!(interpret("-7 // 2"), Ok(Value::Int((-4).into())));
+        assert_eq!(interpret("-3!"), Ok(Value::Int((-6).into())));
+        assert_eq!(interpret_expressions(vec!["x = 2", "-x"]), Ok(Value::Int((-2).into())));
+        assert_eq!(interpret("-(1 < 2)"), Err(CalcError::TypeError("expected a number but found boolean true".to_string())));
+        assert_eq!(interpret("1 - "), Err(CalcError::ExpectedOperand { operator: Op::Minus, position: Some(2) }));
+        assert_eq!(interpret("1 + - "), Err(CalcError::ExpectedOperand { operator: Op::Minus, position: Some(4) }));
+        assert_eq!(parse_str("-x").unwrap().to_string(), "-x");
+        assert_eq!(strip_parens(&parse_str("-(1 + 2) * -x").unwrap()), "-(1 + 2) * -x");
+    }
+
+    #[test]
+    fn test_interpret_unicode_operators() {
+        assert_eq!(interpret("6 × 7"), Ok(Value::Int(42.into())));
+        assert_eq!(interpret("84 ÷ 2"), Ok(Value::Int(42.into())));
+        assert_eq!(interpret("50 \u{2212} 8"), Ok(Value::Int(42.into())));
+        assert_eq!(interpret("1 ÷ 0"), Err(CalcError::DivisionByZero));
+    }
+
+    #[test]
+    fn test_double_star_power() {
+        assert_eq!(interpret("2 ** 3"), Ok(Value::Int(8.into())));
+        assert_eq!(interpret("2 ** 3 ** 2"), Ok(Value::Int(512.into())));
+        assert_eq!(interpret("2 * 3 ** 2"), Ok(Value::Int(18.into())));
+        assert_eq!(interpret("2 ** 3 ^ 2"), interpret("2 ^ 3 ^ 2"));
+        assert_eq!(interpret("2 * *3"), Err(CalcError::ExpectedOperand { operator: Op::Star, position: Some(2) }));
+    }
+
+    #[test]
+    fn test_interpret_beyond_i32_range() {
+        assert_eq!(interpret("1000000 * 1000000"), Ok(Value::Int("1000000000000".parse().unwrap())));
+        assert_eq!(interpret("3000000000 + 1"), Ok(Value::Int("3000000001".parse().unwrap())));
+    }
+
+    #[test]
+    fn test_interpret_expressions() {
+        assert_eq!(interpret_expressions(vec!["x = 1", "y = 2", "x + y"]), Ok(Value::Int(3.into())));
+        assert_eq!(interpret_expressions(vec!["const a = 1", "a = 2"]), Err(CalcError::ReassignConst("a".to_string())));
+        assert_eq!(interpret_expressions(vec![]), Ok(Value::Int(0.into())));
+    }
+
+    #[test]
+    fn test_interpret_all() {
+        assert_eq!(interpret_all(&["x = 2", "x + 1", "x * 5"]), Ok(vec![Value::Int(2.into()), Value::Int(3.into()), Value::Int(10.into())]));
+        assert_eq!(interpret_all(&[]), Ok(vec![]));
+        assert_eq!(interpret_all(&["6*7", "ans + 1"]), Ok(vec![Value::Int(42.into()), Value::Int(43.into())]));
+        assert_eq!(interpret_all(&["x = 2", "y", "x = 3"]), Err(CalcError::UndefinedVariable("y".to_string())));
+        assert_eq!(interpret_all(&["const a = 1", "a + 1", "a = 2"]), Err(CalcError::ReassignConst("a".to_string())));
+    }
+
+    #[test]
+    fn test_interpret_program() {
+        let (value, environment) = interpret_program("a = 2; b = a * 3; b").unwrap();
+        assert_eq!(value, Value::Int(6.into()));
+        assert_eq!(environment, HashMap::from([
+            ("a".to_string(), Value::Int(2.into())), ("b".to_string(), Value::Int(6.into())), ("ans".to_string(), Value::Int(6.into())),
+        ]));
+        assert_eq!(interpret_program("const c = 1; c = 2"), Err(CalcError::ReassignConst("c".to_string())));
+        assert_eq!(interpret_program(""), Err(CalcError::EmptyInput));
+        // Leftover input is reported just as `parse_str` reports it.
+        assert_eq!(interpret_program("1 2"), Err(CalcError::TrailingTokens { position: Some(2) }));
+        assert_eq!(interpret_program("x = 1; { 1 2 }"), Err(CalcError::TrailingTokens { position: Some(11) }));
+        assert_eq!(interpret_lines("x = 1\n1 2"), Err(CalcError::TrailingTokens { position: Some(2) }));
+    }
+
+    #[test]
+    fn test_program_forward_references() {
+        let program = "def even(n) = n == 0 ? 1 : odd(n - 1); result = even(10); def odd(n) = n == 0 ? 0 : even(n - 1); result";
+        assert_eq!(interpret_program(program).map(|(value, _)| value), Ok(Value::Int(1.into())));
+        assert_eq!(interpret_program("def f(n) = g(n) + 1; y = f(1); def g(n) = n * 2; y").map(|(value, _)| value), Ok(Value::Int(3.into())));
+        // Each redefinition still takes effect in its place.
+        assert_eq!(interpret_program("def f() = 1; a = f(); def f() = 2; a * 10 + f()").map(|(value, _)| value), Ok(Value::Int(12.into())));
+        // Early definitions leave `ans` alone.
+        assert_eq!(interpret_program("ans; def f() = 1"), Err(CalcError::UndefinedVariable("ans".to_string())));
+    }
+
+    #[test]
+    fn test_ans() {
+        assert_eq!(interpret_expressions(vec!["6 * 7", "ans + 1"]), Ok(Value::Int(43.into())));
+        assert_eq!(interpret_expressions(vec!["2", "ans * ans", "ans - 1"]), Ok(Value::Int(3.into())));
+        assert_eq!(interpret_expressions(vec!["ans"]), Err(CalcError::UndefinedVariable("ans".to_string())));
+        assert_eq!(interpret_program("x = 5; ans * 2").map(|(value, _)| value), Ok(Value::Int(10.into())));
+        assert_eq!(interpret_lines("10
+ans / 4; ans * 2"), Ok(Value::Float(5.0)));
+        // A program may claim `ans` for itself.
+        assert_eq!(interpret_program("const ans = 1; 2; ans").map(|(value, _)| value), Ok(Value::Int(1.into())));
+        let mut calculator = Calculator::new().with_max_variables(1);
+        assert_eq!(calculator.interpret("6 * 7"), Ok(Value::Int(42.into())));
+        assert_eq!(calculator.interpret("x = ans + 1"), Ok(Value::Int(43.into())));
+        assert_eq!(calculator.interpret("1 / 0"), Err(CalcError::DivisionByZero));
+        assert_eq!(calculator.interpret("ans"), Ok(Value::Int(43.into())));
+        assert_eq!(calculator.interpret("ans = 7"), Ok(Value::Int(7.into())));
+        assert_eq!(calculator.interpret("y = 1"), Err(CalcError::TooManyVariables(1)));
+    }
+
+    #[test]
+    fn test_transactional_program() {
+        let mut environment = HashMap::new();
+        let failed = interpret_program_with_environment("a = 1; b = a / 0", &mut environment, true);
+        assert_eq!(failed, Err(CalcError::DivisionByZero));
+        assert_eq!(environment.get("a"), None);
+        let failed = interpret_program_with_environment("a = 1; b = a / 0", &mut environment, false);
+        assert_eq!(failed, Err(CalcError::DivisionByZero));
+        assert_eq!(environment.get("a"), Some(&Value::Int(1.into())));
+        // A rollback restores earlier values, not just removes new names.
+        let saved = snapshot(&environment);
+        assert!(interpret_program_with_environment("a = 5; c = 2; missing", &mut environment, true).is_err());
+        assert_eq!(environment, saved);
+        assert_eq!(interpret_program_with_environment("a += 1; a * 10", &mut environment, true), Ok(Value::Int(20.into())));
+        assert_eq!(environment.get("a"), Some(&Value::Int(2.into())));
+    }
+
+    #[test]
+    fn test_interpret_reader() {
+        let script = "# setup\na = 2\n\n  b = a * 3; c = 1\n   # done\nb + c\n";
+        let mut environment = HashMap::new();
+        assert_eq!(interpret_reader(script.as_bytes(), &mut environment), Ok(Value::Int(7.into())));
+        assert_eq!(environment.get("b"), Some(&Value::Int(6.into())));
+        assert_eq!(interpret_reader("# nothing\n\n".as_bytes(), &mut environment), Err(CalcError::EmptyInput));
+        assert_eq!(
+            interpret_reader("a = 1\nb +\n".as_bytes(), &mut environment),
+            Err(CalcError::ExpectedOperand { operator: Op::Plus, position: Some(2) })
+        );
+        assert!(matches!(interpret_reader(&[0xff, b'\n'][..], &mut environment), Err(CalcError::Io(_))));
+    }
+
+    #[test]
+    fn test_interpret_lines() {
+        assert_eq!(interpret_lines("x = 1\ny = x + 1\ny * 2"), Ok(Value::Int(4.into())));
+        assert_eq!(interpret_lines("a = 1; b = 2\n\n# total\nc = a + b; c * 10\n"), Ok(Value::Int(30.into())));
+        assert_eq!(interpret_lines("\n  \n"), Err(CalcError::EmptyInput));
+        assert_eq!(interpret_lines("1 +\n2"), Err(CalcError::ExpectedOperand { operator: Op::Plus, position: Some(2) }));
+    }
+
+    #[test]
+    fn test_left_associativity() {
+        assert_eq!(interpret("10 - 3 - 2"), Ok(Value::Int(5.into())));
+        assert_eq!(interpret("16 / 4 / 2"), Ok(Value::Int(2.into())));
+        assert_eq!(interpret("10 - 3 + 2"), Ok(Value::Int(9.into())));
+        assert_eq!(interpret("100 // 10 // 3"), Ok(Value::Int(3.into())));
+        assert_eq!(interpret("2 / 4 * 8"), Ok(Value::Float(4.0)));
+    }
+
+    #[test]
+    fn test_variables_keep_their_type() {
+        assert_eq!(interpret_expressions(vec!["x = 3.5", "x * 2"]), Ok(Value::Float(7.0)));
+        assert_eq!(interpret_expressions(vec!["x = 2.0", "y = 3", "x * y"]), Ok(Value::Float(6.0)));
+        assert_eq!(interpret_expressions(vec!["n = 4", "m = n * 2", "m // 3"]), Ok(Value::Int(2.into())));
+        let mut environment = HashMap::new();
+        interpret_with_environment("x = 1.5", &mut environment).unwrap();
+        interpret_with_environment("n = 2", &mut environment).unwrap();
+        interpret_with_environment("n += 1", &mut environment).unwrap();
+        assert_eq!(environment.get("x"), Some(&Value::Float(1.5)));
+        assert_eq!(environment.get("n"), Some(&Value::Int(3.into())));
+    }
+
+    #[test]
+    fn test_true_and_floor_division() {
+        assert_eq!(interpret("7 / 2"), Ok(Value::Float(3.5)));
+        assert_eq!(interpret("6 / 2"), Ok(Value::Int(3.into())));
+        assert_eq!(interpret("7 // 2"), Ok(Value::Int(3.into())));
+        assert_eq!(interpret("(0 - 7) // 2"), Ok(Value::Int((-4).into())));
+        assert_eq!(interpret("7 // (0 - 2)"), Ok(Value::Int((-4).into())));
+        assert_eq!(interpret("(0 - 7) // (0 - 2)"), Ok(Value::Int(3.into())));
+        assert_eq!(interpret("(0 - 7.5) // 2"), Ok(Value::Float(-4.0)));
+        assert_eq!(interpret("1.5 + 1"), Ok(Value::Float(2.5)));
+        assert_eq!(interpret("0.5 < 1 && 2.0 == 2"), Ok(Value::Bool(true)));
+        assert_eq!(parse_str("a // b * c").unwrap().to_string(), "(a // b) * c");
+    }
+
+    #[test]
+    fn test_comments() {
+        assert_eq!(interpret("6 / 2 # divide"), Ok(Value::Int(3.into())));
+        assert_eq!(interpret("7 // 2 # floor divide"), Ok(Value::Int(3.into())));
+        assert_eq!(interpret_program("x = 1; # one\ny = x + 1 # two\n# done"), interpret_program("x = 1; y = x + 1"));
+        assert_eq!(interpret("# nothing"), Err(CalcError::EmptyInput));
+    }
+
+    #[test]
+    fn test_scientific_notation() {
+        assert_eq!(interpret("1e3"), Ok(Value::Float(1000.0)));
+        assert_eq!(interpret("2.5e-2 * 4"), Ok(Value::Float(0.1)));
+        assert_eq!(interpret_expressions(vec!["e = 2", "e * 1e1"]), Ok(Value::Float(20.0)));
+        assert_eq!(interpret("1 + 2e"), Err(CalcError::MalformedNumber { text: "2e".to_string(), position: Some(4) }));
+        assert_eq!(dry_run("1; 2e", &HashMap::new()), Err(CalcError::MalformedNumber { text: "2e".to_string(), position: Some(3) }));
+    }
+
+    #[test]
+    fn test_digit_separators() {
+        assert_eq!(interpret("1_000 + 1"), Ok(Value::Int(1001.into())));
+        assert_eq!(interpret("1__0"), Err(CalcError::MalformedNumber { text: "1__0".to_string(), position: Some(0) }));
+        assert_eq!(interpret_expressions(vec!["_1 = 5", "_1 * 2"]), Ok(Value::Int(10.into())));
+    }
+
+    #[test]
+    fn test_dry_run() {
+        let environment = HashMap::new();
+        assert_eq!(dry_run("x = 1; x = x + 5", &environment), Ok(vec![
+            SideEffect { name: "x".to_string(), old_value: None, new_value: Value::Int(1.into()) },
+            SideEffect { name: "x".to_string(), old_value: Some(Value::Int(1.into())), new_value: Value::Int(6.into()) },
+        ]));
+        assert!(environment.is_empty());
+
+        let mut environment = HashMap::new();
+        environment.insert("y".to_string(), Value::Int(2.into()));
+        assert_eq!(dry_run("y = y * 10;", &environment), Ok(vec![
+            SideEffect { name: "y".to_string(), old_value: Some(Value::Int(2.into())), new_value: Value::Int(20.into()) },
+        ]));
+        assert_eq!(environment.get("y"), Some(&Value::Int(2.into())));
+        // Writes inside blocks are reported as they happen; names local to a block are not.
+        let environment = HashMap::from([("a".to_string(), Value::Int(0.into())), ("b".to_string(), Value::Int(0.into()))]);
+        let effect = |name: &str, old_value: i32, new_value: i32| SideEffect { name: name.to_string(), old_value: Some(Value::Int(old_value.into())), new_value: Value::Int(new_value.into()) };
+        assert_eq!(dry_run("{ b = 1; a = 2; b = 3 }", &environment), Ok(vec![effect("b", 0, 1), effect("a", 0, 2), effect("b", 1, 3)]));
+        assert_eq!(dry_run("{ a = a }", &environment), Ok(vec![effect("a", 0, 0)]));
+        assert_eq!(dry_run("{ t = 1; { t = 2; a = t } }; let b = 5 in { b = 6; a = b }", &environment), Ok(vec![effect("a", 0, 2), effect("a", 2, 6)]));
+        assert_eq!(dry_run("def f() = { a = 9 }; f()", &environment).map(|effects| effects.len()), Ok(1));
+        let answer = SideEffect { name: "x".to_string(), old_value: None, new_value: Value::Int(43.into()) };
+        assert_eq!(dry_run("6 * 7; x = ans + 1", &environment), Ok(vec![answer]));
+        assert_eq!(dry_run("const ans = 1; 6 * 7; ans", &environment).map(|effects| effects.len()), Ok(1));
+        // Functions are defined up front, as when the program runs, but each is reported once.
+        let effects = dry_run("x = f(1); def f(n) = n", &HashMap::new()).unwrap();
+        let names: Vec<&str> = effects.iter().map(|effect| effect.name.as_str()).collect();
+        assert_eq!(names, ["x", "f"]);
+        assert_eq!(effects[0].new_value, Value::Int(1.into()));
+        assert_eq!(
+            dry_run("1 2", &environment),
+            Err(CalcError::TrailingTokens { position: Some(2) })
+        );
+    }
+
+    #[test]
+    fn test_comparisons() {
+        assert_eq!(interpret("1 < 2"), Ok(Value::Bool(true)));
+        assert_eq!(interpret("2 <= 1"), Ok(Value::Bool(false)));
+        assert_eq!(interpret("1 + 2 == 3"), Ok(Value::Bool(true)));
+        assert_eq!(interpret("3 != 3"), Ok(Value::Bool(false)));
+        assert_eq!(interpret("3 >= 3"), Ok(Value::Bool(true)));
+        assert_eq!(interpret("4 > 5"), Ok(Value::Bool(false)));
+        assert_eq!(interpret("1 < 2").map(|value| value.to_string()), Ok("true".to_string()));
+        assert_eq!(interpret("(1 < 2) == (2 < 1)"), Ok(Value::Bool(false)));
+        assert_eq!(interpret("(1 < 2) != (2 < 1)"), Ok(Value::Bool(true)));
+        let not_a_number = Err(CalcError::TypeError("expected a number but found boolean true".to_string()));
+        assert_eq!(interpret("(1 < 2) + 1"), not_a_number);
+        assert_eq!(interpret("(1 < 2) == 1"), not_a_number);
+        assert_eq!(interpret("(1 < 2) < (2 < 3)"), not_a_number);
+        assert_eq!(interpret("abs(1 < 2)"), not_a_number);
+        assert_eq!(interpret("(1 < 2)!"), not_a_number);
+        assert_eq!(interpret("(1 < 2) & 1"), not_a_number);
+    }
+
+    #[test]
+    fn test_chained_comparisons() {
+        assert_eq!(interpret_expressions(vec!["x = 5", "1 < x < 10"]), Ok(Value::Bool(true)));
+        assert_eq!(interpret_expressions(vec!["x = 50", "1 < x < 10"]), Ok(Value::Bool(false)));
+        assert_eq!(interpret("1 <= 1 < 2 != 3 == 3"), Ok(Value::Bool(true)));
+        // Not `(3 > 2) > 1`, which compares a boolean with a number.
+        assert_eq!(interpret("3 > 2 > 1"), Ok(Value::Bool(true)));
+        assert_eq!(interpret("(3 > 2) > 1"), Err(CalcError::TypeError("expected a number but found boolean true".to_string())));
+        // The middle operand runs once, and nothing after a false comparison runs.
+        assert_eq!(interpret_expressions(vec!["x = 0", "0 < (x = x + 1) < 2", "x"]), Ok(Value::Int(1.into())));
+        assert_eq!(interpret_expressions(vec!["x = 0", "2 < 1 < (x = 1)", "x"]), Ok(Value::Int(0.into())));
+        let ast = Rc::new(parse_str("{ x = 0; 0 < (x = x + 1) < 2 < 3; x }").unwrap());
+        assert_eq!(evaluate_iterative(ast), Ok(Value::Int(1.into())));
+    }
+
+    #[test]
+    fn test_logical_operators() {
+        assert_eq!(interpret("1 < 2 && 2 < 3"), Ok(Value::Bool(true)));
+        assert_eq!(interpret("1 < 2 && 0 > 1"), Ok(Value::Bool(false)));
+        assert_eq!(interpret("1 > 2 || 2 > 1"), Ok(Value::Bool(true)));
+        assert_eq!(interpret("1 > 2 || 2 > 3"), Ok(Value::Bool(false)));
+        assert_eq!(interpret("!(1 > 2)"), Ok(Value::Bool(true)));
+        assert_eq!(interpret("!!(1 > 2)"), Ok(Value::Bool(false)));
+        assert_eq!(interpret("1 < 2 && 3 > 4 || 1 == 1"), Ok(Value::Bool(true)));
+        // Numbers are not truth values.
+        assert_eq!(interpret("1 && 2"), Err(CalcError::TypeError("expected a boolean but found number 1".to_string())));
+        assert_eq!(interpret("1 < 2 && 2.5"), Err(CalcError::TypeError("expected a boolean but found number 2.5".to_string())));
+        assert_eq!(interpret("!0"), Err(CalcError::TypeError("expected a boolean but found number 0".to_string())));
+        let ast = Rc::new(parse_str("1 < 2 || 3").unwrap());
+        assert_eq!(evaluate_iterative(ast), Ok(Value::Bool(true)));
+        let ast = Rc::new(parse_str("1 > 2 || 3").unwrap());
+        assert_eq!(evaluate_iterative(ast), Err(CalcError::TypeError("expected a boolean but found number 3".to_string())));
+        assert_eq!(
+            parse(tokenize_all("a || b && c == d")).unwrap().to_string(),
+            "a || (b && (c == d))"
+        );
+    }
+
+    #[test]
+    fn test_logical_short_circuit() {
+        assert_eq!(interpret("1 > 2 && (1 / 0)"), Ok(Value::Bool(false)));
+        assert_eq!(interpret("1 < 2 || (1 / 0)"), Ok(Value::Bool(true)));
+        assert_eq!(interpret_expressions(vec!["x = 1", "1 > 2 && (x = 2)", "x"]), Ok(Value::Int(1.into())));
+        let ast = Rc::new(parse(tokenize_all("1 > 2 && (1 / 0) || 1 < 2 || (1 / 0)")).unwrap());
+        assert_eq!(evaluate_iterative(ast), Ok(Value::Bool(true)));
+    }
+
+    #[test]
+    fn test_conditional() {
+        assert_eq!(interpret("1 < 2 ? 10 : 20"), Ok(Value::Int(10.into())));
+        assert_eq!(interpret("1 > 2 ? 10 : 20"), Ok(Value::Int(20.into())));
+        assert_eq!(interpret("1 > 2 ? 1 : 2 > 3 ? 2 : 3"), Ok(Value::Int(3.into())));
+        assert_eq!(interpret("1 ? 2 : 3"), Err(CalcError::TypeError("expected a boolean but found number 1".to_string())));
+        assert_eq!(interpret_expressions(vec!["x = 5", "y = x > 3 ? x * 2 : 0", "y"]), Ok(Value::Int(10.into())));
+        assert_eq!(interpret("1 ? 2"), Err(CalcError::UnexpectedToken { token: Token::InputEnd, position: Some(5) }));
+    }
+
+    #[test]
+    fn test_if_then_else() {
+        assert_eq!(interpret("if 1 < 2 then 10 else 20"), Ok(Value::Int(10.into())));
+        assert_eq!(interpret("if 1 > 2 then 10 else 20"), Ok(Value::Int(20.into())));
+        assert_eq!(parse_str("if x then a else b"), parse_str("x ? a : b"));
+        assert_eq!(interpret("if 1 < 2 then 42 else 1 / 0"), Ok(Value::Int(42.into())));
+        assert_eq!(interpret_expressions(vec!["x = 1", "if 1 > 2 then x = 2 else x + 1", "x"]), Ok(Value::Int(1.into())));
+        assert_eq!(interpret("if 1 > 2 then 1 else if 2 > 3 then 2 else 3"), Ok(Value::Int(3.into())));
+        assert_eq!(interpret("2 * (if 1 < 2 then 3 else 4) + 1"), Ok(Value::Int(7.into())));
+        assert_eq!(interpret("if 1 < 2 then 10"), Err(CalcError::UnexpectedToken { token: Token::InputEnd, position: Some(16) }));
+        assert_eq!(interpret("if 1 < 2 10 else 20"), Err(CalcError::UnexpectedToken { token: Token::Int(10.into()), position: Some(9) }));
+        assert_eq!(interpret("then = 1"), Err(CalcError::UnexpectedToken { token: Token::Keyword("then"), position: Some(0) }));
+    }
+
+    #[test]
+    fn test_conditional_evaluates_only_taken_branch() {
+        assert_eq!(interpret("1 < 2 ? 42 : (1 / 0)"), Ok(Value::Int(42.into())));
+        assert_eq!(interpret("1 > 2 ? (1 / 0) : 7"), Ok(Value::Int(7.into())));
+        assert_eq!(interpret_expressions(vec!["x = 1", "1 > 2 ? (x = 2) : 3", "x"]), Ok(Value::Int(1.into())));
+        let ast = Rc::new(parse(tokenize_all("1 < 2 ? 42 : (1 / 0)")).unwrap());
+        assert_eq!(evaluate_iterative(ast), Ok(Value::Int(42.into())));
+    }
+
+    #[test]
+    fn test_bitwise_operators() {
+        assert_eq!(interpret("6 & 3"), Ok(Value::Int(2.into())));
+        assert_eq!(interpret("5 | 2"), Ok(Value::Int(7.into())));
+        assert_eq!(interpret("1 << 4"), Ok(Value::Int(16.into())));
+        assert_eq!(interpret("256 >> 2"), Ok(Value::Int(64.into())));
+        assert_eq!(interpret("(0 - 16) >> 2"), Ok(Value::Int((-4).into())));
+        assert_eq!(interpret("1 << 2 + 1"), Ok(Value::Int(8.into())));
+        assert_eq!(interpret("5 & 1 == 1"), Ok(Value::Bool(true)));
+        assert_eq!(interpret("1 | 6 & 3"), Ok(Value::Int(3.into())));
+        assert_eq!(interpret("1.0 & 1"), Err(CalcError::TypeError("1.0 & 1 needs integer operands".to_string())));
+    }
+
+    #[test]
+    #[cfg(not(feature = "bignum"))]
+    fn test_shift_bounds() {
+        assert_eq!(interpret("1 << 63"), Ok(Value::Int(i64::MIN)));
+        assert_eq!(interpret("1 << 64"), Err(CalcError::ShiftOutOfRange(64)));
+        assert_eq!(interpret("1 >> (0 - 1)"), Err(CalcError::ShiftOutOfRange(-1)));
+        assert_eq!(try_evaluate(Rc::new(parse_str("8 >> 100").unwrap()), &mut HashMap::new()), Err(CalcError::ShiftOutOfRange(100)));
+    }
+
+    #[test]
+    fn test_factorial() {
+        assert_eq!(interpret("3! + 1"), Ok(Value::Int(7.into())));
+        assert_eq!(interpret("5!"), Ok(Value::Int(120.into())));
+        assert_eq!(interpret("0!"), Ok(Value::Int(1.into())));
+        assert_eq!(interpret("3!!"), Ok(Value::Int(720.into())));
+        assert_eq!(interpret("2 * 3!"), Ok(Value::Int(12.into())));
+        // `!0!` is `!(0!)`, so the not sees 1.
+        assert_eq!(interpret("!0!"), Err(CalcError::TypeError("expected a boolean but found number 1".to_string())));
+        assert_eq!(interpret("3!=6"), Ok(Value::Bool(true)));
+        assert_eq!(interpret("3! == 6"), Ok(Value::Bool(true)));
+        assert_eq!(interpret("20!"), Ok(Value::Int("2432902008176640000".parse().unwrap())));
+        assert_eq!(interpret("(0 - 1)!"), Err(CalcError::DomainError("factorial of negative number -1".to_string())));
+        assert_eq!(interpret("2.0!"), Err(CalcError::TypeError("factorial needs an integer operand".to_string())));
+        assert_eq!(try_evaluate(Rc::new(parse_str("4!").unwrap()), &mut HashMap::new()), Ok(Value::Int(24.into())));
+    }
+
+    #[test]
+    fn test_percent_and_modulo() {
+        assert_eq!(interpret("50%"), Ok(Value::Float(0.5)));
+        assert_eq!(interpret("10 % 3"), Ok(Value::Int(1.into())));
+        assert_eq!(interpret("10%3"), Ok(Value::Int(1.into())));
+        assert_eq!(interpret("200 * 10% + 10 % 4"), Ok(Value::Float(22.0)));
+        assert_eq!(interpret("(0 - 7) % 3"), Ok(Value::Int((-1).into())));
+        assert_eq!(interpret("7.5 % 2"), Ok(Value::Float(1.5)));
+        assert_eq!(interpret("5 % 0"), Err(CalcError::DivisionByZero));
+        assert_eq!(interpret("2.5%"), Ok(Value::Float(0.025)));
+        assert_eq!(interpret("10 % (4)"), Ok(Value::Int(2.into())));
+        assert_eq!(interpret("(10)%"), Ok(Value::Float(0.1)));
+        assert_eq!(interpret("3!%"), Ok(Value::Float(0.06)));
+        assert_eq!(strip_parens(&parse_str("a%!").unwrap()), "a%!");
+        assert_eq!(strip_parens(&parse_str("7 % !x").unwrap()), "7 % !x");
+        assert_eq!(interpret("{ x = 40; x% * 5 }"), Ok(Value::Float(2.0)));
+        assert_eq!(parse_str("a % b + c%").unwrap().to_string(), "(a % b) + (c%)");
+        assert_eq!(strip_parens(&parse_str("(a%) % b").unwrap()), "a% % b");
+    }
+
+    #[test]
+    fn test_power() {
+        assert_eq!(interpret("2 ^ 3"), Ok(Value::Int(8.into())));
+        assert_eq!(interpret("2 ^ 0"), Ok(Value::Int(1.into())));
+        assert_eq!(interpret("2 ^ -1"), Ok(Value::Float(0.5)));
+        assert_eq!(interpret("2 ^ (0 - 1)"), Ok(Value::Float(0.5)));
+        assert_eq!(interpret("-2 ^ 2"), Ok(Value::Int((-4).into())));
+        assert_eq!(interpret("(-2) ^ 2"), Ok(Value::Int(4.into())));
+        assert_eq!(interpret("2 ^ -1 ^ 2"), Ok(Value::Float(0.5)));
+        assert_eq!(interpret("-2 ^ 2 * 3"), Ok(Value::Int((-12).into())));
+        assert_eq!(strip_parens(&parse_str("-2 ^ 2 + (-2) ^ 2 + 2 ^ -2").unwrap()), "-2 ^ 2 + (-2) ^ 2 + 2 ^ -2");
+        assert_eq!(interpret("4 ^ 0.5"), Ok(Value::Float(2.0)));
+        assert_eq!(interpret("2.0 ^ 3"), Ok(Value::Float(8.0)));
+        assert_eq!(interpret("2 ^ 3 ^ 2"), Ok(Value::Int(512.into())));
+        assert_eq!(interpret("2 * 3 ^ 2"), Ok(Value::Int(18.into())));
+        assert_eq!(interpret("2 ^ 3!"), Ok(Value::Int(64.into())));
+        assert_eq!(interpret("2 ^ 62"), Ok(Value::Int(Integer::from(1) << 62)));
+        assert_eq!(interpret("(0 - 1) ^ 10000000001"), Ok(Value::Int((-1).into())));
+        assert_eq!(interpret("0 ^ 10000000000"), Ok(Value::Int(0.into())));
+        assert_eq!(parse_str("a ^ b ^ c").unwrap().to_string(), "a ^ (b ^ c)");
+    }
+
+    #[test]
+    fn test_compound_assignment() {
+        assert_eq!(interpret_expressions(vec!["x = 10", "x += 5", "x"]), Ok(Value::Int(15.into())));
+        assert_eq!(interpret_expressions(vec!["x = 10", "x -= 4", "x *= 3", "x /= 2", "x"]), Ok(Value::Int(9.into())));
+        assert_eq!(interpret_expressions(vec!["x = 1", "x += 2 * 3"]), Ok(Value::Int(7.into())));
+        assert_eq!(interpret("(1 + 2) += 3"), Err(CalcError::InvalidAssignTarget { position: Some(0) }));
+    }
+
+    #[test]
+    fn test_assign_return_old() {
+        assert_eq!(interpret_all(&["x = 5", "x := 9", "x"]), Ok(vec![Value::Int(5.into()), Value::Int(5.into()), Value::Int(9.into())]));
+        assert_eq!(interpret_expressions(vec!["x = 5", "x := 9", "x"]), Ok(Value::Int(9.into())));
+        assert_eq!(interpret_expressions(vec!["a = 1", "b = 2", "a := b := a", "a * 10 + b"]), Ok(Value::Int(21.into())));
+        assert_eq!(interpret("y := 1"), Err(CalcError::UndefinedVariable("y".to_string())));
+        assert_eq!(interpret("1 := 2"), Err(CalcError::InvalidAssignTarget { position: Some(0) }));
+        let node = parse_str("x := x + 1").unwrap();
+        assert_eq!(node.to_string(), "x := x + 1");
+        assert_eq!(try_evaluate(Rc::new(node), &mut HashMap::from([("x".to_string(), Value::Int(1.into()))])), Ok(Value::Int(1.into())));
+    }
+
+    #[test]
+    fn test_float_equality_tolerance() {
+        assert_eq!(interpret("0.1 + 0.2 == 0.3"), Ok(Value::Bool(true)));
+        assert_eq!(interpret("0.1 + 0.2 != 0.3"), Ok(Value::Bool(false)));
+        assert_eq!(interpret("1.0 == 1.001"), Ok(Value::Bool(false)));
+        assert_eq!(interpret("3 == 3.0000000000001"), Ok(Value::Bool(true)));
+        assert_eq!(interpret("1e20 == 1e20 + 1000"), Ok(Value::Bool(true)));
+        assert_eq!(interpret("9007199254740993 == 9007199254740992"), Ok(Value::Bool(false)));
+        assert_eq!(interpret("1.0 / 0 == 1.0 / 0"), Ok(Value::Bool(true)));
+        assert_eq!(Calculator::new().with_epsilon(0.0).interpret("0.1 + 0.2 == 0.3"), Ok(Value::Bool(false)));
+        assert_eq!(Calculator::new().with_epsilon(0.01).interpret("1.0 == 1.001"), Ok(Value::Bool(true)));
+    }
+
+    #[test]
+    fn test_arithmetic_errors() {
+        assert_eq!(interpret("1 / 0"), Err(CalcError::DivisionByZero));
+        assert_eq!(interpret("7 // (2 - 2)"), Err(CalcError::DivisionByZero));
+        assert_eq!(interpret("1.0 / 0"), Ok(Value::Float(f64::INFINITY)));
+        assert_eq!(interpret_expressions(vec!["def f(n) = f(n + 1)", "f(0)"]), Err(CalcError::RecursionLimit));
+    }
+
+    // Results past the range of the fixed-width Integer; big integers just keep growing.
+    #[test]
+    #[cfg(not(feature = "bignum"))]
+    fn test_fixed_width_overflow() {
+        assert_eq!(interpret("9223372036854775807 + 1"), Err(CalcError::Overflow));
+        assert_eq!(interpret("-(0 - 9223372036854775807 - 1)"), Err(CalcError::Overflow));
+        assert_eq!(interpret("abs(0 - 9223372036854775807 - 1)"), Err(CalcError::Overflow));
+        assert_eq!(interpret("2 ^ 63"), Err(CalcError::Overflow));
+        assert_eq!(interpret("2 ^ 10000000000"), Err(CalcError::Overflow));
+        assert_eq!(interpret("21!"), Err(CalcError::Overflow));
+        assert_eq!(interpret("1000000000000!"), Err(CalcError::Overflow));
+        assert_eq!(interpret("lcm(4294967311, 4294967357)"), Err(CalcError::Overflow));
+        assert_eq!(interpret("product(4294967296, 4294967296)"), Err(CalcError::Overflow));
+        assert_eq!(interpret("sum(9223372036854775807, 1)"), Err(CalcError::Overflow));
+        let error = interpret_located("9223372036854775807 + 1", &mut HashMap::new());
+        assert_eq!(error, Err(CalcError::Located { error: Box::new(CalcError::Overflow), position: 20 }));
+    }
+
+    #[test]
+    fn test_invalid_character() {
+        assert_eq!(interpret("1 + 😀"), Err(CalcError::InvalidCharacter { character: '😀', position: Some(4) }));
+        assert_eq!(
+            interpret("2 * $x").unwrap_err().render("2 * $x"),
+            "error: invalid character $\n1 | 2 * $x\n  |     ^"
+        );
+        #[cfg(not(feature = "unicode-identifiers"))]
+        assert_eq!(interpret("π = 3"), Err(CalcError::InvalidCharacter { character: 'π', position: Some(0) }));
+        #[cfg(feature = "unicode-identifiers")]
+        assert_eq!(interpret_expressions(vec!["π = 3", "π * 2"]), Ok(Value::Int(6.into())));
+    }
+
+    #[test]
+    fn test_empty_input() {
+        assert_eq!(interpret(""), Err(CalcError::EmptyInput));
+        assert_eq!(interpret("   "), Err(CalcError::EmptyInput));
+        assert_eq!(interpret(" \t\n "), Err(CalcError::EmptyInput));
+        assert_eq!(parse(tokenize_all("")), Err(CalcError::EmptyInput));
+        assert_eq!(dry_run(" ", &HashMap::new()), Err(CalcError::EmptyInput));
+        assert_eq!(lint(""), Err(CalcError::EmptyInput));
+    }
+
+    #[test]
+    fn test_render_error() {
+        let input = "2 * (3 + )";
+        assert_eq!(
+            interpret(input).unwrap_err().render(input),
+            "error: missing operand for +\n1 | 2 * (3 + )\n  |        ^"
+        );
+        let input = "x = 1;\n\t1 + 2 = 3";
+        assert_eq!(
+            dry_run(input, &HashMap::new()).unwrap_err().render(input),
+            "error: only a variable can be assigned to\n2 | \t1 + 2 = 3\n  | \t^"
+        );
+        assert_eq!(interpret("y").unwrap_err().render("y"), "error: undefined variable y");
+    }
+
+    #[test]
+    fn test_located_runtime_errors() {
+        let mut environment = HashMap::new();
+        let located = |error: CalcError, position| Err(CalcError::Located { error: Box::new(error), position });
+        let input = "1 + (2 * missing)";
+        let error = interpret_located(input, &mut environment).unwrap_err();
+        assert_eq!(error.position(), Some(9));
+        assert_eq!(error.render(input), "error: undefined variable missing\n1 | 1 + (2 * missing)\n  |          ^");
+        assert_eq!(error.unlocated(), CalcError::UndefinedVariable("missing".to_string()));
+        assert_eq!(interpret_located("x = 4", &mut environment), Ok(Value::Int(4.into())));
+        assert_eq!(interpret_located("1 + x / (x - 4)", &mut environment), located(CalcError::DivisionByZero, 6));
+        assert_eq!(interpret_located("2 * (0 - 1)!", &mut environment), located(CalcError::DomainError("factorial of negative number -1".to_string()), 11));
+        // Errors inside a function body point at the call.
+        assert!(interpret_located("def f(n) = 10 / n", &mut environment).is_ok());
+        assert_eq!(interpret_located("x + f(0)", &mut environment), located(CalcError::DivisionByZero, 4));
+        assert_eq!(interpret_located("x += y", &mut environment), located(CalcError::UndefinedVariable("y".to_string()), 5));
+        assert_eq!(parse_located("a = b * 2").unwrap().to_string(), "a = b * 2");
+    }
+
+    #[test]
+    fn test_block_scoping() {
+        let mut environment = HashMap::new();
+        assert_eq!(interpret_with_environment("{ x = 1; x + 1 }", &mut environment), Ok(Value::Int(2.into())));
+        assert_eq!(environment.get("x"), None);
+        // Reads see the outer scope, writes to outer names persist, new names stay local.
+        assert_eq!(interpret_with_environment("x = 5", &mut environment), Ok(Value::Int(5.into())));
+        assert_eq!(interpret_with_environment("{ x = x + 1; y = 2; x * y }", &mut environment), Ok(Value::Int(12.into())));
+        assert_eq!(environment.get("x"), Some(&Value::Int(6.into())));
+        assert_eq!(environment.get("y"), None);
+        assert_eq!(interpret("1 + { 2; { 3; }; }"), Ok(Value::Int(4.into())));
+        let ast = Rc::new(parse_str("{ a = 1; { a = a + 1 }; a }").unwrap());
+        assert_eq!(ast.to_string(), "{ a = 1; { a = a + 1 }; a }");
+        assert_eq!(evaluate_iterative(ast), Ok(Value::Int(2.into())));
+        assert_eq!(dry_run("x = 1; { x = 2; z = 3 }", &HashMap::new()), Ok(vec![
+            SideEffect { name: "x".to_string(), old_value: None, new_value: Value::Int(1.into()) },
+            SideEffect { name: "x".to_string(), old_value: Some(Value::Int(1.into())), new_value: Value::Int(2.into()) },
+        ]));
+        assert_eq!(interpret("{ 1; 2"), Err(CalcError::UnexpectedToken { token: Token::InputEnd, position: Some(6) }));
+    }
+
+    #[test]
+    fn test_let() {
+        assert_eq!(interpret("let a = 1, b = 2 in a + b"), Ok(Value::Int(3.into())));
+        // Later bindings see earlier ones.
+        assert_eq!(interpret("let a = 2, b = a * 10, a = a + b in a"), Ok(Value::Int(22.into())));
+        let mut environment = HashMap::new();
+        assert_eq!(interpret_with_environment("a = 100", &mut environment), Ok(Value::Int(100.into())));
+        assert_eq!(interpret_with_environment("let a = 1, b = a + 1 in a * b", &mut environment), Ok(Value::Int(2.into())));
+        assert_eq!(environment.get("a"), Some(&Value::Int(100.into())));
+        assert_eq!(environment.get("b"), None);
+        // Like a block, the body may still update names from outside.
+        assert_eq!(interpret_with_environment("let b = 5 in a = a + b", &mut environment), Ok(Value::Int(105.into())));
+        assert_eq!(environment.get("a"), Some(&Value::Int(105.into())));
+        assert_eq!(interpret_with_environment("(let x = 1 in x) + x", &mut environment), Err(CalcError::UndefinedVariable("x".to_string())));
+        assert_eq!(interpret_expressions(vec!["const k = 1", "let k = 2 in k * 3", "k"]), Ok(Value::Int(1.into())));
+        let ast = Rc::new(parse_str("let n = 3, m = n + 1 in n * m").unwrap());
+        assert_eq!(ast.to_string(), "let n = 3, m = (n + 1) in n * m");
+        assert_eq!(evaluate_iterative(ast), Ok(Value::Int(12.into())));
+        assert_eq!(interpret("let a = 1 a"), Err(CalcError::UnexpectedToken { token: Token::Identifier("a".to_string()), position: Some(10) }));
+        assert_eq!(interpret("let = 1 in 2"), Err(CalcError::UnexpectedToken { token: Token::Operator(Op::Assign), position: Some(4) }));
+    }
+
+    #[test]
+    fn test_undefined_as_zero() {
+        let mut environment = HashMap::new();
+        let strict = EvalOptions::default();
+        let lenient = EvalOptions { undefined_as_zero: true, ..EvalOptions::default() };
+        assert_eq!(interpret_with_options("x + 1", &mut environment, strict), Err(CalcError::UndefinedVariable("x".to_string())));
+        assert_eq!(interpret_with_options("x + 1", &mut environment, lenient), Ok(Value::Int(1.into())));
+        assert_eq!(environment.get("x"), None);
+        assert_eq!(interpret_with_options("{ y = x * 2 + 3; y }", &mut environment, lenient), Ok(Value::Int(3.into())));
+        assert_eq!(interpret_with_options("x = 4", &mut environment, lenient), Ok(Value::Int(4.into())));
+        assert_eq!(interpret_with_options("x + 1", &mut environment, strict), Ok(Value::Int(5.into())));
+        assert_eq!(interpret_with_options("f(1)", &mut environment, lenient), Err(CalcError::UndefinedVariable("f".to_string())));
+    }
+
+    #[test]
+    fn test_implicit_multiplication() {
+        let mut environment = HashMap::from([("x".to_string(), Value::Int(5.into()))]);
+        let implicit = EvalOptions { implicit_mul: true, ..EvalOptions::default() };
+        assert_eq!(interpret_with_options("2(3+4)", &mut environment, implicit), Ok(Value::Int(14.into())));
+        assert_eq!(interpret_with_options("3 x", &mut environment, implicit), Ok(Value::Int(15.into())));
+        assert_eq!(interpret_with_options("3x", &mut environment, implicit), Ok(Value::Int(15.into())));
+        assert_eq!(interpret_with_options("2x^2 + 1", &mut environment, implicit), Ok(Value::Int(51.into())));
+        assert_eq!(interpret_with_options("(1 + 1)(x - 1) x", &mut environment, implicit), Ok(Value::Int(40.into())));
+        assert_eq!(interpret_with_options("2 ^ 3x", &mut environment, implicit), Ok(Value::Int(40.into())));
+        assert_eq!(interpret_with_options("y = 2x", &mut environment, implicit), Ok(Value::Int(10.into())));
+        assert_eq!(interpret_with_options("1 2", &mut environment, implicit), Ok(Value::Int(2.into())));
+        assert_eq!(interpret_with_options("3x", &mut environment, EvalOptions::default()), Err(CalcError::TrailingTokens { position: Some(1) }));
+        assert_eq!(parse_str("2(3+4)"), Err(CalcError::TrailingTokens { position: Some(1) }));
+    }
+
+    #[test]
+    fn test_decimal_comma_locale() {
+        let mut environment = HashMap::new();
+        let comma = EvalOptions { locale: LocaleOptions { decimal_comma: true }, ..EvalOptions::default() };
+        assert_eq!(interpret_with_options("3,5 * 2", &mut environment, comma), Ok(Value::Float(7.0)));
+        assert_eq!(interpret_with_options("max(1,5; 2)", &mut environment, comma), Ok(Value::Int(2.into())));
+        assert_eq!(interpret_with_options("def f(a; b) = a - b", &mut environment, comma).map(|_| ()), Ok(()));
+        assert_eq!(interpret_with_options("let a = 0,5; b = 2 in f(a; b)", &mut environment, comma), Ok(Value::Float(-1.5)));
+        assert!(interpret_with_options("max(1, 2)", &mut environment, comma).is_err());
+        assert_eq!(interpret_with_options("max(1,5, 2)", &mut environment, EvalOptions::default()), Ok(Value::Int(5.into())));
+    }
+
+    #[test]
+    fn test_step_limit() {
+        let mut environment = HashMap::new();
+        let limited = |max_steps| EvalOptions { max_steps: Some(max_steps), ..EvalOptions::default() };
+        assert!(interpret_with_options("def f(n) = n > 0 ? f(n - 1) + f(n - 1) : 0", &mut environment, limited(20)).is_ok());
+        // Never deep enough for the call depth limit, but 2^60 calls would run for ages.
+        assert_eq!(interpret_with_options("f(60)", &mut environment, limited(100_000)), Err(CalcError::StepLimitExceeded));
+        assert_eq!(interpret_with_options("f(3)", &mut environment, limited(100_000)), Ok(Value::Int(0.into())));
+        assert_eq!(interpret_with_options("1 + 2", &mut environment, limited(3)), Ok(Value::Int(3.into())));
+        assert_eq!(interpret_with_options("1 + 2", &mut environment, limited(2)), Err(CalcError::StepLimitExceeded));
+        assert_eq!(interpret_with_options("{ x = 1; x + 1 }", &mut environment, limited(5)), Err(CalcError::StepLimitExceeded));
+        assert_eq!(interpret_with_options("{ x = 1; x + 1 }", &mut environment, limited(6)), Ok(Value::Int(2.into())));
+    }
+
+    #[test]
+    fn test_embedded_assignment() {
+        let mut environment = HashMap::new();
+        let strict = EvalOptions { allow_embedded_assignment: false, ..EvalOptions::default() };
+        assert_eq!(interpret_with_options("1 + (x = 2)", &mut environment, EvalOptions::default()), Ok(Value::Int(3.into())));
+        assert_eq!(environment.get("x"), Some(&Value::Int(2.into())));
+        assert_eq!(interpret_with_options("1 + (x = 5)", &mut environment, strict), Err(CalcError::EmbeddedAssignment));
+        assert_eq!(environment.get("x"), Some(&Value::Int(2.into())));
+        assert_eq!(interpret_with_options("x = y = 3", &mut environment, strict), Ok(Value::Int(3.into())));
+        assert_eq!(interpret_with_options("x += 1", &mut environment, strict), Ok(Value::Int(4.into())));
+        assert_eq!(interpret_with_options("{ x = 1; let a = 2 in y = a; x + y }", &mut environment, strict), Ok(Value::Int(3.into())));
+        assert!(interpret_with_options("def f(n) = total = n", &mut environment, strict).is_ok());
+        for embedded in ["x > 0 ? x = 1 : 0", "f(x = 1)", "let a = (x = 1) in a", "(x = 1)!", "{ 1 + (x = 1) }", "const c = x = 1"] {
+            assert_eq!(interpret_with_options(embedded, &mut environment, strict), Err(CalcError::EmbeddedAssignment), "{}", embedded);
+        }
+    }
+
+    #[test]
+    fn test_undefined_variable() {
+        let mut environment = HashMap::new();
+        assert_eq!(interpret_with_environment("z", &mut environment), Err(CalcError::UndefinedVariable("z".to_string())));
+        assert_eq!(interpret_with_environment("z + 1", &mut environment), Err(CalcError::UndefinedVariable("z".to_string())));
+        assert_eq!(interpret_with_environment("z = 4", &mut environment), Ok(Value::Int(4.into())));
+        assert_eq!(interpret_with_environment("z + 1", &mut environment), Ok(Value::Int(5.into())));
+        assert_eq!(interpret("f(1)"), Err(CalcError::UndefinedVariable("f".to_string())));
+    }
+
+    #[test]
+    fn test_interpret_readonly() {
+        let mut vars = HashMap::new();
+        vars.insert("x".to_string(), Value::Int(41.into()));
+        interpret_with_environment("def double(n) = { m = n; m * 2 }", &mut vars).unwrap();
+        assert_eq!(interpret_readonly("x + 1", &vars), Ok(Value::Int(42.into())));
+        assert_eq!(interpret_readonly("double(x)", &vars), Ok(Value::Int(82.into())));
+        assert_eq!(interpret_readonly("x = 2", &vars), Err(CalcError::AssignmentForbidden));
+        assert_eq!(interpret_readonly("y = 2", &vars), Err(CalcError::AssignmentForbidden));
+        assert_eq!(interpret_readonly("x > 0 ? { x += 1 } : 0", &vars), Err(CalcError::AssignmentForbidden));
+        assert_eq!(interpret_readonly("const c = 1", &vars), Err(CalcError::AssignmentForbidden));
+        assert_eq!(interpret_readonly("def f() = 1", &vars), Err(CalcError::AssignmentForbidden));
+        assert_eq!(interpret_readonly("z", &vars), Err(CalcError::UndefinedVariable("z".to_string())));
+        assert_eq!(vars.get("x"), Some(&Value::Int(41.into())));
+    }
+
+    #[test]
+    fn test_interpret_with_vars() {
+        let vars = [("a", Value::Int(2.into())), ("b", Value::Int(3.into())), ("c", Value::Int(4.into()))];
+        assert_eq!(interpret_with_vars("a*b+c", &vars), Ok(Value::Int(10.into())));
+        assert_eq!(interpret_with_vars("{ a = a * 10; a + b }", &vars), Ok(Value::Int(23.into())));
+        assert_eq!(vars[0], ("a", Value::Int(2.into())));
+        assert_eq!(interpret_with_vars("x * 0.5", &[("x", Value::Float(3.0))]), Ok(Value::Float(1.5)));
+        assert_eq!(interpret_with_vars("d", &vars), Err(CalcError::UndefinedVariable("d".to_string())));
+    }
+
+    #[test]
+    fn test_eval_const() {
+        assert_eq!(eval_const("2 * (3 + 4)"), Ok(14.into()));
+        assert_eq!(eval_const("max(2, 5) ^ 2"), Ok(25.into()));
+        assert_eq!(eval_const("x + 1"), Err(CalcError::NotConstant));
+        assert_eq!(eval_const("let x = 1 in x"), Err(CalcError::NotConstant));
+        assert_eq!(eval_const("def f() = 1"), Err(CalcError::NotConstant));
+        assert_eq!(eval_const("7 / 2"), Err(CalcError::TypeError("expected an integer but found number 3.5".to_string())));
+        assert_eq!(eval_const("1 +"), Err(CalcError::ExpectedOperand { operator: Op::Plus, position: Some(2) }));
+    }
+
+    #[test]
+    fn test_interpret_with_source() {
+        struct Config(Vec<(&'static str, f64)>);
+        impl VariableSource for Config {
+            fn get(&self, name: &str) -> Option<Value> {
+                self.0.iter().find(|(key, _)| *key == name).map(|(_, value)| Value::Float(*value))
+            }
+        }
+        let config = Config(vec![("rate", 0.5), ("base", 10.0)]);
+        assert_eq!(interpret_with_source("base * rate + 1", &config), Ok(Value::Float(6.0)));
+        assert_eq!(interpret_with_source("let rate = 2 in base * rate", &config), Ok(Value::Float(20.0)));
+        assert_eq!(interpret_with_source("rate = 1", &config), Err(CalcError::AssignmentForbidden));
+        assert_eq!(interpret_with_source("missing", &config), Err(CalcError::UndefinedVariable("missing".to_string())));
+        let mut vars = HashMap::new();
+        vars.insert("x".to_string(), Value::Int(2.into()));
+        assert_eq!(interpret_with_source("x * 3", &vars), Ok(Value::Int(6.into())));
+    }
+
+    #[test]
+    fn test_calculator_max_variables() {
+        let mut calculator = Calculator::new().with_max_variables(3);
+        assert_eq!(calculator.interpret("a = 1"), Ok(Value::Int(1.into())));
+        assert_eq!(calculator.interpret("b = 2"), Ok(Value::Int(2.into())));
+        assert!(calculator.interpret("def c(x) = x").is_ok());
+        assert_eq!(calculator.interpret("d = 4"), Err(CalcError::TooManyVariables(3)));
+        assert_eq!(calculator.interpret("a = a + 10"), Ok(Value::Int(11.into())));
+        assert_eq!(calculator.interpret("b += 1"), Ok(Value::Int(3.into())));
+        assert_eq!(calculator.interpret("{ e = 1 }"), Err(CalcError::TooManyVariables(3)));
+        assert_eq!(calculator.interpret("d"), Err(CalcError::UndefinedVariable("d".to_string())));
+    }
+
+    #[test]
+    #[cfg(not(feature = "bignum"))]
+    fn test_calculator_arithmetic_mode() {
+        let mut calculator = Calculator::new().with_arithmetic_mode(ArithmeticMode::Saturating);
+        assert!(calculator.interpret("def twice(x) = x * 2").is_ok());
+        assert_eq!(calculator.interpret("twice(9223372036854775807)"), Ok(Value::Int(i64::MAX)));
+        assert!(calculator.interpret("m = 0 - 9223372036854775807").is_ok());
+        assert_eq!(calculator.interpret("m -= 5"), Ok(Value::Int(i64::MIN)));
+        assert_eq!(calculator.interpret("25!"), Err(CalcError::Overflow));
+        let mut calculator = Calculator::new().with_arithmetic_mode(ArithmeticMode::Wrapping);
+        assert_eq!(calculator.interpret("9223372036854775807 + 2"), Ok(Value::Int(i64::MIN + 1)));
+        assert_eq!(Calculator::new().interpret("9223372036854775807 + 2"), Err(CalcError::Overflow));
+    }
+
+    #[test]
+    #[cfg(not(feature = "bignum"))]
+    fn test_calculator_combined_settings() {
+        let mut calculator = Calculator::new().with_arithmetic_mode(ArithmeticMode::Saturating).with_max_variables(1).with_epsilon(0.0);
+        assert_eq!(calculator.interpret("a = 9223372036854775807 + 1"), Ok(Value::Int(i64::MAX)));
+        assert_eq!(calculator.interpret("b = 1"), Err(CalcError::TooManyVariables(1)));
+        assert_eq!(calculator.interpret("0.1 + 0.2 == 0.3"), Ok(Value::Bool(false)));
+        assert_eq!(Calculator::new().interpret("0.1 + 0.2 == 0.3"), Ok(Value::Bool(true)));
+    }
+
+    #[test]
+    #[cfg(not(feature = "bignum"))]
+    fn test_explicit_mode_builtins() {
+        assert_eq!(interpret("saturating_add(9223372036854775807, 1)"), Ok(Value::Int(i64::MAX)));
+        assert_eq!(interpret("checked_add(9223372036854775807, 1)"), Err(CalcError::Overflow));
+        assert_eq!(interpret("wrapping_add(9223372036854775807, 1)"), Ok(Value::Int(i64::MIN)));
+        assert_eq!(interpret("saturating_sub(0 - 9223372036854775807, 2)"), Ok(Value::Int(i64::MIN)));
+        assert_eq!(interpret("wrapping_mul(4611686018427387904, 2)"), Ok(Value::Int(i64::MIN)));
+        assert_eq!(interpret("checked_mul(6, 7) + saturating_mul(9223372036854775807, 9223372036854775807)"), Err(CalcError::Overflow));
+        assert_eq!(interpret("checked_sub(1.5, 1)"), Ok(Value::Float(0.5)));
+        assert_eq!(interpret("saturating_add(1)"), Err(CalcError::ArityMismatch { name: "saturating_add".to_string(), expected: 2, found: 1 }));
+        // The builtins ignore the evaluation's own mode, both ways.
+        let mut calculator = Calculator::new().with_arithmetic_mode(ArithmeticMode::Saturating);
+        assert_eq!(calculator.interpret("checked_add(9223372036854775807, 1)"), Err(CalcError::Overflow));
+        assert_eq!(Calculator::new().interpret("saturating_mul(9223372036854775807, 2)"), Ok(Value::Int(i64::MAX)));
+    }
+
+    #[test]
+    fn test_describe() {
+        assert_eq!(describe("(1 + 2) * 3"), Ok("Multiply the result of (add 1 and 2) by 3".to_string()));
+        assert_eq!(describe("x = y - 2 ^ n"), Ok("Assign to x the value of (subtract the result of (raise 2 to the power of n) from y)".to_string()));
+        assert_eq!(describe("0 < x <= 10"), Ok("Check whether 0 is less than x and x is at most 10".to_string()));
+        assert_eq!(describe("def f(a, b) = max(a, b)"), Ok("Define the function f(a, b) as (call max with a, b)".to_string()));
+        assert_eq!(describe("x > 0 ? x : 0 - x"), Ok("If (check whether x is greater than 0) then x, otherwise (subtract x from 0)".to_string()));
+        assert_eq!(describe("x"), Ok("The value of x".to_string()));
+        assert_eq!(describe("1 +"), Err(CalcError::ExpectedOperand { operator: Op::Plus, position: Some(2) }));
+    }
+
+    #[test]
+    fn test_parse_skips_kept_comments() {
+        let tokens = tokenize_with_options("1 + 2 # note", TokenizerOptions { keep_comments: true });
+        assert!(tokens.contains(&Token::Comment(" note".to_string())));
+        assert_eq!(evaluate(Rc::new(parse_tokens(&tokens).unwrap())), Ok(Value::Int(3.into())));
+        let tokens = tokenize_with_options("# total\n1 +\n# more\n2; 3", TokenizerOptions { keep_comments: true });
+        assert!(parse_all_errors(&tokens).is_ok());
+    }
+
+    #[test]
+    fn test_clamp_gcd_lcm() {
+        assert_eq!(interpret("clamp(0 - 5, 0, 10)"), Ok(Value::Int(0.into())));
+        assert_eq!(interpret("clamp(5, 0, 10)"), Ok(Value::Int(5.into())));
+        assert_eq!(interpret("clamp(15, 0, 10)"), Ok(Value::Int(10.into())));
+        assert_eq!(interpret("clamp(0.5, 0, 1)"), Ok(Value::Float(0.5)));
+        assert_eq!(interpret("clamp(2, 0, 1.5)"), Ok(Value::Float(1.5)));
+        assert_eq!(interpret("clamp(3, 3, 3)"), Ok(Value::Int(3.into())));
+        assert!(matches!(interpret("clamp(5, 10, 0)"), Err(CalcError::DomainError(_))));
+        assert_eq!(interpret("clamp(1, 2)"), Err(CalcError::ArityMismatch { name: "clamp".to_string(), expected: 3, found: 2 }));
+        assert_eq!(interpret("gcd(12, 18)"), Ok(Value::Int(6.into())));
+        assert_eq!(interpret("gcd(0 - 12, 18)"), Ok(Value::Int(6.into())));
+        assert_eq!(interpret("gcd(0, 0)"), Ok(Value::Int(0.into())));
+        assert_eq!(interpret("lcm(4, 6)"), Ok(Value::Int(12.into())));
+        assert_eq!(interpret("lcm(0 - 4, 6)"), Ok(Value::Int(12.into())));
+        assert_eq!(interpret("lcm(0, 6)"), Ok(Value::Int(0.into())));
+        assert!(matches!(interpret("gcd(1.5, 3)"), Err(CalcError::TypeError(_))));
+    }
+
+    #[test]
+    fn test_constants() {
+        let mut calculator = Calculator::new();
+        assert_eq!(calculator.interpret("const PI = 3"), Ok(Value::Int(3.into())));
+        assert_eq!(calculator.interpret("PI * 2"), Ok(Value::Int(6.into())));
+        assert_eq!(calculator.interpret("PI = 4"), Err(CalcError::ReassignConst("PI".to_string())));
+        assert_eq!(calculator.interpret("PI += 1"), Err(CalcError::ReassignConst("PI".to_string())));
+        assert_eq!(calculator.interpret("const PI = 5"), Err(CalcError::ReassignConst("PI".to_string())));
+        assert_eq!(calculator.interpret("def PI() = 1"), Err(CalcError::ReassignConst("PI".to_string())));
+        assert_eq!(calculator.interpret("PI"), Ok(Value::Int(3.into())));
+        assert!(calculator.interpret("def bump(PI) = { PI = PI + 1; PI }").is_ok());
+        assert_eq!(calculator.interpret("bump(1)"), Ok(Value::Int(2.into())));
+        assert_eq!(calculator.interpret("{ const local = 1; local + 1 }"), Ok(Value::Int(2.into())));
+        assert_eq!(calculator.interpret("local = 5"), Ok(Value::Int(5.into())));
+        assert_eq!(calculator.interpret("x = 1"), Ok(Value::Int(1.into())));
+        assert_eq!(calculator.interpret("x = 2"), Ok(Value::Int(2.into())));
+        assert_eq!(interpret("{ const k = 2; k = 3 }"), Err(CalcError::ReassignConst("k".to_string())));
+        assert_eq!(parse_str("const k = 1 + 2").unwrap().to_string(), "const k = 1 + 2");
+    }
+
+    #[test]
+    fn test_calculator_load() {
+        let mut calculator = Calculator::new();
+        calculator.load(vec![("a".to_string(), 2.into()), ("b".to_string(), 3.into()), ("c".to_string(), 4.into())]).unwrap();
+        assert_eq!(calculator.interpret("a * b + c"), Ok(Value::Int(10.into())));
+        assert_eq!(calculator.interpret("a = 10"), Ok(Value::Int(10.into())));
+        assert_eq!(calculator.interpret("a * b + c"), Ok(Value::Int(34.into())));
+        calculator.interpret("const k = 1").unwrap();
+        assert_eq!(calculator.load(vec![("a".to_string(), 5.into()), ("k".to_string(), 2.into())]), Err(CalcError::ReassignConst("k".to_string())));
+        assert_eq!(calculator.interpret("a + k"), Ok(Value::Int(11.into())));
+    }
+
+    #[test]
+    fn test_function_values() {
+        assert_eq!(interpret_expressions(vec!["def square(n) = n * n", "f = square", "f(5)"]), Ok(Value::Int(25.into())));
+        assert_eq!(interpret_expressions(vec!["def add(a, b) = a + b", "g = add", "g(2, 3) * 2"]), Ok(Value::Int(10.into())));
+        assert_eq!(interpret_expressions(vec!["def square(n) = n * n", "square(3)"]), Ok(Value::Int(9.into())));
+    }
+
+    #[test]
+    fn test_user_functions() {
+        let mut environment = HashMap::new();
+        assert!(interpret_with_environment("def square(n) = n * n", &mut environment).is_ok());
+        assert!(interpret_with_environment("def hypot2(a, b) = square(a) + square(b)", &mut environment).is_ok());
+        assert_eq!(interpret_with_environment("square(5)", &mut environment), Ok(Value::Int(25.into())));
+        assert_eq!(interpret_with_environment("hypot2(3, 4)", &mut environment), Ok(Value::Int(25.into())));
+        assert_eq!(
+            interpret_with_environment("hypot2(3)", &mut environment),
+            Err(CalcError::ArityMismatch { name: "hypot2".to_string(), expected: 2, found: 1 })
+        );
+        // Parameters are bound in a fresh scope and do not leak out of the call.
+        assert_eq!(interpret_with_environment("n", &mut environment), Err(CalcError::UndefinedVariable("n".to_string())));
+        assert!(interpret_with_environment("def fact(n) = n <= 1 ? 1 : n * fact(n - 1)", &mut environment).is_ok());
+        assert_eq!(interpret_with_environment("fact(10)", &mut environment), Ok(Value::Int(3628800.into())));
+    }
+
+    #[test]
+    fn test_call_non_function() {
+        assert_eq!(interpret_expressions(vec!["x = 1", "x(2)"]), Err(CalcError::NotCallable("x".to_string())));
+        assert_eq!(
+            interpret_expressions(vec!["def square(n) = n * n", "square(1, 2)"]),
+            Err(CalcError::ArityMismatch { name: "square".to_string(), expected: 1, found: 2 })
+        );
+    }
+
+    #[test]
+    fn test_print() {
+        let mut output = Vec::new();
+        let mut environment = HashMap::new();
+        assert_eq!(interpret_with_output("print(21 + 21)", &mut environment, &mut output), Ok(Value::Int(42.into())));
+        assert_eq!(interpret_with_output("def f(x) = print(x * 2.5)", &mut environment, &mut output).map(|_| ()), Ok(()));
+        assert_eq!(interpret_with_output("{ y = print(f(2)) + 1; print(y) }", &mut environment, &mut output), Ok(Value::Float(6.0)));
+        assert_eq!(String::from_utf8(output).unwrap(), "42\n5.0\n5.0\n6.0\n");
+        assert_eq!(interpret("print(1, 2)"), Err(CalcError::ArityMismatch { name: "print".to_string(), expected: 1, found: 2 }));
+        assert_eq!(interpret_expressions(vec!["def print(x) = x + 1", "print(1)"]), Ok(Value::Int(2.into())));
+    }
+
+    #[test]
+    fn test_abs_and_sign() {
+        assert_eq!(interpret("abs(0 - 7)"), Ok(Value::Int(7.into())));
+        assert_eq!(interpret("abs(7)"), Ok(Value::Int(7.into())));
+        assert_eq!(interpret("abs(0 - 2.5)"), Ok(Value::Float(2.5)));
+        assert_eq!(interpret("abs(0 - 9223372036854775807)"), Ok(Value::Int("9223372036854775807".parse().unwrap())));
+        assert_eq!(interpret("sign(0 - 9223372036854775807 - 1)"), Ok(Value::Int((-1).into())));
+        assert_eq!(interpret("sign(0) + sign(42)"), Ok(Value::Int(1.into())));
+        assert_eq!(interpret("sign(0 - 0.5)"), Ok(Value::Int((-1).into())));
+        assert_eq!(interpret("sign(0.0)"), Ok(Value::Int(0.into())));
+        assert!(matches!(interpret("sign(0.0 / 0)"), Err(CalcError::DomainError(_))));
+        assert_eq!(interpret("abs(1, 2)"), Err(CalcError::ArityMismatch { name: "abs".to_string(), expected: 1, found: 2 }));
+        assert_eq!(interpret_expressions(vec!["def abs(x) = 0", "abs(0 - 3)"]), Ok(Value::Int(0.into())));
+    }
+
+    #[test]
+    fn test_variadic_builtins() {
+        assert_eq!(interpret("sum(1, 2, 3, 4)"), Ok(Value::Int(10.into())));
+        assert_eq!(interpret("max(3, 9, 2)"), Ok(Value::Int(9.into())));
+        assert_eq!(interpret("min(3, 9, 2)"), Ok(Value::Int(2.into())));
+        assert_eq!(interpret("sum(1, 2.5)"), Ok(Value::Float(3.5)));
+        assert_eq!(interpret("max(1, 2.5, 2)"), Ok(Value::Float(2.5)));
+        assert_eq!(interpret("min(7)"), Ok(Value::Int(7.into())));
+        assert_eq!(interpret("sum(7)"), Ok(Value::Int(7.into())));
+        assert_eq!(interpret("max(2, 2.0)"), Ok(Value::Int(2.into())));
+        assert_eq!(interpret("product(2, 3, 4)"), Ok(Value::Int(24.into())));
+        assert_eq!(interpret("product(2, 0.5)"), Ok(Value::Float(1.0)));
+        assert_eq!(interpret("average(2, 4, 6)"), Ok(Value::Float(4.0)));
+        assert_eq!(interpret("average(1, 2)"), Ok(Value::Float(1.5)));
+        assert_eq!(interpret("average(9223372036854775807, 9223372036854775807)"), Ok(Value::Float(9223372036854775807.0)));
+        for name in ["min", "max", "sum", "product", "average"] {
+            assert_eq!(
+                interpret(&format!("{}()", name)),
+                Err(CalcError::ArityMismatch { name: name.to_string(), expected: 1, found: 0 })
+            );
+        }
+        assert!(matches!(interpret_expressions(vec!["def f(x) = x", "max(f)"]), Err(CalcError::TypeError(_))));
+        assert_eq!(interpret_expressions(vec!["def sum(a, b) = a - b", "sum(5, 2)"]), Ok(Value::Int(3.into())));
+    }
+
+    #[test]
+    fn test_host_functions() {
+        let mut functions = HostFunctions::new();
+        functions.insert("double".to_string(), Box::new(|arguments: &[Value]| match arguments {
+            [Value::Int(value)] => Ok(Value::Int(value * 2)),
+            _ => Err(CalcError::TypeError("double takes one integer".to_string())),
+        }));
+        let mut environment = HashMap::new();
+        assert_eq!(interpret_with_host_functions("double(3) + 1", &mut environment, &functions), Ok(Value::Int(7.into())));
+        assert_eq!(interpret_with_host_functions("def quad(n) = double(double(n))", &mut environment, &functions).map(|_| ()), Ok(()));
+        assert_eq!(interpret_with_host_functions("quad(5)", &mut environment, &functions), Ok(Value::Int(20.into())));
+        assert_eq!(
+            interpret_with_host_functions("double(1, 2)", &mut environment, &functions),
+            Err(CalcError::TypeError("double takes one integer".to_string()))
+        );
+        // Bindings in the environment shadow host functions.
+        assert_eq!(interpret_with_host_functions("def double(n) = n", &mut environment, &functions).map(|_| ()), Ok(()));
+        assert_eq!(interpret_with_host_functions("double(3)", &mut environment, &functions), Ok(Value::Int(3.into())));
+        assert_eq!(interpret_with_environment("triple(3)", &mut environment), Err(CalcError::UndefinedVariable("triple".to_string())));
+    }
+}
+
+#[cfg(all(test, feature = "bignum"))]
+mod bignum_tests {
+    use super::*;
+
+    #[test]
+    fn test_large_power() {
+        let result = interpret_expressions(vec![
+            "def pow(b, e) = e == 0 ? 1 : e // 2 * 2 == e ? pow(b * b, e // 2) : b * pow(b, e - 1)",
+            "pow(2, 200)",
+        ]);
+        let expected: Integer = "1606938044258990275541962092341162602522202993782792835301376".parse().unwrap();
+        assert_eq!(result, Ok(Value::Int(expected.clone())));
+        assert_eq!(interpret("2 ^ 200"), Ok(Value::Int(expected)));
+        assert_eq!(interpret("(0 - 1) ^ 10000000000001"), Ok(Value::Int(Integer::from(-1))));
+        assert_eq!(interpret("2 ^ 32768"), Ok(Value::Int(Integer::from(1) << 32768)));
+        assert_eq!(interpret("2 ^ 100000"), Err(CalcError::PowerTooLarge));
+        assert_eq!(interpret("3 ^ 50000"), Err(CalcError::PowerTooLarge));
+        assert_eq!(interpret("2 ^ 10000000000"), Err(CalcError::PowerTooLarge));
+    }
+
+    #[test]
+    fn test_division() {
+        assert_eq!(interpret("7 / 2"), Ok(Value::Float(3.5)));
+        assert_eq!(interpret("(0 - 7) // 2"), Ok(Value::Int(Integer::from(-4))));
+        assert_eq!(interpret("2 ^ 2000 * 1.0"), Ok(Value::Float(f64::INFINITY)));
+        assert_eq!(interpret("(0 - 2) ^ 2001 * 1.0"), Ok(Value::Float(f64::NEG_INFINITY)));
+    }
+
+    #[test]
+    fn test_wide_shift() {
+        let expected: Integer = "1267650600228229401496703205376".parse().unwrap();
+        assert_eq!(interpret("1 << 100"), Ok(Value::Int(expected)));
+        assert_eq!(interpret("1 << 65536"), Err(CalcError::ShiftOutOfRange(Integer::from(65536))));
+    }
+
+    #[test]
+    fn test_no_overflow() {
+        let expected: Integer = "1000000000000000000000000000000000000".parse().unwrap();
+        assert_eq!(interpret("1000000000000 * 1000000000000 * 1000000000000"), Ok(Value::Int(expected)));
+    }
+}
+
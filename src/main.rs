@@ -1,360 +1,195 @@
-use std::cell::Cell;
-use std::rc::Rc;
-use std::fmt;
-use std::collections::HashMap;
-
-struct TokenizerImpl {
-    input: &'static str,
-    current_index: Cell<usize>,
+use std::env;
+use std::io::{self, BufRead};
+use std::process;
+
+use simple_rust_calculator::{operator_table, parse_str, pretty_print_ast, tokenize_all, Associativity, Calculator, CalcError, Integer, Value};
+
+const USAGE: &str = "usage: simple-rust-calculator [--radix bin|oct|dec|hex] [--precision digits] [--tokens | --ast] [expression]";
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Radix {
+    Binary,
+    Octal,
+    Decimal,
+    Hex,
+}
+
+impl Radix {
+    fn from_name(name: &str) -> Option<Radix> {
+        match name {
+            "bin" | "2" => Some(Radix::Binary),
+            "oct" | "8" => Some(Radix::Octal),
+            "dec" | "10" => Some(Radix::Decimal),
+            "hex" | "16" => Some(Radix::Hex),
+            _ => None,
+        }
+    }
 }
 
-#[derive(Clone, Eq, PartialEq, Debug)]
-enum Token {
-    Int(i32),
-    OpenParen,
-    ClosedParen,
-    Operator(&'static str),
-    Identifier(String),
-    InputEnd,
+#[cfg(not(feature = "bignum"))]
+fn magnitude(value: &Integer) -> u64 {
+    value.unsigned_abs()
 }
 
-trait Tokenizer {
-    fn new(name: &'static str) -> Self;
-    fn next_token(&self) -> Token;
+#[cfg(feature = "bignum")]
+fn magnitude(value: &Integer) -> &num_bigint::BigUint {
+    value.magnitude()
 }
 
-impl Tokenizer for TokenizerImpl {
-    fn new(input: &'static str) -> TokenizerImpl {
-        TokenizerImpl { input: input, current_index: Cell::new(0), }
-    }
-    fn next_token(&self) -> Token {
-        match self.input.chars().nth(self.current_index.get()) {
-            Some('(') => {
-                self.current_index.set(self.current_index.get() + 1);
-                Token::OpenParen
-            },
-            Some(')') => {
-                self.current_index.set(self.current_index.get() + 1);
-                Token::ClosedParen
-            }
-            Some('+') => {
-                self.current_index.set(self.current_index.get() + 1);
-                Token::Operator("+")
-            },
-            Some('-') => {
-                self.current_index.set(self.current_index.get() + 1);
-                Token::Operator("-")
-            },
-            Some('*') => {
-                self.current_index.set(self.current_index.get() + 1);
-                Token::Operator("*")
-            },
-            Some('/') => {
-                self.current_index.set(self.current_index.get() + 1);
-                Token::Operator("/")
-            },
-            Some('=') => {
-                self.current_index.set(self.current_index.get() + 1);
-                Token::Operator("=")
-            },
-            Some('a'..='z') | Some('A'..='Z') => {
-                let mut identifier = String::new();
-                while let Some(c) = self.input.chars().nth(self.current_index.get()) {
-                    if c.is_alphabetic() {
-                        identifier.push(c);
-                        self.current_index.set(self.current_index.get() + 1);
-                    } else {
-                        break;
-                    }
-                }
-                let result = identifier.clone().to_owned();
-                Token::Identifier(result)
-            },
-            Some('0'..='9') => {
-                let mut number = String::new();
-                while let Some(c) = self.input.chars().nth(self.current_index.get()) {
-                    if c.is_digit(10) {
-                        number.push(c);
-                        self.current_index.set(self.current_index.get() + 1);
-                    } else {
-                        break;
-                    }
-                }
-                Token::Int(number.parse::<i32>().unwrap())
-            },
-            Some(' ' | '\r' | '\n' | '\t') => {
-                self.current_index.set(self.current_index.get() + 1);
-                self.next_token()
-            },
-            None => Token::InputEnd,
-            x => panic!("Invalid character {}", x.unwrap()),
-        }
+// Integers in a radix other than decimal get a `0b`/`0o`/`0x` prefix. A negative one is written
+// as a minus sign before the prefixed magnitude (`-0xff`) rather than in two's complement, which
+// would depend on the width of the integer type. Floats are rounded to `precision` digits after
+// the point, if given. Other values print as usual.
+fn format_value(value: &Value, radix: Radix, precision: Option<usize>) -> String {
+    let integer = match (value, precision) {
+        (Value::Int(integer), _) if radix != Radix::Decimal => integer,
+        (_, Some(precision)) => return format!("{:.*}", precision, value),
+        (_, None) => return value.to_string(),
+    };
+    let sign = if *integer < Integer::default() { "-" } else { "" };
+    let magnitude = magnitude(integer);
+    match radix {
+        Radix::Binary => format!("{}0b{:b}", sign, magnitude),
+        Radix::Octal => format!("{}0o{:o}", sign, magnitude),
+        Radix::Hex => format!("{}0x{:x}", sign, magnitude),
+        Radix::Decimal => unreachable!(),
     }
 }
 
-fn tokenize_all(input: &'static str) -> Vec<Token> {
-    let tokenizer = TokenizerImpl::new(input);
-    let mut tokens = Vec::new();
-    loop {
-        let token = tokenizer.next_token();
-        tokens.push(token.clone());
-        if let Token::InputEnd = token.clone() {
-            break;
-        }
-    }
-    tokens
+// What to print in place of the value, for diagnosing the tokenizer or the parser.
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Dump {
+    Tokens,
+    Ast,
 }
 
-fn parse(tokens: Vec<Token>) -> AstNode {
-    let mut current_index = 0;
-    fn parse_expression(tokens: &Vec<Token>, current_index: &mut usize) -> AstNode {
-        let mut node = parse_additive(tokens, current_index);
-        loop {
-            match tokens[*current_index] {
-                Token::Operator("=") => {
-                    *current_index += 1;
-                    node = AstNode::Assign(Rc::new(node), Rc::new(parse_expression(tokens, current_index)));
-                },
-                _ => break,
-            }
-        }
-        node
-    }
-    fn parse_additive(tokens: &Vec<Token>, current_index: &mut usize) -> AstNode {
-        let mut node = parse_term(tokens, current_index);
-        loop {
-            match tokens[*current_index] {
-                Token::Operator("+") => {
-                    *current_index += 1;
-                    node = AstNode::Add(Rc::new(node), Rc::new(parse_term(tokens, current_index)));
-                },
-                Token::Operator("-") => {
-                    *current_index += 1;
-                    node = AstNode::Subtract(Rc::new(node), Rc::new(parse_term(tokens, current_index)));
-                },
-                _ => break,
-            }
-        }
-        node
-    }
-    fn parse_term(tokens: &Vec<Token>, current_index: &mut usize) -> AstNode {
-        let mut node = parse_factor(tokens, current_index);
-        loop {
-            match tokens[*current_index] {
-                Token::Operator("*") => {
-                    *current_index += 1;
-                    node = AstNode::Multiply(Rc::new(node), Rc::new(parse_factor(tokens, current_index)));
-                },
-                Token::Operator("/") => {
-                    *current_index += 1;
-                    node = AstNode::Divide(Rc::new(node), Rc::new(parse_factor(tokens, current_index)));
-                },
-                _ => break,
-            }
-        }
-        node
-    }
-    fn parse_factor(tokens: &Vec<Token>, current_index: &mut usize) -> AstNode {
-        match tokens[*current_index].clone() {
-            Token::Int(value) => {
-                *current_index += 1;
-                AstNode::IntLiteral(value)
-            },
-            Token::OpenParen => {
-                *current_index += 1;
-                let node = parse_expression(tokens, current_index);
-                match tokens[*current_index] {
-                    Token::ClosedParen => {
-                        *current_index += 1;
-                        node
-                    },
-                    _ => panic!("Expected closing parenthesis"),
-                }
-            },
-            Token::Identifier(identifier) => {
-                *current_index += 1;
-                AstNode::Identifier(identifier)
-            },
-            _ => panic!("Expected integer or opening parenthesis")
-        }
-    }
-    parse_expression(&tokens, &mut current_index)
+// The tokens of the input one per line, ending with `InputEnd`.
+fn dump_tokens(input: &str) -> String {
+    tokenize_all(input).iter().map(|token| format!("{}\n", token)).collect()
 }
 
-fn interpret(input: &'static str) -> i32 {
-    let tokens = tokenize_all(input);
-    let ast = parse(tokens);
-    evaluate(Rc::new(ast))
+fn dump(kind: Dump, input: &str) -> Result<String, CalcError> {
+    match kind {
+        Dump::Tokens => Ok(dump_tokens(input)),
+        Dump::Ast => Ok(pretty_print_ast(&parse_str(input)?, 0)),
+    }
 }
 
-fn interpret_with_environment(input: &'static str, environment: &mut HashMap<String, i32>) -> i32 {
-    let tokens = tokenize_all(input);
-    let ast = parse(tokens);
-    evaluate_expression(Rc::new(ast), environment)
+// One operator per line, loosest-binding first, for the `:ops` command.
+fn operator_help() -> String {
+    operator_table().iter().map(|operator| {
+        let kind = match (operator.arity, operator.associativity) {
+            (1, Associativity::Right) => "prefix",
+            (1, Associativity::Left) => "postfix",
+            (3, _) => "ternary",
+            (_, Associativity::Left) => "left",
+            (_, Associativity::Right) => "right",
+        };
+        format!("{:>3}  {:<3} {}\n", operator.precedence, operator.symbol, kind)
+    }).collect()
 }
 
-fn interpret_expressions(inputs: Vec<&'static str>) -> i32 {
-    let mut result = 0;
-    let mut environment = HashMap::new();
-    for input in inputs {
-        result = interpret_with_environment(input, &mut environment);
-    }
-    result
+fn fail(message: &str) -> ! {
+    eprintln!("{}", message);
+    process::exit(2);
 }
 
-
-impl fmt::Display for Token {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        match self {
-            Token::Int(value) => write!(f, "Int({})", value),
-            Token::OpenParen => write!(f, "OpenParen"),
-            Token::ClosedParen => write!(f, "ClosedParen"),
-            Token::Operator(value) => write!(f, "Operator({})", value),
-            Token::InputEnd => write!(f, "InputEnd"),
-            Token::Identifier(value) => write!(f, "Identifier({})", value),
+// Evaluates the expression given on the command line, or else reads one per line from standard
+// input, where `:radix <name>` switches the output radix and `:ops` lists the operators. With
+// `--tokens` or `--ast`, each expression's tokens or syntax tree are printed instead of its value.
+fn main() {
+    let mut radix = Radix::Decimal;
+    let mut precision = None;
+    let mut dump_kind = None;
+    let mut expression = Vec::new();
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--radix" => {
+                let name = args.next().unwrap_or_else(|| fail(USAGE));
+                radix = Radix::from_name(&name).unwrap_or_else(|| fail(USAGE));
+            },
+            "--precision" => {
+                let digits = args.next().unwrap_or_else(|| fail(USAGE));
+                precision = Some(digits.parse().unwrap_or_else(|_| fail(USAGE)));
+            },
+            "--tokens" => dump_kind = Some(Dump::Tokens),
+            "--ast" => dump_kind = Some(Dump::Ast),
+            "--help" | "-h" => {
+                println!("{}", USAGE);
+                return;
+            },
+            _ => expression.push(arg),
         }
     }
-}
-
-#[derive(Clone, Eq, PartialEq, Debug)]
-enum AstNode {
-    Add(Rc<AstNode>, Rc<AstNode>),
-    Subtract(Rc<AstNode>, Rc<AstNode>),
-    Multiply(Rc<AstNode>, Rc<AstNode>),
-    Divide(Rc<AstNode>, Rc<AstNode>),
-    Assign(Rc<AstNode>, Rc<AstNode>),
-    Identifier(String),
-    IntLiteral(i32),
-}
-
-fn evaluate_expression(node: Rc<AstNode>, environment: &mut HashMap<String, i32>) -> i32 {
-    match *node {
-        AstNode::Add(ref left, ref right) =>
-            evaluate_expression(left.clone(), environment) + evaluate_expression(right.clone(), environment),
-        AstNode::Subtract(ref left, ref right) =>
-            evaluate_expression(left.clone(), environment) - evaluate_expression(right.clone(), environment),
-        AstNode::Multiply(ref left, ref right) =>
-            evaluate_expression(left.clone(), environment) * evaluate_expression(right.clone(), environment),
-        AstNode::Divide(ref left, ref right) =>
-            evaluate_expression(left.clone(), environment) / evaluate_expression(right.clone(), environment),
-        AstNode::IntLiteral(value) =>
-            value,
-        AstNode::Assign(ref left, ref right) => {
-            if let AstNode::Identifier(ref identifier) = **left {
-                let value = evaluate_expression(right.clone(), environment);
-                environment.insert(identifier.to_string(), value);
-                value
-            } else {
-                panic!("Expected identifier on left side of assignment");
+    let mut calculator = Calculator::new();
+    if !expression.is_empty() {
+        let input = expression.join(" ");
+        let result = match dump_kind {
+            Some(kind) => dump(kind, &input).map(|text| print!("{}", text)),
+            None => calculator.interpret(&input).map(|value| println!("{}", format_value(&value, radix, precision))),
+        };
+        if let Err(error) = result {
+            eprintln!("{}", error.render(&input));
+            process::exit(1);
+        }
+        return;
+    }
+    for line in io::stdin().lock().lines() {
+        let line = line.unwrap_or_else(|error| fail(&error.to_string()));
+        let input = line.trim();
+        if input.is_empty() {
+            continue;
+        }
+        if let Some(kind) = dump_kind {
+            match dump(kind, input) {
+                Ok(text) => print!("{}", text),
+                Err(error) => eprintln!("{}", error.render(input)),
             }
-        },
-        AstNode::Identifier(ref identifier) => {
-            if let Some(value) = environment.get(identifier) {
-                *value
-            } else {
-                panic!("Unknown identifier {}", identifier);
+            continue;
+        }
+        if input == ":ops" {
+            print!("{}", operator_help());
+            continue;
+        }
+        if let Some(name) = input.strip_prefix(":radix") {
+            match Radix::from_name(name.trim()) {
+                Some(chosen) => radix = chosen,
+                None => eprintln!("error: unknown radix {}", name.trim()),
             }
-        },
+            continue;
+        }
+        match calculator.interpret(input) {
+            Ok(value) => println!("{}", format_value(&value, radix, precision)),
+            Err(error) => eprintln!("{}", error.render(input)),
+        }
     }
 }
 
-fn evaluate(node: Rc<AstNode>) -> i32 {
-    let mut environment = HashMap::new();
-    evaluate_expression(node, &mut environment)
-}
-
 #[cfg(test)]
 mod tests {
     use super::*;
 
     #[test]
-    fn test_int_literal() {
-        let node = Rc::new(AstNode::IntLiteral(1));
-        assert_eq!(evaluate(node), 1);
-    }
-
-    #[test]
-    fn test_add() {
-        let node = Rc::new(AstNode::Add(Rc::new(AstNode::IntLiteral(1)), Rc::new(AstNode::IntLiteral(2))));
-        assert_eq!(evaluate(node), 3);
-    }
-
-    #[test]
-    fn test_subtract() {
-        let node = Rc::new(AstNode::Subtract(Rc::new(AstNode::IntLiteral(1)), Rc::new(AstNode::IntLiteral(2))));
-        assert_eq!(evaluate(node), -1);
-    }
-
-    #[test]
-    fn test_multiply() {
-        let node = Rc::new(AstNode::Multiply(Rc::new(AstNode::IntLiteral(2)), Rc::new(AstNode::IntLiteral(3))));
-        assert_eq!(evaluate(node), 6);
-    }
-
-    #[test]
-    fn test_divide() {
-        let node = Rc::new(AstNode::Divide(Rc::new(AstNode::IntLiteral(6)), Rc::new(AstNode::IntLiteral(2))));
-        assert_eq!(evaluate(node), 3);
+    fn test_format_value() {
+        assert_eq!(format_value(&Value::Int(255.into()), Radix::Hex, None), "0xff");
+        assert_eq!(format_value(&Value::Int(5.into()), Radix::Binary, None), "0b101");
+        assert_eq!(format_value(&Value::Int(8.into()), Radix::Octal, None), "0o10");
+        assert_eq!(format_value(&Value::Int((-255).into()), Radix::Hex, None), "-0xff");
+        assert_eq!(format_value(&Value::Int("-9223372036854775808".parse().unwrap()), Radix::Hex, None), "-0x8000000000000000");
+        assert_eq!(format_value(&Value::Int((-7).into()), Radix::Decimal, None), "-7");
+        assert_eq!(format_value(&Value::Float(2.5), Radix::Hex, None), "2.5");
+        assert_eq!(format_value(&Value::Float(1.0 / 3.0), Radix::Decimal, Some(3)), "0.333");
+        assert_eq!(format_value(&Value::Int(255.into()), Radix::Hex, Some(3)), "0xff");
+        assert_eq!(Radix::from_name("bin"), Some(Radix::Binary));
+        assert_eq!(Radix::from_name("base64"), None);
     }
 
     #[test]
-    // (1 + 2) * (3 - 6) = -9
-    fn test_complex_expression() {
-        let node = Rc::new(AstNode::Multiply(
-            Rc::new(AstNode::Add(Rc::new(AstNode::IntLiteral(1)), Rc::new(AstNode::IntLiteral(2)))),
-            Rc::new(AstNode::Subtract(Rc::new(AstNode::IntLiteral(3)), Rc::new(AstNode::IntLiteral(6))))
-        ));
-        assert_eq!(evaluate(node), -9);
+    fn test_dump_tokens() {
+        let dump = dump_tokens("(1+2)*3");
+        assert_eq!(dump.lines().count(), 8);
+        assert_eq!(dump.lines().nth(2), Some("Operator(+)"));
+        assert_eq!(dump.lines().last(), Some("InputEnd"));
     }
 }
-
-#[cfg(test)]
-mod all_tests {
-    use super::*;
-
-    #[test]
-    fn test_tokenize_all() {
-        let tokens = tokenize_all("(1 + 2) * (3 - 6)");
-        assert_eq!(tokens.len(), 12);
-        assert_eq!(tokens[0], Token::OpenParen);
-        assert_eq!(tokens[1], Token::Int(1));
-        assert_eq!(tokens[2], Token::Operator("+"));
-        assert_eq!(tokens[3], Token::Int(2));
-        assert_eq!(tokens[4], Token::ClosedParen);
-        assert_eq!(tokens[5], Token::Operator("*"));
-        assert_eq!(tokens[6], Token::OpenParen);
-        assert_eq!(tokens[7], Token::Int(3));
-        assert_eq!(tokens[8], Token::Operator("-"));
-        assert_eq!(tokens[9], Token::Int(6));
-        assert_eq!(tokens[10], Token::ClosedParen);
-        assert_eq!(tokens[11], Token::InputEnd);
-    }
-
-    #[test]
-    fn test_parse() {
-        let tokens = tokenize_all("(1 + 2) * (3 - 6)");
-        let ast = parse(tokens);
-        assert_eq!(ast, AstNode::Multiply(
-            Rc::new(AstNode::Add(Rc::new(AstNode::IntLiteral(1)), Rc::new(AstNode::IntLiteral(2)))),
-            Rc::new(AstNode::Subtract(Rc::new(AstNode::IntLiteral(3)), Rc::new(AstNode::IntLiteral(6))))
-        ));
-    }
-
-    #[test]
-    fn test_interpret() {
-        assert_eq!(interpret("(1 + 2) * (3 - 6)"), -9);
-        assert_eq!(interpret("(1 + 3) * (4 * 2)"), 32);
-        assert_eq!(interpret("x = (1 + 3) * (4 * 2)"), 32);
-    }
-
-    #[test]
-    fn test_interpret_expressions() {
-        assert_eq!(interpret_expressions(vec!["x = 1", "y = 2", "x + y"]), 3);
-    }
-}
-
-fn main() {
-    tokenize_all("(1 + 2) * (3 - 6)");
-    println!("1 = {}", evaluate(Rc::new(AstNode::IntLiteral(1))));
-}
-
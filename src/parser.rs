@@ -0,0 +1,1058 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::rc::Rc;
+
+use crate::ast::{comparison_parts, has_embedded_assignment, AstNode};
+use crate::error::CalcError;
+use crate::evaluator::EvalOptions;
+use crate::tokenizer::{tokenize_with_locale, tokenize_with_positions, Op, Token};
+
+// How deeply parentheses, blocks, `!`s and the like may nest before parsing gives up with
+// `NestingTooDeep`. Each level costs several recursive-descent frames, so this leaves headroom
+// even on a 2 MiB thread stack in an unoptimized build.
+pub const DEFAULT_MAX_DEPTH: usize = 100;
+
+pub fn parse(tokens: Vec<Token>) -> Result<AstNode, CalcError> {
+    parse_tokens(&tokens)
+}
+
+// Borrows the tokens, so the same slice can be parsed again. Bare tokens carry no source
+// positions, so errors from here come without one.
+pub fn parse_tokens(tokens: &[Token]) -> Result<AstNode, CalcError> {
+    parse_with_max_depth(tokens, DEFAULT_MAX_DEPTH)
+}
+
+// The tokens without comments and ending in `InputEnd`, if they are not already. The parser relies
+// on `InputEnd` as a sentinel, but tokens collected from the tokenizer's iterator stop just before
+// it; comments, kept only for tools like formatters, mean nothing to the parser.
+fn parseable(tokens: &[Token]) -> Option<Vec<Token>> {
+    let has_comments = tokens.iter().any(|token| matches!(token, Token::Comment(_)));
+    if !has_comments && tokens.last() == Some(&Token::InputEnd) {
+        return None;
+    }
+    let mut cleaned: Vec<Token> = tokens.iter().filter(|token| !matches!(token, Token::Comment(_))).cloned().collect();
+    if cleaned.last() != Some(&Token::InputEnd) {
+        cleaned.push(Token::InputEnd);
+    }
+    Some(cleaned)
+}
+
+pub fn parse_with_max_depth(tokens: &[Token], max_depth: usize) -> Result<AstNode, CalcError> {
+    if let Some(tokens) = parseable(tokens) {
+        return parse_with_max_depth(&tokens, max_depth);
+    }
+    Ok(parse_with_warnings(tokens, &[], max_depth)?.0)
+}
+
+// Parses `;`-separated statements, but rather than stopping at the first error, records it, skips
+// to the next `;` outside any brackets and carries on, so one pass reports the first error of every
+// statement. A single statement comes back as itself, several as a block. As with `parse_tokens`,
+// errors come without positions.
+pub fn parse_all_errors(tokens: &[Token]) -> Result<AstNode, Vec<CalcError>> {
+    if let Some(tokens) = parseable(tokens) {
+        return parse_all_errors(&tokens);
+    }
+    check_not_empty(tokens).map_err(|error| vec![error])?;
+    let mut statements = Vec::new();
+    let mut errors = Vec::new();
+    let mut parser = Parser::new(tokens, &[], DEFAULT_MAX_DEPTH);
+    while *parser.peek() != Token::InputEnd {
+        let start = parser.pos;
+        // A failed statement may have left its nesting unbalanced.
+        parser.depth = 0;
+        let statement = parser.parse_expression().and_then(|statement| match parser.peek() {
+            Token::Semicolon | Token::InputEnd => Ok(statement),
+            _ => Err(parser.leftover()),
+        });
+        match statement {
+            Ok(statement) => statements.push(statement),
+            Err(error) => {
+                errors.push(error);
+                parser.pos = statement_end(tokens, start);
+            },
+        }
+        if *parser.peek() == Token::Semicolon {
+            parser.advance();
+        }
+    }
+    match statements.len() {
+        _ if !errors.is_empty() => Err(errors),
+        1 => Ok(statements.pop().unwrap()),
+        _ => Ok(AstNode::Block(statements.into_iter().map(Rc::new).collect())),
+    }
+}
+
+// The index of the `;` or `InputEnd` ending the statement that starts at `start`.
+fn statement_end(tokens: &[Token], start: usize) -> usize {
+    let mut depth = 0;
+    let mut index = start;
+    loop {
+        match token_at(tokens, index) {
+            Token::OpenParen | Token::OpenBrace => depth += 1,
+            Token::ClosedParen | Token::CloseBrace => depth -= 1,
+            Token::Semicolon if depth <= 0 => return index,
+            Token::InputEnd => return index,
+            _ => {},
+        }
+        index += 1;
+    }
+}
+
+// Parses source text directly, so errors know where in `input` they occurred.
+pub fn parse_str(input: &str) -> Result<AstNode, CalcError> {
+    let (tokens, positions) = tokenize_split(input);
+    Ok(parse_with_warnings(&tokens, &positions, DEFAULT_MAX_DEPTH)?.0)
+}
+
+// Like `parse_str`, but wraps variable reads, calls, factorials and arithmetic operators in
+// `Located` nodes, so errors evaluating them carry their offset in `input`: the name's for reads
+// and calls, the operator's otherwise.
+pub fn parse_located(input: &str) -> Result<AstNode, CalcError> {
+    let (tokens, positions) = tokenize_split(input);
+    check_not_empty(&tokens)?;
+    let mut parser = Parser::new(&tokens, &positions, DEFAULT_MAX_DEPTH);
+    parser.locate = true;
+    parser.parse_top_level()
+}
+
+// Remembers the AST of every input parsed through it, so re-evaluating the same text skips
+// tokenizing and parsing. Inputs that fail to parse are not remembered.
+#[derive(Default)]
+pub struct ParseCache {
+    asts: HashMap<String, Rc<AstNode>>,
+}
+
+impl ParseCache {
+    pub fn new() -> ParseCache {
+        ParseCache::default()
+    }
+
+    pub fn parse_cached(&mut self, input: &str) -> Result<Rc<AstNode>, CalcError> {
+        if let Some(ast) = self.asts.get(input) {
+            return Ok(ast.clone());
+        }
+        let ast = Rc::new(parse_str(input)?);
+        self.asts.insert(input.to_string(), ast.clone());
+        Ok(ast)
+    }
+}
+
+// Like `parse_str`, with the syntax the options ask for. Under `implicit_mul` a number, name or
+// `(` where an operator should be multiplies, at `*`'s precedence, so `2x^2` is `2 * (x ^ 2)`.
+pub(crate) fn parse_with_options(input: &str, options: EvalOptions) -> Result<AstNode, CalcError> {
+    let (tokens, positions): (Vec<Token>, Vec<usize>) = tokenize_with_locale(input, options.locale).into_iter().unzip();
+    check_not_empty(&tokens)?;
+    let mut parser = Parser::new(&tokens, &positions, DEFAULT_MAX_DEPTH);
+    parser.implicit_mul = options.implicit_mul;
+    if options.locale.decimal_comma {
+        parser.separator = Token::Semicolon;
+    }
+    let node = parser.parse_top_level()?;
+    if !options.allow_embedded_assignment && has_embedded_assignment(&node) {
+        return Err(CalcError::EmbeddedAssignment);
+    }
+    Ok(node)
+}
+
+fn tokenize_split(input: &str) -> (Vec<Token>, Vec<usize>) {
+    tokenize_with_positions(input).into_iter().unzip()
+}
+
+// The recursive descent: a cursor into the tokens, which must end with `InputEnd`, plus the lint
+// warnings collected so far, the current nesting depth with its cap, and whether to build
+// `Located` nodes.
+struct Parser<'a> {
+    tokens: &'a [Token],
+    // Each token's character offset in the input; empty for bare tokens.
+    positions: &'a [usize],
+    pos: usize,
+    warnings: Vec<Warning>,
+    depth: usize,
+    max_depth: usize,
+    locate: bool,
+    // Whether an operand straight after another, as in `2(3 + 4)` or `3x`, multiplies it.
+    implicit_mul: bool,
+    // What separates arguments, parameters and `let` bindings: `,`, or `;` under a decimal-comma
+    // locale.
+    separator: Token,
+}
+
+impl<'a> Parser<'a> {
+    fn new(tokens: &'a [Token], positions: &'a [usize], max_depth: usize) -> Parser<'a> {
+        Parser {
+            tokens,
+            positions,
+            pos: 0,
+            warnings: Vec::new(),
+            depth: 0,
+            max_depth,
+            locate: false,
+            implicit_mul: false,
+            separator: Token::Comma,
+        }
+    }
+
+    fn peek(&self) -> &'a Token {
+        token_at(self.tokens, self.pos)
+    }
+
+    // Moves past the current token and returns it. `InputEnd` is never passed, since every
+    // caller checks for what it expects first.
+    fn advance(&mut self) -> &'a Token {
+        let token = self.peek();
+        self.pos += 1;
+        token
+    }
+
+    fn expect(&mut self, token: Token) -> Result<(), CalcError> {
+        if *self.peek() != token {
+            return Err(self.unexpected());
+        }
+        self.advance();
+        Ok(())
+    }
+
+    fn expect_identifier(&mut self) -> Result<String, CalcError> {
+        match self.peek() {
+            Token::Identifier(name) => {
+                self.advance();
+                Ok(name.clone())
+            },
+            _ => Err(self.unexpected()),
+        }
+    }
+
+    // The error for the current token, which is not what the grammar allows here.
+    fn unexpected(&self) -> CalcError {
+        unexpected(self.tokens, self.positions, self.pos)
+    }
+
+    // The error for a token that cannot start an operand. Anything that ends an operand there (the
+    // end of the input, a `;`, a `,`, a closing bracket) or an operator means an operator went
+    // without one: the operator just before, if any, as in `1 + )`, or else the one found, as in
+    // `* 3`.
+    fn missing_operand(&self) -> CalcError {
+        let found = self.peek();
+        let ends_operand = matches!(found, Token::InputEnd | Token::Semicolon | Token::Comma | Token::ClosedParen | Token::CloseBrace);
+        if !ends_operand && !matches!(found, Token::Operator(_)) {
+            return self.unexpected();
+        }
+        let index = match self.pos.checked_sub(1).map(|index| (index, token_at(self.tokens, index))) {
+            Some((index, Token::Operator(_))) => index,
+            _ => self.pos,
+        };
+        match token_at(self.tokens, index) {
+            Token::Operator(operator) => CalcError::ExpectedOperand { operator: *operator, position: self.positions.get(index).copied() },
+            _ => self.unexpected(),
+        }
+    }
+
+    // Wraps the node starting at token `index` in its source position, when locating.
+    fn located(&self, index: usize, node: AstNode) -> AstNode {
+        match self.positions.get(index) {
+            Some(&position) if self.locate => AstNode::Located(position, Rc::new(node)),
+            _ => node,
+        }
+    }
+
+    // Called on the way into a nested construct starting at the current token; the caller steps
+    // back out with `leave`. An error ends the whole parse, so it needs no matching `leave`.
+    fn enter(&mut self) -> Result<(), CalcError> {
+        if self.depth == self.max_depth {
+            return Err(CalcError::NestingTooDeep { position: self.positions.get(self.pos).copied() });
+        }
+        self.depth += 1;
+        Ok(())
+    }
+
+    fn leave(&mut self) {
+        self.depth -= 1;
+    }
+
+    // `;`-separated statements up to (but not consuming) `end`; a trailing `;` is allowed.
+    fn parse_statements(&mut self, end: Token) -> Result<Vec<AstNode>, CalcError> {
+        let mut statements = Vec::new();
+        loop {
+            statements.push(self.parse_expression()?);
+            match self.peek() {
+                Token::Semicolon => {
+                    self.advance();
+                    if *self.peek() == end {
+                        break;
+                    }
+                },
+                token if *token == end => break,
+                _ => return Err(self.leftover()),
+            }
+        }
+        Ok(statements)
+    }
+
+    // A whole input's expression, which must use up the input.
+    fn parse_top_level(&mut self) -> Result<AstNode, CalcError> {
+        let node = self.parse_expression()?;
+        match self.peek() {
+            Token::InputEnd => Ok(node),
+            _ => Err(self.leftover()),
+        }
+    }
+
+    // The error for what follows a complete statement where its end should be. A `)` there can
+    // only be unmatched, and the end of the input leaves a block open; any other token, as the `2`
+    // in `1 2`, is trailing.
+    fn leftover(&self) -> CalcError {
+        match self.peek() {
+            Token::InputEnd | Token::ClosedParen | Token::Invalid(_) | Token::Malformed(_) => self.unexpected(),
+            _ => CalcError::TrailingTokens { position: self.positions.get(self.pos).copied() },
+        }
+    }
+
+    // Every nested construct (parentheses, blocks, arguments, right-hand sides) comes back through
+    // here, so this is where nesting depth is counted.
+    fn parse_expression(&mut self) -> Result<AstNode, CalcError> {
+        self.enter()?;
+        let node = match self.peek() {
+            Token::Keyword("def") => {
+                self.advance();
+                self.parse_function_definition()?
+            },
+            Token::Keyword("const") => {
+                self.advance();
+                self.parse_const_declaration()?
+            },
+            Token::Keyword("let") => {
+                self.advance();
+                self.parse_let()?
+            },
+            Token::Keyword("if") => {
+                self.advance();
+                self.parse_if()?
+            },
+            _ => self.parse_binary(0)?,
+        };
+        self.leave();
+        Ok(node)
+    }
+
+    // const name = value
+    fn parse_const_declaration(&mut self) -> Result<AstNode, CalcError> {
+        let name = self.expect_identifier()?;
+        self.expect(Token::Operator(Op::Assign))?;
+        let value = self.parse_expression()?;
+        Ok(AstNode::ConstAssign(name, Rc::new(value)))
+    }
+
+    // let name = value, ... in body
+    // A value stops at the next `,` or `in`; an assignment or another `let` there needs parentheses.
+    fn parse_let(&mut self) -> Result<AstNode, CalcError> {
+        let mut bindings = Vec::new();
+        loop {
+            let name = self.expect_identifier()?;
+            self.expect(Token::Operator(Op::Assign))?;
+            let value = self.parse_binary(CONDITIONAL_PRECEDENCE)?;
+            bindings.push((name, Rc::new(value)));
+            match self.peek() {
+                token if *token == self.separator => self.advance(),
+                Token::Keyword("in") => break,
+                _ => return Err(self.unexpected()),
+            };
+        }
+        self.advance();
+        let body = self.parse_expression()?;
+        Ok(AstNode::Let(bindings, Rc::new(body)))
+    }
+
+    // if condition then value else value
+    // The same conditional as `condition ? value : value`; the `else` is required.
+    fn parse_if(&mut self) -> Result<AstNode, CalcError> {
+        let condition = self.parse_expression()?;
+        self.expect(Token::Keyword("then"))?;
+        let then_branch = self.parse_expression()?;
+        self.expect(Token::Keyword("else"))?;
+        let else_branch = self.parse_expression()?;
+        Ok(AstNode::Conditional(Rc::new(condition), Rc::new(then_branch), Rc::new(else_branch)))
+    }
+
+    // def name(param, ...) = body
+    fn parse_function_definition(&mut self) -> Result<AstNode, CalcError> {
+        let name = self.expect_identifier()?;
+        self.expect(Token::OpenParen)?;
+        let mut parameters = Vec::new();
+        if *self.peek() != Token::ClosedParen {
+            loop {
+                let position = self.positions.get(self.pos).copied();
+                let parameter = self.expect_identifier()?;
+                if parameters.contains(&parameter) {
+                    return Err(CalcError::DuplicateParameter { name: parameter, position });
+                }
+                parameters.push(parameter);
+                match self.peek() {
+                    token if *token == self.separator => self.advance(),
+                    Token::ClosedParen => break,
+                    _ => return Err(self.unexpected()),
+                };
+            }
+        }
+        self.advance();
+        self.expect(Token::Operator(Op::Assign))?;
+        let body = self.parse_expression()?;
+        Ok(AstNode::FunctionDef(name, parameters, Rc::new(body)))
+    }
+}
+
+fn parse_with_warnings(tokens: &[Token], positions: &[usize], max_depth: usize) -> Result<(AstNode, Vec<Warning>), CalcError> {
+    check_not_empty(tokens)?;
+    let mut parser = Parser::new(tokens, positions, max_depth);
+    let node = parser.parse_top_level()?;
+    Ok((node, parser.warnings))
+}
+
+// Parses `;`-separated statements; a trailing `;` is allowed.
+pub(crate) fn parse_program(input: &str) -> Result<Vec<AstNode>, CalcError> {
+    let (tokens, positions) = tokenize_split(input);
+    check_not_empty(&tokens)?;
+    Parser::new(&tokens, &positions, DEFAULT_MAX_DEPTH).parse_statements(Token::InputEnd)
+}
+
+fn check_not_empty(tokens: &[Token]) -> Result<(), CalcError> {
+    match tokens.first() {
+        Some(Token::InputEnd) | None => Err(CalcError::EmptyInput),
+        Some(_) => Ok(()),
+    }
+}
+
+// The token at `index`, or `InputEnd` past the last one. Every read of the tokens goes through
+// here, so a stream missing its `InputEnd` (or a cursor that overruns it) reads as the end of the
+// input instead of panicking.
+fn token_at(tokens: &[Token], index: usize) -> &Token {
+    static INPUT_END: Token = Token::InputEnd;
+    tokens.get(index).unwrap_or(&INPUT_END)
+}
+
+// Whether every `(` before `index` is already closed.
+fn parens_balanced(tokens: &[Token], index: usize) -> bool {
+    let tokens = &tokens[..index.min(tokens.len())];
+    let opened = tokens.iter().filter(|token| **token == Token::OpenParen).count();
+    let closed = tokens.iter().filter(|token| **token == Token::ClosedParen).count();
+    opened <= closed
+}
+
+// Malformed literals, invalid characters and unmatched `)`s surface here, wherever the parser
+// first trips over them.
+fn unexpected(tokens: &[Token], positions: &[usize], index: usize) -> CalcError {
+    let position = positions.get(index).copied();
+    match *token_at(tokens, index) {
+        Token::Malformed(ref text) => CalcError::MalformedNumber { text: text.clone(), position },
+        Token::Invalid(character) => CalcError::InvalidCharacter { character, position },
+        Token::ClosedParen if parens_balanced(tokens, index) => CalcError::UnexpectedCloseParen { position },
+        ref token => CalcError::UnexpectedToken { token: token.clone(), position },
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Associativity {
+    Left,
+    Right,
+}
+
+// How an operator builds its node: most wrap both operands directly; assignments check their
+// target, build their node with `store`, and desugar `x op= rhs` to `x = x op rhs`.
+#[derive(Clone, Copy)]
+enum Combine {
+    Node(Constructor),
+    Assign { store: Constructor, compound: Option<Constructor> },
+}
+
+pub(crate) type Constructor = fn(Rc<AstNode>, Rc<AstNode>) -> AstNode;
+
+struct BinaryOperator {
+    op: Op,
+    precedence: u8,
+    associativity: Associativity,
+    combine: Combine,
+}
+
+const fn operator(op: Op, precedence: u8, combine: Constructor) -> BinaryOperator {
+    BinaryOperator { op, precedence, associativity: Associativity::Left, combine: Combine::Node(combine) }
+}
+
+const fn right_operator(op: Op, precedence: u8, combine: Constructor) -> BinaryOperator {
+    BinaryOperator { op, precedence, associativity: Associativity::Right, combine: Combine::Node(combine) }
+}
+
+const fn assignment(op: Op, store: Constructor, compound: Option<Constructor>) -> BinaryOperator {
+    BinaryOperator { op, precedence: ASSIGNMENT_PRECEDENCE, associativity: Associativity::Right, combine: Combine::Assign { store, compound } }
+}
+
+pub(crate) const ASSIGNMENT_PRECEDENCE: u8 = 1;
+// `cond ? then : else` sits between assignment and `||`; it is a mixfix operator, so
+// `parse_binary` handles it by hand rather than through the table.
+pub(crate) const CONDITIONAL_PRECEDENCE: u8 = 2;
+pub(crate) const COMPARISON_PRECEDENCE: u8 = 5;
+const ADDITIVE_PRECEDENCE: u8 = 9;
+// Prefix `-` binds looser than `^` to its right, as in mathematics: `-2 ^ 2` is `-(2 ^ 2)`. It can
+// still start the right operand of `^`, as in `2 ^ -1`.
+pub(crate) const POWER_PRECEDENCE: u8 = 11;
+// Prefix `!` and `+` bind tighter than every binary operator, and postfix `!` and `%` tighter
+// still.
+pub(crate) const PREFIX_PRECEDENCE: u8 = 12;
+pub(crate) const POSTFIX_PRECEDENCE: u8 = 13;
+
+// Binary operators, loosest-binding first. A higher precedence binds tighter. As in Python, the
+// bitwise operators bind tighter than comparisons, so `x & 1 == 1` tests the masked bit.
+static BINARY_OPERATORS: [BinaryOperator; 25] = [
+    assignment(Op::Assign, AstNode::Assign, None),
+    assignment(Op::ColonAssign, AstNode::AssignReturnOld, None),
+    assignment(Op::PlusAssign, AstNode::Assign, Some(AstNode::Add)),
+    assignment(Op::MinusAssign, AstNode::Assign, Some(AstNode::Subtract)),
+    assignment(Op::StarAssign, AstNode::Assign, Some(AstNode::Multiply)),
+    assignment(Op::SlashAssign, AstNode::Assign, Some(AstNode::Divide)),
+    operator(Op::OrOr, 3, AstNode::Or),
+    operator(Op::AndAnd, 4, AstNode::And),
+    operator(Op::Equal, COMPARISON_PRECEDENCE, AstNode::Equal),
+    operator(Op::NotEqual, COMPARISON_PRECEDENCE, AstNode::NotEqual),
+    operator(Op::Less, COMPARISON_PRECEDENCE, AstNode::Less),
+    operator(Op::LessEqual, COMPARISON_PRECEDENCE, AstNode::LessEqual),
+    operator(Op::Greater, COMPARISON_PRECEDENCE, AstNode::Greater),
+    operator(Op::GreaterEqual, COMPARISON_PRECEDENCE, AstNode::GreaterEqual),
+    operator(Op::Pipe, 6, AstNode::BitOr),
+    operator(Op::Ampersand, 7, AstNode::BitAnd),
+    operator(Op::ShiftLeft, 8, AstNode::ShiftLeft),
+    operator(Op::ShiftRight, 8, AstNode::ShiftRight),
+    operator(Op::Plus, ADDITIVE_PRECEDENCE, AstNode::Add),
+    operator(Op::Minus, ADDITIVE_PRECEDENCE, AstNode::Subtract),
+    operator(Op::Star, 10, AstNode::Multiply),
+    operator(Op::Slash, 10, AstNode::Divide),
+    operator(Op::DoubleSlash, 10, AstNode::FloorDivide),
+    // Only infix: a `%` with no operand after it is `parse_postfix`'s percent.
+    operator(Op::Percent, 10, AstNode::Modulo),
+    // Right-associative, as in mathematics: `2 ^ 3 ^ 2` is `2 ^ 9`.
+    right_operator(Op::Caret, POWER_PRECEDENCE, AstNode::Power),
+];
+
+fn binary_operator(token: &Token) -> Option<&'static BinaryOperator> {
+    match *token {
+        Token::Operator(op) => BINARY_OPERATORS.iter().find(|operator| operator.op == op),
+        _ => None,
+    }
+}
+
+// How tightly `op` binds as a binary operator, and which way it associates.
+pub(crate) fn operator_binding(op: Op) -> Option<(u8, Associativity)> {
+    binary_operator(&Token::Operator(op)).map(|operator| (operator.precedence, operator.associativity))
+}
+
+// The node `op` builds as a binary operator; the assignment operators build none of their own.
+pub(crate) fn binary_node(op: Op) -> Option<Constructor> {
+    match binary_operator(&Token::Operator(op))?.combine {
+        Combine::Node(combine) => Some(combine),
+        Combine::Assign { .. } => None,
+    }
+}
+
+// One operator of the grammar, as listed by `operator_table`. A higher precedence binds tighter.
+// Prefix operators count as right-associative and postfix ones as left-associative, since that is
+// the way a run of them groups.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct OperatorInfo {
+    pub symbol: &'static str,
+    pub precedence: u8,
+    pub associativity: Associativity,
+    // 1 for prefix and postfix operators, 2 for binary ones, 3 for `?:`.
+    pub arity: usize,
+}
+
+// Every operator the parser knows, loosest-binding first. A symbol used both ways, like `!` or `+`,
+// has an entry for each.
+pub fn operator_table() -> Vec<OperatorInfo> {
+    let info = |symbol, precedence, associativity, arity| OperatorInfo { symbol, precedence, associativity, arity };
+    let mut table: Vec<OperatorInfo> = BINARY_OPERATORS.iter()
+        .map(|operator| info(operator.op.symbol(), operator.precedence, operator.associativity, 2))
+        .collect();
+    table.push(info("?:", CONDITIONAL_PRECEDENCE, Associativity::Right, 3));
+    table.push(info("!", PREFIX_PRECEDENCE, Associativity::Right, 1));
+    table.push(info("-", POWER_PRECEDENCE, Associativity::Right, 1));
+    table.push(info("+", PREFIX_PRECEDENCE, Associativity::Right, 1));
+    table.push(info("!", POSTFIX_PRECEDENCE, Associativity::Left, 1));
+    table.push(info("%", POSTFIX_PRECEDENCE, Associativity::Left, 1));
+    table.sort_by_key(|operator| operator.precedence);
+    table
+}
+
+// An operator binding tighter than `+`/`-` outside any parentheses in the given operand tokens
+// means precedence, not the user, decided the grouping.
+fn has_bare_multiplicative(tokens: &[Token]) -> bool {
+    let mut depth = 0;
+    for token in tokens {
+        match token {
+            Token::OpenParen | Token::OpenBrace => depth += 1,
+            Token::ClosedParen | Token::CloseBrace => depth -= 1,
+            token if depth == 0 && binary_operator(token).is_some_and(|operator| operator.precedence > ADDITIVE_PRECEDENCE) =>
+                return true,
+            _ => {},
+        }
+    }
+    false
+}
+
+impl<'a> Parser<'a> {
+    // Precedence climbing: parses an operand, then folds in every following operator that binds at
+    // least as tightly as `min_precedence`. Left-associative operators parse their right operand one
+    // level tighter, right-associative ones at their own level.
+    fn parse_binary(&mut self, min_precedence: u8) -> Result<AstNode, CalcError> {
+        let start = self.pos;
+        let mut node = self.parse_unary()?;
+        // A run of `+`/`-` whose operands contain bare `*`, `/` or `//` gets a precedence hint.
+        let mut chained = false;
+        let mut mixed = false;
+        // Whether `node` is a comparison built by this loop, which a following comparison extends
+        // into a chain. A parenthesized one is an operand like any other: `(a < b) < c`.
+        let mut comparing = false;
+        loop {
+            let operator_index = self.pos;
+            let operator = match self.peek() {
+                Token::Question if CONDITIONAL_PRECEDENCE >= min_precedence => None,
+                token => match binary_operator(token) {
+                    Some(operator) if operator.precedence >= min_precedence => Some(operator),
+                    None if self.implicit_mul && starts_factor(token) => match binary_operator(&Token::Operator(Op::Star)) {
+                        Some(operator) if operator.precedence >= min_precedence => Some(operator),
+                        _ => break,
+                    },
+                    _ => break,
+                },
+            };
+            // An implicit multiplication has no operator token to step over.
+            let implicit = binary_operator(self.peek()).is_none() && *self.peek() != Token::Question;
+            let additive = operator.is_some_and(|operator| operator.precedence == ADDITIVE_PRECEDENCE);
+            let comparison = operator.is_some_and(|operator| operator.precedence == COMPARISON_PRECEDENCE);
+            let extends_chain = comparing && comparison;
+            comparing = comparison;
+            if additive && !chained {
+                mixed = has_bare_multiplicative(&self.tokens[start..operator_index]);
+                chained = true;
+            } else if !additive {
+                self.hint_precedence(&node, &mut chained, mixed);
+            }
+            if !implicit {
+                self.advance();
+            }
+            let right_start = self.pos;
+            node = match operator {
+                None => {
+                    let then_branch = self.parse_expression()?;
+                    self.expect(Token::Colon)?;
+                    self.enter()?;
+                    let else_branch = self.parse_binary(CONDITIONAL_PRECEDENCE)?;
+                    self.leave();
+                    AstNode::Conditional(Rc::new(node), Rc::new(then_branch), Rc::new(else_branch))
+                },
+                Some(&BinaryOperator { combine: Combine::Assign { store, compound }, .. }) => {
+                    // An assignment's target is not read, so it needs no position.
+                    let node = match node {
+                        AstNode::Located(_, target) => Rc::unwrap_or_clone(target),
+                        node => node,
+                    };
+                    if !matches!(node, AstNode::Identifier(_)) {
+                        return Err(CalcError::InvalidAssignTarget { position: self.positions.get(start).copied() });
+                    }
+                    // The right-hand side is a whole expression, `def` included.
+                    let target = Rc::new(node);
+                    let right = Rc::new(self.parse_expression()?);
+                    match compound {
+                        None => store(target, right),
+                        Some(combine) => store(target.clone(), Rc::new(combine(target, right))),
+                    }
+                },
+                Some(&BinaryOperator { combine: Combine::Node(combine), precedence, associativity, .. }) => {
+                    let right = match associativity {
+                        Associativity::Left => self.parse_binary(precedence + 1)?,
+                        Associativity::Right => {
+                            self.enter()?;
+                            let right = self.parse_binary(precedence)?;
+                            self.leave();
+                            right
+                        },
+                    };
+                    if additive {
+                        mixed |= has_bare_multiplicative(&self.tokens[right_start..self.pos]);
+                    }
+                    if extends_chain {
+                        extend_chain(node, combine, Rc::new(right))
+                    } else if comparison {
+                        combine(Rc::new(node), Rc::new(right))
+                    } else {
+                        self.located(operator_index, combine(Rc::new(node), Rc::new(right)))
+                    }
+                },
+            };
+        }
+        self.hint_precedence(&node, &mut chained, mixed);
+        Ok(node)
+    }
+
+    // Ends an additive run, warning if it mixed in tighter operators without parentheses.
+    fn hint_precedence(&mut self, node: &AstNode, chained: &mut bool, mixed: bool) {
+        if *chained && mixed {
+            self.warnings.push(Warning::PrecedenceHint { suggestion: node.to_string() });
+        }
+        *chained = false;
+    }
+
+    // Prefix `!` is logical not and prefix `-` negates; prefix `+` changes nothing, so `+5` is just
+    // `5`. A `-` takes in any `^` after its operand; see `POWER_PRECEDENCE`.
+    fn parse_unary(&mut self) -> Result<AstNode, CalcError> {
+        let start = self.pos;
+        let op = match *self.peek() {
+            Token::Operator(op @ (Op::Bang | Op::Minus | Op::Plus)) => op,
+            _ => return self.parse_postfix(),
+        };
+        self.advance();
+        self.enter()?;
+        let operand = match op {
+            Op::Minus => self.parse_binary(POWER_PRECEDENCE)?,
+            _ => self.parse_unary()?,
+        };
+        self.leave();
+        Ok(match op {
+            Op::Bang => AstNode::Not(Rc::new(operand)),
+            Op::Minus => self.located(start, AstNode::Negate(Rc::new(operand))),
+            _ => operand,
+        })
+    }
+
+    // A `!` straight after a complete factor is a factorial, as in `3!`; one in front of a factor
+    // is `parse_unary`'s logical not. Adjacent `!=` is always the comparison, so `3!=6` compares.
+    // A `%` is a percent, as in `50%`, unless what follows it could start an operand, which makes
+    // it `parse_binary`'s modulo: `10 % 3`.
+    fn parse_postfix(&mut self) -> Result<AstNode, CalcError> {
+        let mut node = self.parse_factor()?;
+        let mut nesting = 0;
+        loop {
+            let postfix = match self.peek() {
+                Token::Operator(Op::Bang) => AstNode::Factorial,
+                Token::Operator(Op::Percent) if !starts_operand(self.tokens.get(self.pos + 1..).unwrap_or_default()) => AstNode::Percent,
+                _ => break,
+            };
+            self.enter()?;
+            nesting += 1;
+            node = self.located(self.pos, postfix(Rc::new(node)));
+            self.advance();
+        }
+        for _ in 0..nesting {
+            self.leave();
+        }
+        Ok(node)
+    }
+
+    fn parse_factor(&mut self) -> Result<AstNode, CalcError> {
+        let start = self.pos;
+        let node = match self.peek() {
+            Token::Int(value) => AstNode::IntLiteral(value.clone()),
+            Token::Float(value) => AstNode::FloatLiteral(*value),
+            Token::OpenParen => {
+                self.advance();
+                let node = self.parse_expression()?;
+                self.expect(Token::ClosedParen)?;
+                return Ok(node);
+            },
+            Token::OpenBrace => {
+                self.advance();
+                let statements = self.parse_statements(Token::CloseBrace)?;
+                self.advance();
+                return Ok(AstNode::Block(statements.into_iter().map(Rc::new).collect()));
+            },
+            Token::Identifier(identifier) => {
+                self.advance();
+                if *self.peek() != Token::OpenParen {
+                    return Ok(self.located(start, AstNode::Identifier(identifier.clone())));
+                }
+                self.advance();
+                let mut arguments = Vec::new();
+                if *self.peek() != Token::ClosedParen {
+                    loop {
+                        arguments.push(Rc::new(self.parse_expression()?));
+                        match self.peek() {
+                            token if *token == self.separator => self.advance(),
+                            Token::ClosedParen => break,
+                            _ => return Err(self.unexpected()),
+                        };
+                    }
+                }
+                self.advance();
+                return Ok(self.located(start, AstNode::Call(identifier.clone(), arguments)));
+            },
+            _ => return Err(self.missing_operand()),
+        };
+        self.advance();
+        Ok(node)
+    }
+}
+
+// The tokens that, straight after an operand, make an implicit multiplication. A `!` or `{` there
+// stays an error, so typos like `2 {` are not silently multiplied.
+fn starts_factor(token: &Token) -> bool {
+    matches!(token, Token::Int(_) | Token::Float(_) | Token::Identifier(_) | Token::OpenParen)
+}
+
+// Whether the tokens begin with an operand. A `!` begins one only as a `not` in front of another,
+// so in `5%!` it is a factorial.
+fn starts_operand(tokens: &[Token]) -> bool {
+    match token_at(tokens, 0) {
+        Token::Int(_) | Token::Float(_) | Token::Identifier(_) | Token::OpenParen | Token::OpenBrace => true,
+        Token::Operator(Op::Bang) => starts_operand(&tokens[1..]),
+        _ => false,
+    }
+}
+
+// `a < b < c` means `a < b && b < c`, not `(a < b) < c`: the new comparison takes the previous
+// one's right operand, shared rather than copied, as its left.
+fn extend_chain(node: AstNode, combine: Constructor, right: Rc<AstNode>) -> AstNode {
+    let mut comparisons = match node {
+        AstNode::Chain(comparisons) => comparisons,
+        comparison => vec![Rc::new(comparison)],
+    };
+    let (_, _, middle) = comparison_parts(comparisons.last().unwrap()).expect("chains hold comparisons");
+    let comparison = combine(middle.clone(), right);
+    comparisons.push(Rc::new(comparison));
+    AstNode::Chain(comparisons)
+}
+
+pub fn lint(input: &str) -> Result<Vec<Warning>, CalcError> {
+    let (tokens, positions) = tokenize_split(input);
+    Ok(parse_with_warnings(&tokens, &positions, DEFAULT_MAX_DEPTH)?.1)
+}
+
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub enum Warning {
+    PrecedenceHint { suggestion: String },
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Warning::PrecedenceHint { suggestion } =>
+                write!(f, "mixed operator precedence; did you mean {}?", suggestion),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tokenizer::{tokenize_all, Tokenizer, TokenizerImpl};
+
+    fn parsed(input: &str) -> String {
+        parse_str(input).unwrap().to_string()
+    }
+
+    #[test]
+    fn test_parse() {
+        let tokens = tokenize_all("(1 + 2) * (3 - 6)");
+        let ast = parse(tokens).unwrap();
+        assert_eq!(ast, AstNode::Multiply(
+            Rc::new(AstNode::Add(Rc::new(AstNode::IntLiteral(1.into())), Rc::new(AstNode::IntLiteral(2.into())))),
+            Rc::new(AstNode::Subtract(Rc::new(AstNode::IntLiteral(3.into())), Rc::new(AstNode::IntLiteral(6.into()))))
+        ));
+    }
+
+    #[test]
+    fn test_parse_precedence() {
+        assert_eq!(parsed("a || b && c == d + e * f"), "a || (b && (c == (d + (e * f))))");
+        assert_eq!(parsed("a * b // c - d - e"), "(((a * b) // c) - d) - e");
+        assert_eq!(parsed("x = y += a < b ? c : d ? e : f"), "x = y = y + ((a < b) ? c : (d ? e : f))");
+        assert_eq!(parsed("!a + b"), "(!a) + b");
+        assert_eq!(parsed("!a! * b"), "(!(a!)) * b");
+    }
+
+    #[test]
+    fn test_operator_table() {
+        let table = operator_table();
+        let find = |symbol, arity| table.iter().find(|operator| operator.symbol == symbol && operator.arity == arity).unwrap();
+        assert_eq!(*find("+", 2), OperatorInfo { symbol: "+", precedence: 9, associativity: Associativity::Left, arity: 2 });
+        assert_eq!(*find("*", 2), OperatorInfo { symbol: "*", precedence: 10, associativity: Associativity::Left, arity: 2 });
+        assert_eq!(*find("=", 2), OperatorInfo { symbol: "=", precedence: 1, associativity: Associativity::Right, arity: 2 });
+        assert_eq!(find("^", 2).associativity, Associativity::Right);
+        assert!(find("+", 1).precedence > find("^", 2).precedence);
+        assert!(find("%", 1).precedence > find("%", 2).precedence);
+        assert_eq!(find("?:", 3).precedence, CONDITIONAL_PRECEDENCE);
+        assert!(table.windows(2).all(|pair| pair[0].precedence <= pair[1].precedence));
+        assert_eq!(find("-", 1).precedence, find("^", 2).precedence);
+        assert_eq!(table.len(), BINARY_OPERATORS.len() + 6);
+    }
+
+    #[test]
+    fn test_parse_left_associative() {
+        assert_eq!(parsed("10 - 3 - 2"), "(10 - 3) - 2");
+        assert_eq!(parsed("16 / 4 / 2"), "(16 / 4) / 2");
+        assert_eq!(parsed("a - b + c"), "(a - b) + c");
+        assert_eq!(parsed("a + b - c - d"), "((a + b) - c) - d");
+        assert_eq!(parsed("a / b * c // d"), "((a / b) * c) // d");
+    }
+
+    #[test]
+    fn test_parse_chained_comparisons() {
+        assert_eq!(parsed("1 < x <= y + 1"), "1 < x <= (y + 1)");
+        assert_eq!(parsed("(a < b) < c"), "(a < b) < c");
+        assert_eq!(parsed("a < b < c && c > d > e"), "(a < b < c) && (c > d > e)");
+        match parse_str("a < b < c").unwrap() {
+            AstNode::Chain(comparisons) => {
+                assert_eq!(comparisons.len(), 2);
+                let (_, _, middle) = comparison_parts(&comparisons[0]).unwrap();
+                let (_, shared, _) = comparison_parts(&comparisons[1]).unwrap();
+                assert!(Rc::ptr_eq(middle, shared));
+            },
+            node => panic!("expected a chain, found {:?}", node),
+        }
+    }
+
+    #[test]
+    fn test_parse_all_errors() {
+        let recovered = |input| parse_all_errors(&tokenize_all(input));
+        assert_eq!(recovered("1 + 2").unwrap().to_string(), "1 + 2");
+        assert_eq!(recovered("x = 1; x * 2;").unwrap().to_string(), "{ x = 1; x * 2 }");
+        assert_eq!(recovered("1 +"), Err(vec![CalcError::ExpectedOperand { operator: Op::Plus, position: None }]));
+        assert_eq!(recovered("1 + * 2; x = ; y = 3; (4 5); { a; ) }; z"), Err(vec![
+            CalcError::ExpectedOperand { operator: Op::Plus, position: None },
+            CalcError::ExpectedOperand { operator: Op::Assign, position: None },
+            CalcError::UnexpectedToken { token: Token::Int(5.into()), position: None },
+            CalcError::UnexpectedCloseParen { position: None },
+        ]));
+        assert_eq!(recovered("1 2"), Err(vec![CalcError::TrailingTokens { position: None }]));
+        assert_eq!(recovered(""), Err(vec![CalcError::EmptyInput]));
+    }
+
+    #[test]
+    fn test_parse_cache() {
+        let mut cache = ParseCache::new();
+        let first = cache.parse_cached("x * (y + 1)").unwrap();
+        let second = cache.parse_cached("x * (y + 1)").unwrap();
+        assert!(Rc::ptr_eq(&first, &second));
+        assert!(!Rc::ptr_eq(&first, &cache.parse_cached("x * (y + 2)").unwrap()));
+        assert_eq!(cache.parse_cached("1 +"), Err(CalcError::ExpectedOperand { operator: Op::Plus, position: Some(2) }));
+    }
+
+    #[test]
+    fn test_parse_tokens() {
+        let tokens = tokenize_all("x * (y + 1)");
+        let first = parse_tokens(&tokens).unwrap();
+        assert_eq!(parse_tokens(&tokens), Ok(first.clone()));
+        assert_eq!(parse(tokens), Ok(first.clone()));
+        let unterminated: Vec<Token> = TokenizerImpl::new("x * (y + 1)").collect();
+        assert_eq!(parse_tokens(&unterminated), Ok(first));
+        assert_eq!(parse_tokens(&[]), Err(CalcError::EmptyInput));
+    }
+
+    #[test]
+    fn test_parse_spaced_operators() {
+        assert_eq!(parse_str("x = = 5"), Err(CalcError::ExpectedOperand { operator: Op::Assign, position: Some(2) }));
+        assert_eq!(parse_str("x > = 5"), Err(CalcError::ExpectedOperand { operator: Op::Greater, position: Some(2) }));
+        assert_eq!(parse_str("x == 5").unwrap().to_string(), "x == 5");
+    }
+
+    #[test]
+    fn test_nesting_too_deep() {
+        let nested = |depth| format!("{}1{}", "(".repeat(depth), ")".repeat(depth));
+        assert!(parse_str(&nested(DEFAULT_MAX_DEPTH - 1)).is_ok());
+        // The outermost expression is one level, so the cap trips at the 100th parenthesis.
+        assert_eq!(parse_str(&nested(1_000)), Err(CalcError::NestingTooDeep { position: Some(DEFAULT_MAX_DEPTH) }));
+        assert_eq!(parse_str(&"!".repeat(1_000)), Err(CalcError::NestingTooDeep { position: Some(DEFAULT_MAX_DEPTH) }));
+        assert!(matches!(parse_str(&format!("1{}", "!".repeat(1_000))), Err(CalcError::NestingTooDeep { .. })));
+        assert!(matches!(parse_str(&"1 ? 1 : ".repeat(1_000)), Err(CalcError::NestingTooDeep { .. })));
+        let tokens = tokenize_all(&nested(10));
+        assert!(parse_with_max_depth(&tokens, 11).is_ok());
+        assert_eq!(parse_with_max_depth(&tokens, 10), Err(CalcError::NestingTooDeep { position: None }));
+    }
+
+    #[test]
+    fn test_parse_invalid_assign_target() {
+        assert_eq!(parse(tokenize_all("(1 + 2) = 3")), Err(CalcError::InvalidAssignTarget { position: None }));
+        assert_eq!(parse_str("x + 1 = 3"), Err(CalcError::InvalidAssignTarget { position: Some(0) }));
+        assert_eq!(parse_str("2 * f(1) = 3"), Err(CalcError::InvalidAssignTarget { position: Some(0) }));
+        assert!(parse(tokenize_all("x = y = 3")).is_ok());
+        assert_eq!(parse_str("1 = 2"), Err(CalcError::InvalidAssignTarget { position: Some(0) }));
+        assert_eq!(parse_str("= 5"), Err(CalcError::ExpectedOperand { operator: Op::Assign, position: Some(0) }));
+        assert_eq!(parse_str("x = = 5"), Err(CalcError::ExpectedOperand { operator: Op::Assign, position: Some(2) }));
+    }
+
+    #[test]
+    fn test_parse_error_positions() {
+        assert_eq!(
+            parse_str("1 + * 2"),
+            Err(CalcError::ExpectedOperand { operator: Op::Plus, position: Some(2) })
+        );
+        assert_eq!(parse_str("(1 + 2"), Err(CalcError::UnexpectedToken { token: Token::InputEnd, position: Some(6) }));
+        assert_eq!(
+            parse_str("def f(a b) = a"),
+            Err(CalcError::UnexpectedToken { token: Token::Identifier("b".to_string()), position: Some(8) })
+        );
+        assert_eq!(parse(tokenize_all("1 +")), Err(CalcError::ExpectedOperand { operator: Op::Plus, position: None }));
+    }
+
+    #[test]
+    fn test_duplicate_parameter() {
+        assert_eq!(parse_str("def f(a, a) = a"), Err(CalcError::DuplicateParameter { name: "a".to_string(), position: Some(9) }));
+        assert_eq!(parse_str("def f(a, b, a) = a").unwrap_err().render("def f(a, b, a) = a"), "error: duplicate parameter a\n1 | def f(a, b, a) = a\n  |             ^");
+        assert_eq!(parse_str("def f(a, b) = a").unwrap().to_string(), "def f(a, b) = a");
+    }
+
+    #[test]
+    fn test_expected_operand() {
+        assert_eq!(parse_str("1 +"), Err(CalcError::ExpectedOperand { operator: Op::Plus, position: Some(2) }));
+        assert_eq!(parse_str("* 3"), Err(CalcError::ExpectedOperand { operator: Op::Star, position: Some(0) }));
+        assert_eq!(parse_str("(1 + )"), Err(CalcError::ExpectedOperand { operator: Op::Plus, position: Some(3) }));
+        assert_eq!(parse_str("2 * !"), Err(CalcError::ExpectedOperand { operator: Op::Bang, position: Some(4) }));
+        assert_eq!(parse_program("1 +; 2"), Err(CalcError::ExpectedOperand { operator: Op::Plus, position: Some(2) }));
+        assert_eq!(parse_str("{ 1 + }"), Err(CalcError::ExpectedOperand { operator: Op::Plus, position: Some(4) }));
+        assert_eq!(parse_str("max(1 *, 2)"), Err(CalcError::ExpectedOperand { operator: Op::Star, position: Some(6) }));
+        assert_eq!(parse_str("max(1, )"), Err(CalcError::UnexpectedToken { token: Token::ClosedParen, position: Some(7) }));
+        assert_eq!(parse_str("1 +").unwrap_err().to_string(), "missing operand for +");
+        // Nothing where an operand belongs, with no operator involved, stays a plain unexpected token.
+        assert_eq!(parse_str("()"), Err(CalcError::UnexpectedToken { token: Token::ClosedParen, position: Some(1) }));
+    }
+
+    #[test]
+    fn test_truncated_tokens() {
+        let plus = Token::Operator(Op::Plus);
+        let star = Token::Operator(Op::Star);
+        assert_eq!(parse(vec![star, Token::InputEnd]), Err(CalcError::ExpectedOperand { operator: Op::Star, position: None }));
+        assert_eq!(parse(vec![plus.clone(), Token::InputEnd]), Err(CalcError::ExpectedOperand { operator: Op::Plus, position: None }));
+        assert_eq!(parse(vec![Token::OpenParen, Token::InputEnd]), Err(CalcError::UnexpectedToken { token: Token::InputEnd, position: None }));
+        // Without the `InputEnd` sentinel, reads past the last token see the end of the input.
+        let parse_unterminated = |tokens: &[Token]| {
+            let mut parser = Parser::new(tokens, &[], DEFAULT_MAX_DEPTH);
+            parser.parse_expression().and_then(|_| parser.expect(Token::Semicolon))
+        };
+        let end = Err(CalcError::UnexpectedToken { token: Token::InputEnd, position: None });
+        assert_eq!(parse_unterminated(&[Token::OpenParen]), end);
+        assert_eq!(parse_unterminated(&[Token::Int(5.into()), Token::Operator(Op::Percent)]), end);
+        assert_eq!(parse_unterminated(&[Token::Int(1.into()), plus]), Err(CalcError::ExpectedOperand { operator: Op::Plus, position: None }));
+    }
+
+    #[test]
+    fn test_unmatched_close_paren() {
+        assert_eq!(parse_str("1)"), Err(CalcError::UnexpectedCloseParen { position: Some(1) }));
+        assert_eq!(parse_str("(1+2))"), Err(CalcError::UnexpectedCloseParen { position: Some(5) }));
+        assert_eq!(parse_str(")1"), Err(CalcError::UnexpectedCloseParen { position: Some(0) }));
+        assert_eq!(parse_str("1 + 2)").unwrap_err().to_string(), "unmatched )");
+        // A `)` that closes an open `(` too early is just out of place.
+        assert_eq!(parse_str("f(1, )"), Err(CalcError::UnexpectedToken { token: Token::ClosedParen, position: Some(5) }));
+    }
+
+    #[test]
+    fn test_trailing_tokens() {
+        assert_eq!(parse_str("1 2"), Err(CalcError::TrailingTokens { position: Some(2) }));
+        assert_eq!(parse_str("1 + 2 3 * 4"), Err(CalcError::TrailingTokens { position: Some(6) }));
+        assert_eq!(parse_str("(1) x"), Err(CalcError::TrailingTokens { position: Some(4) }));
+        assert_eq!(parse_located("1 2"), Err(CalcError::TrailingTokens { position: Some(2) }));
+        assert_eq!(parse_str("1 2").unwrap_err().render("1 2"), "error: unexpected input after the expression\n1 | 1 2\n  |   ^");
+        assert_eq!(parse_str("1 $"), Err(CalcError::InvalidCharacter { character: '$', position: Some(2) }));
+    }
+
+    #[test]
+    fn test_lint_precedence_hint() {
+        assert_eq!(lint("1 + 2 * 3"), Ok(vec![Warning::PrecedenceHint { suggestion: "1 + (2 * 3)".to_string() }]));
+        assert_eq!(lint("(1 + 2) * 3"), Ok(vec![]));
+        assert_eq!(lint("1 + (2 * 3)"), Ok(vec![]));
+        assert_eq!(lint("1 + 2 + 3"), Ok(vec![]));
+    }
+}
@@ -0,0 +1,198 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::rc::Rc;
+
+use crate::ast::{binary_parts, label, AstNode};
+use crate::error::CalcError;
+use crate::evaluator::evaluate_expression;
+use crate::parser::binary_node;
+use crate::tokenizer::Op;
+use crate::value::Value;
+use crate::Integer;
+
+// One step of a postfix program: an operand to push, or an operator taking its operands off the
+// stack and pushing its result.
+#[derive(Clone, PartialEq, Debug)]
+pub enum RpnToken {
+    Int(Integer),
+    Float(f64),
+    Identifier(String),
+    Binary(Op),
+    Not,
+    Negate,
+    Factorial,
+    Percent,
+    // Stores the value on top of the stack in the named variable, leaving it there.
+    Assign(String),
+    // Takes a condition and both branches' values, in that order, and keeps the chosen one.
+    Conditional,
+    // Takes the given number of arguments and calls the named function with them.
+    Call(String, usize),
+}
+
+impl fmt::Display for RpnToken {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RpnToken::Int(value) => write!(f, "{}", value),
+            RpnToken::Float(value) => write!(f, "{:?}", value),
+            RpnToken::Identifier(name) => write!(f, "{}", name),
+            RpnToken::Binary(op) => write!(f, "{}", op.symbol()),
+            RpnToken::Not => write!(f, "not"),
+            RpnToken::Negate => write!(f, "neg"),
+            RpnToken::Factorial => write!(f, "!"),
+            RpnToken::Percent => write!(f, "percent"),
+            RpnToken::Assign(name) => write!(f, "={}", name),
+            RpnToken::Conditional => write!(f, "?:"),
+            RpnToken::Call(name, count) => write!(f, "{}/{}", name, count),
+        }
+    }
+}
+
+// The tree in postfix order: `(1 + 2) * 3` becomes `1 2 + 3 *`. A chained comparison is spelled
+// out as `&&`s of its comparisons, so `a < b < c` mentions `b` twice. Blocks, `let` and
+// definitions have no postfix form.
+pub fn to_rpn(node: &AstNode) -> Result<Vec<RpnToken>, CalcError> {
+    let mut tokens = Vec::new();
+    push_rpn(node, &mut tokens)?;
+    Ok(tokens)
+}
+
+fn push_rpn(node: &AstNode, tokens: &mut Vec<RpnToken>) -> Result<(), CalcError> {
+    if let Some((op, left, right)) = binary_parts(node) {
+        push_rpn(left, tokens)?;
+        push_rpn(right, tokens)?;
+        tokens.push(RpnToken::Binary(op));
+        return Ok(());
+    }
+    match node {
+        AstNode::IntLiteral(value) => tokens.push(RpnToken::Int(value.clone())),
+        AstNode::FloatLiteral(value) => tokens.push(RpnToken::Float(*value)),
+        AstNode::Identifier(name) => tokens.push(RpnToken::Identifier(name.clone())),
+        AstNode::Not(operand) | AstNode::Negate(operand) | AstNode::Factorial(operand) | AstNode::Percent(operand) => {
+            push_rpn(operand, tokens)?;
+            tokens.push(match node {
+                AstNode::Not(_) => RpnToken::Not,
+                AstNode::Negate(_) => RpnToken::Negate,
+                AstNode::Factorial(_) => RpnToken::Factorial,
+                _ => RpnToken::Percent,
+            });
+        },
+        AstNode::Assign(target, value) => {
+            let AstNode::Identifier(name) = &**target else {
+                return Err(CalcError::InvalidAssignTarget { position: None });
+            };
+            push_rpn(value, tokens)?;
+            tokens.push(RpnToken::Assign(name.clone()));
+        },
+        AstNode::Conditional(condition, then_branch, else_branch) => {
+            for operand in [condition, then_branch, else_branch] {
+                push_rpn(operand, tokens)?;
+            }
+            tokens.push(RpnToken::Conditional);
+        },
+        AstNode::Call(name, arguments) => {
+            for argument in arguments {
+                push_rpn(argument, tokens)?;
+            }
+            tokens.push(RpnToken::Call(name.clone(), arguments.len()));
+        },
+        AstNode::Chain(comparisons) => {
+            for (index, comparison) in comparisons.iter().enumerate() {
+                push_rpn(comparison, tokens)?;
+                if index > 0 {
+                    tokens.push(RpnToken::Binary(Op::AndAnd));
+                }
+            }
+        },
+        AstNode::Located(_, inner) => push_rpn(inner, tokens)?,
+        _ => return Err(CalcError::TypeError(format!("{} has no postfix form", label(node)))),
+    }
+    Ok(())
+}
+
+// Rebuilds the tree the postfix program stands for and evaluates it against `environment`, so
+// `eval_rpn(&to_rpn(node)?, ..)` agrees with evaluating `node`. In particular `&&`, `||` and
+// `?:` still evaluate only the operands they need.
+pub fn eval_rpn(tokens: &[RpnToken], environment: &mut HashMap<String, Value>) -> Result<Value, CalcError> {
+    evaluate_expression(from_rpn(tokens)?, environment)
+}
+
+fn from_rpn(tokens: &[RpnToken]) -> Result<Rc<AstNode>, CalcError> {
+    let malformed = || CalcError::TypeError("malformed postfix program".to_string());
+    let mut stack: Vec<Rc<AstNode>> = Vec::new();
+    for token in tokens {
+        let node = match token {
+            RpnToken::Int(value) => AstNode::IntLiteral(value.clone()),
+            RpnToken::Float(value) => AstNode::FloatLiteral(*value),
+            RpnToken::Identifier(name) => AstNode::Identifier(name.clone()),
+            RpnToken::Binary(op) => {
+                let combine = binary_node(*op).ok_or_else(malformed)?;
+                let right = stack.pop().ok_or_else(malformed)?;
+                let left = stack.pop().ok_or_else(malformed)?;
+                combine(left, right)
+            },
+            RpnToken::Not => AstNode::Not(stack.pop().ok_or_else(malformed)?),
+            RpnToken::Negate => AstNode::Negate(stack.pop().ok_or_else(malformed)?),
+            RpnToken::Factorial => AstNode::Factorial(stack.pop().ok_or_else(malformed)?),
+            RpnToken::Percent => AstNode::Percent(stack.pop().ok_or_else(malformed)?),
+            RpnToken::Assign(name) =>
+                AstNode::Assign(Rc::new(AstNode::Identifier(name.clone())), stack.pop().ok_or_else(malformed)?),
+            RpnToken::Conditional => {
+                let else_branch = stack.pop().ok_or_else(malformed)?;
+                let then_branch = stack.pop().ok_or_else(malformed)?;
+                let condition = stack.pop().ok_or_else(malformed)?;
+                AstNode::Conditional(condition, then_branch, else_branch)
+            },
+            RpnToken::Call(name, count) => {
+                let start = stack.len().checked_sub(*count).ok_or_else(malformed)?;
+                AstNode::Call(name.clone(), stack.split_off(start))
+            },
+        };
+        stack.push(Rc::new(node));
+    }
+    match stack.len() {
+        1 => Ok(stack.pop().unwrap()),
+        _ => Err(malformed()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse_str;
+
+    fn rpn(input: &str) -> String {
+        let tokens = to_rpn(&parse_str(input).unwrap()).unwrap();
+        tokens.iter().map(|token| token.to_string()).collect::<Vec<_>>().join(" ")
+    }
+
+    #[test]
+    fn test_to_rpn() {
+        assert_eq!(rpn("(1+2)*3"), "1 2 + 3 *");
+        assert_eq!(rpn("1 + 2 * 3"), "1 2 3 * +");
+        assert_eq!(rpn("2 ^ 3 ^ 2"), "2 3 2 ^ ^");
+        assert_eq!(rpn("x = !y + 3!"), "y not 3 ! + =x");
+        assert_eq!(rpn("50% + 7 % 4"), "50 percent 7 4 % +");
+        assert_eq!(rpn("f(1, g(), 2.5)"), "1 g/0 2.5 f/3");
+        assert_eq!(rpn("a < b <= c ? 1 : 0"), "a b < b c <= && 1 0 ?:");
+        assert_eq!(to_rpn(&parse_str("{ 1 }").unwrap()), Err(CalcError::TypeError("Block has no postfix form".to_string())));
+    }
+
+    #[test]
+    fn test_eval_rpn_agrees_with_evaluate() {
+        for input in ["(1+2)*3", "2 ^ 10 - 3 // 2", "1 < 2 < 3 ? 10 : 20", "x = 4!", "0 && 1 / 0", "max(1, 5, 3) % 3"] {
+            let node = parse_str(input).unwrap();
+            let mut environment = HashMap::new();
+            let expected = evaluate_expression(Rc::new(node.clone()), &mut HashMap::new());
+            assert_eq!(eval_rpn(&to_rpn(&node).unwrap(), &mut environment), expected, "{}", input);
+        }
+        let mut environment = HashMap::new();
+        assert_eq!(eval_rpn(&[RpnToken::Int(5.into()), RpnToken::Assign("x".to_string())], &mut environment), Ok(Value::Int(5.into())));
+        assert_eq!(environment.get("x"), Some(&Value::Int(5.into())));
+        let malformed = Err(CalcError::TypeError("malformed postfix program".to_string()));
+        assert_eq!(eval_rpn(&[RpnToken::Int(1.into()), RpnToken::Int(2.into())], &mut environment), malformed);
+        assert_eq!(eval_rpn(&[RpnToken::Binary(Op::Plus)], &mut environment), malformed);
+        assert_eq!(eval_rpn(&[RpnToken::Int(1.into()), RpnToken::Int(2.into()), RpnToken::Binary(Op::PlusAssign)], &mut environment), malformed);
+        assert_eq!(eval_rpn(&[], &mut environment), malformed);
+    }
+}
@@ -0,0 +1,163 @@
+use std::rc::Rc;
+
+use crate::ast::{walk, AstNode, Visitor};
+use crate::Integer;
+
+fn is_int(node: &AstNode, value: u8) -> bool {
+    matches!(node, AstNode::IntLiteral(literal) if *literal == Integer::from(value))
+}
+
+// Whether evaluating the node can change the environment. Assignments, definitions and blocks
+// can; calls are counted too, to stay on the safe side.
+fn has_side_effects(node: &AstNode) -> bool {
+    struct Finder(bool);
+    impl Visitor for Finder {
+        fn visit(&mut self, node: &AstNode) {
+            match node {
+                AstNode::Assign(..)
+                | AstNode::AssignReturnOld(..)
+                | AstNode::ConstAssign(..)
+                | AstNode::FunctionDef(..)
+                | AstNode::Block(_)
+                | AstNode::Call(..) => self.0 = true,
+                _ => walk(self, node),
+            }
+        }
+    }
+    let mut finder = Finder(false);
+    finder.visit(node);
+    finder.0
+}
+
+// Rewrites the identities `x + 0`, `0 + x`, `x - 0`, `x * 1`, `1 * x` and `x / 1` to `x`, and
+// `x * 0`, `0 * x` to `0`, bottom-up; everything else is rebuilt unchanged. Only integer literal
+// 0 and 1 count, so no rewrite turns an integer result into a float or back.
+//
+// The rewrites assume operands are numbers, and `x * 0` drops `x` unevaluated: it is only applied
+// when `x` has no side effects, but an error `x` would have raised (an undefined variable, a
+// division by zero) is lost along with it.
+pub fn simplify(node: Rc<AstNode>) -> Rc<AstNode> {
+    fn binary(constructor: fn(Rc<AstNode>, Rc<AstNode>) -> AstNode, left: &Rc<AstNode>, right: &Rc<AstNode>) -> Rc<AstNode> {
+        Rc::new(constructor(simplify(left.clone()), simplify(right.clone())))
+    }
+    match *node {
+        AstNode::Add(ref left, ref right) => {
+            let (left, right) = (simplify(left.clone()), simplify(right.clone()));
+            if is_int(&right, 0) {
+                left
+            } else if is_int(&left, 0) {
+                right
+            } else {
+                Rc::new(AstNode::Add(left, right))
+            }
+        },
+        AstNode::Subtract(ref left, ref right) => {
+            let (left, right) = (simplify(left.clone()), simplify(right.clone()));
+            if is_int(&right, 0) {
+                left
+            } else {
+                Rc::new(AstNode::Subtract(left, right))
+            }
+        },
+        AstNode::Multiply(ref left, ref right) => {
+            let (left, right) = (simplify(left.clone()), simplify(right.clone()));
+            if is_int(&right, 1) || (is_int(&left, 0) && !has_side_effects(&right)) {
+                left
+            } else if is_int(&left, 1) || (is_int(&right, 0) && !has_side_effects(&left)) {
+                right
+            } else {
+                Rc::new(AstNode::Multiply(left, right))
+            }
+        },
+        AstNode::Divide(ref left, ref right) => {
+            let (left, right) = (simplify(left.clone()), simplify(right.clone()));
+            if is_int(&right, 1) {
+                left
+            } else {
+                Rc::new(AstNode::Divide(left, right))
+            }
+        },
+        AstNode::FloorDivide(ref left, ref right) => binary(AstNode::FloorDivide, left, right),
+        AstNode::Modulo(ref left, ref right) => binary(AstNode::Modulo, left, right),
+        AstNode::Assign(ref left, ref right) => binary(AstNode::Assign, left, right),
+        AstNode::AssignReturnOld(ref left, ref right) => binary(AstNode::AssignReturnOld, left, right),
+        AstNode::ConstAssign(ref name, ref value) => Rc::new(AstNode::ConstAssign(name.clone(), simplify(value.clone()))),
+        AstNode::Equal(ref left, ref right) => binary(AstNode::Equal, left, right),
+        AstNode::NotEqual(ref left, ref right) => binary(AstNode::NotEqual, left, right),
+        AstNode::Less(ref left, ref right) => binary(AstNode::Less, left, right),
+        AstNode::LessEqual(ref left, ref right) => binary(AstNode::LessEqual, left, right),
+        AstNode::Greater(ref left, ref right) => binary(AstNode::Greater, left, right),
+        AstNode::GreaterEqual(ref left, ref right) => binary(AstNode::GreaterEqual, left, right),
+        AstNode::And(ref left, ref right) => binary(AstNode::And, left, right),
+        AstNode::Or(ref left, ref right) => binary(AstNode::Or, left, right),
+        AstNode::BitAnd(ref left, ref right) => binary(AstNode::BitAnd, left, right),
+        AstNode::BitOr(ref left, ref right) => binary(AstNode::BitOr, left, right),
+        AstNode::ShiftLeft(ref left, ref right) => binary(AstNode::ShiftLeft, left, right),
+        AstNode::ShiftRight(ref left, ref right) => binary(AstNode::ShiftRight, left, right),
+        AstNode::Power(ref left, ref right) => binary(AstNode::Power, left, right),
+        AstNode::Not(ref operand) => Rc::new(AstNode::Not(simplify(operand.clone()))),
+        AstNode::Negate(ref operand) => Rc::new(AstNode::Negate(simplify(operand.clone()))),
+        AstNode::Factorial(ref operand) => Rc::new(AstNode::Factorial(simplify(operand.clone()))),
+        AstNode::Percent(ref operand) => Rc::new(AstNode::Percent(simplify(operand.clone()))),
+        AstNode::Conditional(ref condition, ref then_branch, ref else_branch) => Rc::new(AstNode::Conditional(
+            simplify(condition.clone()),
+            simplify(then_branch.clone()),
+            simplify(else_branch.clone()),
+        )),
+        AstNode::Block(ref statements) =>
+            Rc::new(AstNode::Block(statements.iter().cloned().map(simplify).collect())),
+        AstNode::Let(ref bindings, ref body) => Rc::new(AstNode::Let(
+            bindings.iter().map(|(name, value)| (name.clone(), simplify(value.clone()))).collect(),
+            simplify(body.clone()),
+        )),
+        AstNode::Located(position, ref inner) => Rc::new(AstNode::Located(position, simplify(inner.clone()))),
+        AstNode::Chain(ref comparisons) =>
+            Rc::new(AstNode::Chain(comparisons.iter().cloned().map(simplify).collect())),
+        AstNode::FunctionDef(ref name, ref parameters, ref body) =>
+            Rc::new(AstNode::FunctionDef(name.clone(), parameters.clone(), simplify(body.clone()))),
+        AstNode::Call(ref name, ref arguments) =>
+            Rc::new(AstNode::Call(name.clone(), arguments.iter().cloned().map(simplify).collect())),
+        AstNode::Identifier(_) | AstNode::IntLiteral(_) | AstNode::FloatLiteral(_) => node,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse_str;
+
+    fn simplified(input: &str) -> String {
+        simplify(Rc::new(parse_str(input).unwrap())).to_string()
+    }
+
+    #[test]
+    fn test_simplify_identities() {
+        assert_eq!(simplified("x + 0"), "x");
+        assert_eq!(simplified("0 + x"), "x");
+        assert_eq!(simplified("x - 0"), "x");
+        assert_eq!(simplified("0 - x"), "0 - x");
+        assert_eq!(simplified("x * 1"), "x");
+        assert_eq!(simplified("1 * x"), "x");
+        assert_eq!(simplified("x * 0"), "0");
+        assert_eq!(simplified("0 * x"), "0");
+        assert_eq!(simplified("x / 1"), "x");
+        assert_eq!(simplified("1 / x"), "1 / x");
+    }
+
+    #[test]
+    fn test_simplify_nested() {
+        assert_eq!(simplified("(x * 1 + 0) * (y - 0)"), "x * y");
+        assert_eq!(simplified("f(a + 0, (b * 0) + c)"), "f(a, c)");
+        assert_eq!(simplified("def g(n) = n * 1"), "def g(n) = n");
+        assert_eq!(simplified("x > 0 ? x * 1 : 0 + y"), "(x > 0) ? x : y");
+    }
+
+    #[test]
+    fn test_simplify_keeps_side_effects_and_types() {
+        assert_eq!(simplified("(x = 5) * 0"), "(x = 5) * 0");
+        assert_eq!(simplified("0 * f(1)"), "0 * f(1)");
+        assert_eq!(simplified("{ y = 1 } * 0"), "{ y = 1 } * 0");
+        assert_eq!(simplified("x + 0.0"), "x + 0.0");
+        assert_eq!(simplified("x * 1.0"), "x * 1.0");
+    }
+}
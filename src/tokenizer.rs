@@ -0,0 +1,851 @@
+use std::cell::Cell;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::mem;
+
+use crate::ast::float_bits;
+use crate::Integer;
+
+pub struct TokenizerImpl<'a> {
+    input: &'a str,
+    // The position in characters, for token positions, and the same position in bytes, for peeking.
+    current_index: Cell<usize>,
+    byte_index: Cell<usize>,
+    decimal_comma: bool,
+    keep_comments: bool,
+}
+
+// What the tokenizer keeps besides the tokens themselves. With `keep_comments` set, each `#`
+// comment comes out as a `Comment` token, so a formatter can put it back.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct TokenizerOptions {
+    pub keep_comments: bool,
+}
+
+// Regional conventions for reading numbers.
+//
+// With `decimal_comma` set, a `,` between digits is the decimal point, as in `3,5`, and `.` is no
+// longer one. A `,` can then no longer separate arguments, since `f(1,5)` would be ambiguous, so
+// `;` takes its place in argument, parameter and `let` binding lists: `max(1,5; 2)` is 2. A
+// `,` anywhere else is an error.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct LocaleOptions {
+    pub decimal_comma: bool,
+}
+
+// Keyword text. The alias matters to serde: its derive would tie any field spelled `&'static str`
+// to a `'static` input, while keywords are deserialized through `KEYWORDS`.
+type Symbol = &'static str;
+
+// Every operator the tokenizer can produce, named for its spelling rather than its meaning:
+// `Star` rather than `Multiply`, since the parser decides what an operator does.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Op {
+    Plus,
+    PlusAssign,
+    Minus,
+    MinusAssign,
+    Star,
+    StarAssign,
+    Slash,
+    SlashAssign,
+    DoubleSlash,
+    Assign,
+    ColonAssign,
+    Equal,
+    Bang,
+    NotEqual,
+    Less,
+    LessEqual,
+    ShiftLeft,
+    Greater,
+    GreaterEqual,
+    ShiftRight,
+    Ampersand,
+    AndAnd,
+    Pipe,
+    OrOr,
+    Caret,
+    Percent,
+}
+
+impl Op {
+    // The operator as written in an expression.
+    pub fn symbol(self) -> &'static str {
+        match self {
+            Op::Plus => "+",
+            Op::PlusAssign => "+=",
+            Op::Minus => "-",
+            Op::MinusAssign => "-=",
+            Op::Star => "*",
+            Op::StarAssign => "*=",
+            Op::Slash => "/",
+            Op::SlashAssign => "/=",
+            Op::DoubleSlash => "//",
+            Op::Assign => "=",
+            Op::ColonAssign => ":=",
+            Op::Equal => "==",
+            Op::Bang => "!",
+            Op::NotEqual => "!=",
+            Op::Less => "<",
+            Op::LessEqual => "<=",
+            Op::ShiftLeft => "<<",
+            Op::Greater => ">",
+            Op::GreaterEqual => ">=",
+            Op::ShiftRight => ">>",
+            Op::Ampersand => "&",
+            Op::AndAnd => "&&",
+            Op::Pipe => "|",
+            Op::OrOr => "||",
+            Op::Caret => "^",
+            Op::Percent => "%",
+        }
+    }
+}
+
+impl fmt::Display for Op {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.symbol())
+    }
+}
+
+#[derive(Clone, PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Token {
+    Int(Integer),
+    Float(f64),
+    // A numeric literal that could not be read, e.g. `2e` with no exponent digits. It is left for
+    // the parser to report, so the tokenizer itself never fails.
+    Malformed(String),
+    // A character that cannot start any token, likewise left for the parser to report.
+    Invalid(char),
+    OpenParen,
+    ClosedParen,
+    OpenBrace,
+    CloseBrace,
+    Operator(Op),
+    Identifier(String),
+    Keyword(#[cfg_attr(feature = "serde", serde(deserialize_with = "deserialize_keyword"))] Symbol),
+    Comma,
+    Semicolon,
+    Question,
+    Colon,
+    // The text of a `#` comment after the `#`, up to the end of the line. Only emitted when
+    // `TokenizerOptions::keep_comments` asks for it; the parser skips it.
+    Comment(String),
+    InputEnd,
+}
+
+const KEYWORDS: [&str; 7] = ["def", "const", "let", "in", "if", "then", "else"];
+
+// A `_` must sit between two digits.
+fn has_misplaced_separator(number: &str) -> bool {
+    let characters: Vec<char> = number.chars().collect();
+    characters.iter().enumerate().any(|(index, c)| *c == '_' && !matches!(characters.get(index + 1), Some('0'..='9')))
+}
+
+#[cfg(not(feature = "bignum"))]
+fn parse_integer(digits: &str, radix: u32) -> Option<Integer> {
+    Integer::from_str_radix(digits, radix).ok()
+}
+
+#[cfg(feature = "bignum")]
+fn parse_integer(digits: &str, radix: u32) -> Option<Integer> {
+    num_traits::Num::from_str_radix(digits, radix).ok()
+}
+
+// Identifiers are a letter or `_`, then letters, digits and `_`s. Letters are ASCII only, unless
+// the `unicode-identifiers` feature admits every alphabetic character (`π`, `é`, ...). Digits
+// are always ASCII.
+#[cfg(not(feature = "unicode-identifiers"))]
+fn is_identifier_start(c: char) -> bool {
+    c.is_ascii_alphabetic() || c == '_'
+}
+
+#[cfg(feature = "unicode-identifiers")]
+fn is_identifier_start(c: char) -> bool {
+    c.is_alphabetic() || c == '_'
+}
+
+fn is_identifier_continue(c: char) -> bool {
+    is_identifier_start(c) || c.is_ascii_digit()
+}
+
+// Keywords are `&'static str`s, so deserialization maps the incoming text back onto `KEYWORDS`.
+#[cfg(feature = "serde")]
+fn deserialize_keyword<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<&'static str, D::Error> {
+    let text: String = serde::Deserialize::deserialize(deserializer)?;
+    KEYWORDS.iter().copied().find(|keyword| *keyword == text)
+        .ok_or_else(|| serde::de::Error::custom(format!("unknown keyword {}", text)))
+}
+
+pub trait Tokenizer<'a> {
+    fn new(input: &'a str) -> Self;
+    fn next_token(&self) -> Token;
+}
+
+impl TokenizerImpl<'_> {
+    pub fn with_locale(input: &str, locale: LocaleOptions) -> TokenizerImpl<'_> {
+        TokenizerImpl { input, current_index: Cell::new(0), byte_index: Cell::new(0), decimal_comma: locale.decimal_comma, keep_comments: false }
+    }
+
+    pub fn with_options(input: &str, options: TokenizerOptions) -> TokenizerImpl<'_> {
+        TokenizerImpl { keep_comments: options.keep_comments, ..TokenizerImpl::new(input) }
+    }
+
+    // Consumes a one-character operator, combining it with a directly following `=` into its
+    // compound form (`+=`, `==`, `<=`, ...). Only adjacent characters combine, here and for every
+    // other multi-character operator: `> =` is `>` then `=`.
+    fn operator(&self, single: Op, compound: Op) -> Token {
+        self.operator_followed_by(single, '=', compound)
+    }
+
+    // Character offset of the next token: skips any whitespace and comments in front of it.
+    pub fn next_token_position(&self) -> usize {
+        self.skip_trivia();
+        self.current_index.get()
+    }
+
+    // Skips whitespace and, unless they are kept, comments. A comment runs from `#` to the end of
+    // the line. `//` is floor division, not a comment: `7 // 2` is 3.
+    fn skip_trivia(&self) {
+        loop {
+            match self.peek_at(0) {
+                Some(' ' | '\r' | '\n' | '\t') => self.advance(1),
+                Some('#') if !self.keep_comments => {
+                    self.comment();
+                },
+                _ => break,
+            }
+        }
+    }
+
+    // Consumes a comment, `#` included, and returns its text after the `#`.
+    fn comment(&self) -> String {
+        self.advance(1);
+        let mut text = String::new();
+        while let Some(c) = self.peek_at(0).filter(|c| *c != '\n') {
+            text.push(c);
+            self.advance(1);
+        }
+        text
+    }
+
+    fn peek_at(&self, offset: usize) -> Option<char> {
+        self.input[self.byte_index.get()..].chars().nth(offset)
+    }
+
+    // Moves past the next `count` characters, which the caller has already peeked at.
+    fn advance(&self, count: usize) {
+        let skipped: usize = self.input[self.byte_index.get()..].chars().take(count).map(char::len_utf8).sum();
+        self.byte_index.set(self.byte_index.get() + skipped);
+        self.current_index.set(self.current_index.get() + count);
+    }
+
+    // Digits and `_` separators, kept as written; `number` checks the separators' placement.
+    fn take_digits(&self, number: &mut String) {
+        while let Some(c) = self.peek_at(0) {
+            if c.is_ascii_digit() || c == '_' {
+                number.push(c);
+                self.advance(1);
+            } else {
+                break;
+            }
+        }
+    }
+
+    // Digits, then an optional fraction (`.`, or `,` under a decimal-comma locale, followed by a
+    // digit) and an optional exponent
+    // (`e` or `E`, an optional sign, and digits). Either of the latter makes the number a float.
+    // Any run of digits may use single underscores between digits as separators, as in `1_000`.
+    // Leading zeros never make a number octal: `0123` is 123, and octal needs the `0o` prefix.
+    fn number(&self) -> Token {
+        if self.peek_at(0) == Some('0') && self.peek_at(1) == Some('o') {
+            return self.octal();
+        }
+        let mut number = String::new();
+        self.take_digits(&mut number);
+        let mut float = false;
+        let point = if self.decimal_comma { ',' } else { '.' };
+        if self.peek_at(0) == Some(point) && matches!(self.peek_at(1), Some('0'..='9')) {
+            number.push(point);
+            self.advance(1);
+            self.take_digits(&mut number);
+            float = true;
+        }
+        if let Some(e @ ('e' | 'E')) = self.peek_at(0) {
+            number.push(e);
+            self.advance(1);
+            if let Some(sign @ ('+' | '-')) = self.peek_at(0) {
+                number.push(sign);
+                self.advance(1);
+            }
+            if !matches!(self.peek_at(0), Some('0'..='9')) {
+                return Token::Malformed(number);
+            }
+            self.take_digits(&mut number);
+            float = true;
+        }
+        if has_misplaced_separator(&number) {
+            return Token::Malformed(number);
+        }
+        let digits: String = number.chars().filter(|c| *c != '_').map(|c| if c == ',' { '.' } else { c }).collect();
+        if float {
+            return Token::Float(digits.parse::<f64>().unwrap());
+        }
+        let significant = digits.trim_start_matches('0');
+        match parse_integer(if significant.is_empty() { "0" } else { significant }, 10) {
+            Some(value) => Token::Int(value),
+            // Too big for the integer type.
+            None => Token::Malformed(number),
+        }
+    }
+
+    // `0o` and octal digits, with separators as in decimal: `0o17` is 15, `0o7_777` is 4095.
+    fn octal(&self) -> Token {
+        let mut number = "0o".to_string();
+        self.advance(2);
+        self.take_digits(&mut number);
+        let digits = &number[2..];
+        let valid = digits.starts_with(|c: char| c.is_digit(8))
+            && digits.chars().all(|c| c == '_' || c.is_digit(8))
+            && !has_misplaced_separator(digits);
+        let value = if valid { parse_integer(&digits.replace('_', ""), 8) } else { None };
+        match value {
+            Some(value) => Token::Int(value),
+            None => Token::Malformed(number),
+        }
+    }
+
+    fn operator_followed_by(&self, single: Op, next: char, compound: Op) -> Token {
+        self.advance(1);
+        if self.peek_at(0) == Some(next) {
+            self.advance(1);
+            Token::Operator(compound)
+        } else {
+            Token::Operator(single)
+        }
+    }
+}
+
+impl<'a> Tokenizer<'a> for TokenizerImpl<'a> {
+    fn new(input: &'a str) -> TokenizerImpl<'a> {
+        TokenizerImpl::with_locale(input, LocaleOptions::default())
+    }
+    fn next_token(&self) -> Token {
+        self.skip_trivia();
+        match self.peek_at(0) {
+            Some('+') => self.operator(Op::Plus, Op::PlusAssign),
+            // U+2212 MINUS SIGN, ×, and ÷ as pasted from typeset math are the ASCII operators.
+            Some('-' | '\u{2212}') => self.operator(Op::Minus, Op::MinusAssign),
+            // `**` is `^`, as in Python; the two `*`s must be adjacent.
+            Some('*') if self.peek_at(1) == Some('*') => {
+                self.advance(2);
+                Token::Operator(Op::Caret)
+            },
+            Some('*' | '×') => self.operator(Op::Star, Op::StarAssign),
+            Some('÷') => self.operator(Op::Slash, Op::SlashAssign),
+            Some('/') if self.peek_at(1) == Some('/') => {
+                self.advance(2);
+                Token::Operator(Op::DoubleSlash)
+            },
+            Some('/') => self.operator(Op::Slash, Op::SlashAssign),
+            Some('=') => self.operator(Op::Assign, Op::Equal),
+            Some('!') => self.operator(Op::Bang, Op::NotEqual),
+            Some('<') if self.peek_at(1) == Some('<') => {
+                self.advance(2);
+                Token::Operator(Op::ShiftLeft)
+            },
+            Some('<') => self.operator(Op::Less, Op::LessEqual),
+            Some('>') if self.peek_at(1) == Some('>') => {
+                self.advance(2);
+                Token::Operator(Op::ShiftRight)
+            },
+            Some('>') => self.operator(Op::Greater, Op::GreaterEqual),
+            Some(':') if self.peek_at(1) == Some('=') => {
+                self.advance(2);
+                Token::Operator(Op::ColonAssign)
+            },
+            Some('&') => self.operator_followed_by(Op::Ampersand, '&', Op::AndAnd),
+            Some('|') => self.operator_followed_by(Op::Pipe, '|', Op::OrOr),
+            Some(c) if is_identifier_start(c) => {
+                let mut identifier = String::new();
+                while let Some(c) = self.peek_at(0) {
+                    if is_identifier_continue(c) {
+                        identifier.push(c);
+                        self.advance(1);
+                    } else {
+                        break;
+                    }
+                }
+                if let Some(keyword) = KEYWORDS.iter().find(|keyword| **keyword == identifier) {
+                    return Token::Keyword(keyword);
+                }
+                let result = identifier.clone().to_owned();
+                Token::Identifier(result)
+            },
+            Some('0'..='9') => self.number(),
+            // Only reached when comments are kept; otherwise `skip_trivia` has consumed them.
+            Some('#') => Token::Comment(self.comment()),
+            None => Token::InputEnd,
+            Some(c) => {
+                self.advance(1);
+                Token::try_from(c).unwrap_or(Token::Invalid(c))
+            },
+        }
+    }
+}
+
+// Yields tokens lazily, one `next_token` call at a time. The stream ends at the end of the input:
+// `InputEnd` itself is not yielded, and the iterator keeps returning `None` afterwards.
+impl Iterator for TokenizerImpl<'_> {
+    type Item = Token;
+
+    fn next(&mut self) -> Option<Token> {
+        match self.next_token() {
+            Token::InputEnd => None,
+            token => Some(token),
+        }
+    }
+}
+
+pub fn tokenize_all(input: &str) -> Vec<Token> {
+    let tokenizer = TokenizerImpl::new(input);
+    let mut tokens = Vec::new();
+    loop {
+        let token = tokenizer.next_token();
+        tokens.push(token.clone());
+        if let Token::InputEnd = token.clone() {
+            break;
+        }
+    }
+    tokens
+}
+
+// Like `tokenize_all`, with the options' extra tokens in the stream.
+pub fn tokenize_with_options(input: &str, options: TokenizerOptions) -> Vec<Token> {
+    let mut tokens: Vec<Token> = TokenizerImpl::with_options(input, options).collect();
+    tokens.push(Token::InputEnd);
+    tokens
+}
+
+// Like `tokenize_all`, but pairs each token with the character offset it starts at. The
+// trailing `InputEnd` sits at the length of the input.
+pub fn tokenize_with_positions(input: &str) -> Vec<(Token, usize)> {
+    tokenize_with_locale(input, LocaleOptions::default())
+}
+
+pub(crate) fn tokenize_with_locale(input: &str, locale: LocaleOptions) -> Vec<(Token, usize)> {
+    let tokenizer = TokenizerImpl::with_locale(input, locale);
+    let mut tokens = Vec::new();
+    loop {
+        let position = tokenizer.next_token_position();
+        let token = tokenizer.next_token();
+        let end = token == Token::InputEnd;
+        tokens.push((token, position));
+        if end {
+            break;
+        }
+    }
+    tokens
+}
+
+// The tokens a character stands for on its own: brackets, punctuation and the single-character
+// operators, `×`, `÷` and `−` included. Anything else (a digit, a letter, whitespace) needs
+// context the character alone doesn't carry and is handed back as the error.
+impl TryFrom<char> for Token {
+    type Error = char;
+
+    fn try_from(c: char) -> Result<Token, char> {
+        Ok(match c {
+            '(' => Token::OpenParen,
+            ')' => Token::ClosedParen,
+            '{' => Token::OpenBrace,
+            '}' => Token::CloseBrace,
+            ',' => Token::Comma,
+            ';' => Token::Semicolon,
+            '?' => Token::Question,
+            ':' => Token::Colon,
+            '+' => Token::Operator(Op::Plus),
+            '-' | '\u{2212}' => Token::Operator(Op::Minus),
+            '*' | '×' => Token::Operator(Op::Star),
+            '/' | '÷' => Token::Operator(Op::Slash),
+            '=' => Token::Operator(Op::Assign),
+            '!' => Token::Operator(Op::Bang),
+            '<' => Token::Operator(Op::Less),
+            '>' => Token::Operator(Op::Greater),
+            '&' => Token::Operator(Op::Ampersand),
+            '|' => Token::Operator(Op::Pipe),
+            '^' => Token::Operator(Op::Caret),
+            '%' => Token::Operator(Op::Percent),
+            _ => return Err(c),
+        })
+    }
+}
+
+// Like `AstNode`, tokens hash floats by their bits, with `0.0` and `-0.0` alike.
+impl Hash for Token {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        mem::discriminant(self).hash(state);
+        match self {
+            Token::Int(value) => value.hash(state),
+            Token::Float(value) => float_bits(*value).hash(state),
+            Token::Malformed(text) | Token::Identifier(text) | Token::Comment(text) => text.hash(state),
+            Token::Invalid(c) => c.hash(state),
+            Token::Operator(op) => op.hash(state),
+            Token::Keyword(keyword) => keyword.hash(state),
+            _ => {},
+        }
+    }
+}
+
+impl Token {
+    // The token as it would be written in an expression: `+`, `3`, `x`. `InputEnd` is empty.
+    // Joining these with spaces rebuilds the input up to whitespace, except that numbers come back
+    // in canonical form (`1_000` as `1000`, `1e3` as `1000.0`).
+    pub fn to_source(&self) -> String {
+        match self {
+            Token::Int(value) => value.to_string(),
+            Token::Float(value) => format!("{:?}", value),
+            Token::Malformed(text) => text.clone(),
+            Token::Invalid(c) => c.to_string(),
+            Token::OpenParen => "(".to_string(),
+            Token::ClosedParen => ")".to_string(),
+            Token::OpenBrace => "{".to_string(),
+            Token::CloseBrace => "}".to_string(),
+            Token::Operator(op) => op.symbol().to_string(),
+            Token::Keyword(symbol) => symbol.to_string(),
+            Token::Identifier(name) => name.clone(),
+            Token::Comma => ",".to_string(),
+            Token::Semicolon => ";".to_string(),
+            Token::Question => "?".to_string(),
+            Token::Colon => ":".to_string(),
+            Token::Comment(text) => format!("#{}", text),
+            Token::InputEnd => String::new(),
+        }
+    }
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Token::Int(value) => write!(f, "Int({})", value),
+            Token::Float(value) => write!(f, "Float({:?})", value),
+            Token::Malformed(text) => write!(f, "Malformed({})", text),
+            Token::Invalid(c) => write!(f, "Invalid({})", c),
+            Token::OpenParen => write!(f, "OpenParen"),
+            Token::ClosedParen => write!(f, "ClosedParen"),
+            Token::OpenBrace => write!(f, "OpenBrace"),
+            Token::CloseBrace => write!(f, "CloseBrace"),
+            Token::Operator(value) => write!(f, "Operator({})", value),
+            Token::InputEnd => write!(f, "InputEnd"),
+            Token::Identifier(value) => write!(f, "Identifier({})", value),
+            Token::Keyword(value) => write!(f, "Keyword({})", value),
+            Token::Comma => write!(f, "Comma"),
+            Token::Semicolon => write!(f, "Semicolon"),
+            Token::Question => write!(f, "Question"),
+            Token::Colon => write!(f, "Colon"),
+            Token::Comment(text) => write!(f, "Comment({})", text),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tokenize_all() {
+        let tokens = tokenize_all("(1 + 2) * (3 - 6)");
+        assert_eq!(tokens.len(), 12);
+        assert_eq!(tokens[0], Token::OpenParen);
+        assert_eq!(tokens[1], Token::Int(1.into()));
+        assert_eq!(tokens[2], Token::Operator(Op::Plus));
+        assert_eq!(tokens[3], Token::Int(2.into()));
+        assert_eq!(tokens[4], Token::ClosedParen);
+        assert_eq!(tokens[5], Token::Operator(Op::Star));
+        assert_eq!(tokens[6], Token::OpenParen);
+        assert_eq!(tokens[7], Token::Int(3.into()));
+        assert_eq!(tokens[8], Token::Operator(Op::Minus));
+        assert_eq!(tokens[9], Token::Int(6.into()));
+        assert_eq!(tokens[10], Token::ClosedParen);
+        assert_eq!(tokens[11], Token::InputEnd);
+    }
+
+    #[test]
+    fn test_tokenize_compound_operators() {
+        let tokens = tokenize_all("x += 1 -= *= /=");
+        assert_eq!(tokens[1], Token::Operator(Op::PlusAssign));
+        assert_eq!(tokens[3], Token::Operator(Op::MinusAssign));
+        assert_eq!(tokens[4], Token::Operator(Op::StarAssign));
+        assert_eq!(tokens[5], Token::Operator(Op::SlashAssign));
+        let tokens = tokenize_all("x + = 1");
+        assert_eq!(tokens[1], Token::Operator(Op::Plus));
+        assert_eq!(tokens[2], Token::Operator(Op::Assign));
+    }
+
+    #[test]
+    fn test_tokenize_bitwise_operators() {
+        assert_eq!(tokenize_all("a & b | c << 2 >> 1 && d < e <= f"), vec![
+            Token::Identifier("a".to_string()), Token::Operator(Op::Ampersand), Token::Identifier("b".to_string()),
+            Token::Operator(Op::Pipe), Token::Identifier("c".to_string()), Token::Operator(Op::ShiftLeft), Token::Int(2.into()),
+            Token::Operator(Op::ShiftRight), Token::Int(1.into()), Token::Operator(Op::AndAnd), Token::Identifier("d".to_string()),
+            Token::Operator(Op::Less), Token::Identifier("e".to_string()), Token::Operator(Op::LessEqual),
+            Token::Identifier("f".to_string()), Token::InputEnd,
+        ]);
+    }
+
+    #[test]
+    fn test_tokenize_operators_combine_only_when_adjacent() {
+        let operators = |input| tokenize_all(input).into_iter().filter_map(|token| match token {
+            Token::Operator(operator) => Some(operator.symbol()),
+            _ => None,
+        }).collect::<Vec<_>>();
+        assert_eq!(operators(">= > ="), vec![">=", ">", "="]);
+        assert_eq!(operators("== = ="), vec!["==", "=", "="]);
+        assert_eq!(operators("// / /"), vec!["//", "/", "/"]);
+        assert_eq!(operators("<< < <"), vec!["<<", "<", "<"]);
+        assert_eq!(operators("&& & &"), vec!["&&", "&", "&"]);
+        assert_eq!(operators("+=+ ="), vec!["+=", "+", "="]);
+    }
+
+    #[test]
+    fn test_tokenize_unicode_operators() {
+        assert_eq!(tokenize_all("6 × 7 ÷ 2 \u{2212} 1"), tokenize_all("6 * 7 / 2 - 1"));
+        assert_eq!(tokenize_all("x ×= 2"), tokenize_all("x *= 2"));
+        assert_eq!(tokenize_all("x \u{2212}= 2"), tokenize_all("x -= 2"));
+        // Other dashes are not minus signs.
+        assert_eq!(tokenize_all("3 \u{2013} 1")[1], Token::Invalid('\u{2013}'));
+    }
+
+    #[test]
+    fn test_tokenize_colon_assign() {
+        assert_eq!(tokenize_all("x := 1")[1], Token::Operator(Op::ColonAssign));
+        assert_eq!(tokenize_all("c ? 1 : 2")[3], Token::Colon);
+    }
+
+    #[test]
+    fn test_tokenize_double_star() {
+        assert_eq!(tokenize_all("2 ** 3"), tokenize_all("2 ^ 3"));
+        assert_eq!(tokenize_all("2**3"), tokenize_all("2 ^ 3"));
+        assert_eq!(tokenize_all("2 * *3")[1..3], [Token::Operator(Op::Star), Token::Operator(Op::Star)]);
+        assert_eq!(tokenize_all("x *= 2")[1], Token::Operator(Op::StarAssign));
+    }
+
+    #[test]
+    fn test_token_try_from_char() {
+        assert_eq!(Token::try_from('('), Ok(Token::OpenParen));
+        assert_eq!(Token::try_from('+'), Ok(Token::Operator(Op::Plus)));
+        assert_eq!(Token::try_from('×'), Ok(Token::Operator(Op::Star)));
+        assert_eq!(Token::try_from('a'), Err('a'));
+        assert_eq!(Token::try_from('7'), Err('7'));
+        let expected: Result<Vec<Token>, char> = "(+)".chars().map(Token::try_from).collect();
+        assert_eq!(tokenize_all("( + )")[..3], expected.unwrap()[..]);
+    }
+
+    #[test]
+    fn test_tokenize_invalid_characters() {
+        assert_eq!(tokenize_all("x + 😀"), vec![
+            Token::Identifier("x".to_string()), Token::Operator(Op::Plus), Token::Invalid('😀'), Token::InputEnd,
+        ]);
+        assert_eq!(tokenize_all("1 $ 2")[1], Token::Invalid('$'));
+    }
+
+    #[cfg(not(feature = "unicode-identifiers"))]
+    #[test]
+    fn test_tokenize_ascii_identifiers() {
+        assert_eq!(tokenize_all("π * r"), vec![
+            Token::Invalid('π'), Token::Operator(Op::Star), Token::Identifier("r".to_string()), Token::InputEnd,
+        ]);
+        assert_eq!(tokenize_all("aπ"), vec![Token::Identifier("a".to_string()), Token::Invalid('π'), Token::InputEnd]);
+    }
+
+    #[cfg(feature = "unicode-identifiers")]
+    #[test]
+    fn test_tokenize_unicode_identifiers() {
+        assert_eq!(tokenize_all("π * r"), vec![
+            Token::Identifier("π".to_string()), Token::Operator(Op::Star), Token::Identifier("r".to_string()), Token::InputEnd,
+        ]);
+        assert_eq!(tokenize_all("aπ"), vec![Token::Identifier("aπ".to_string()), Token::InputEnd]);
+        assert_eq!(tokenize_all("π😀")[1], Token::Invalid('😀'));
+    }
+
+    #[test]
+    fn test_tokenize_floats_and_floor_division() {
+        assert_eq!(tokenize_all("3.25 // 2 / 10 /= 0.5"), vec![
+            Token::Float(3.25),
+            Token::Operator(Op::DoubleSlash),
+            Token::Int(2.into()),
+            Token::Operator(Op::Slash),
+            Token::Int(10.into()),
+            Token::Operator(Op::SlashAssign),
+            Token::Float(0.5),
+            Token::InputEnd,
+        ]);
+    }
+
+    #[test]
+    fn test_tokenize_decimal_comma() {
+        let locale = LocaleOptions { decimal_comma: true };
+        let tokens: Vec<Token> = tokenize_with_locale("3,5 1.5 2, 1,5e2", locale).into_iter().map(|(token, _)| token).collect();
+        assert_eq!(tokens, vec![
+            Token::Float(3.5),
+            Token::Int(1.into()),
+            Token::Invalid('.'),
+            Token::Int(5.into()),
+            Token::Int(2.into()),
+            Token::Comma,
+            Token::Float(150.0),
+            Token::InputEnd,
+        ]);
+        assert_eq!(tokenize_all("3,5"), vec![Token::Int(3.into()), Token::Comma, Token::Int(5.into()), Token::InputEnd]);
+    }
+
+    #[test]
+    fn test_tokenize_scientific_notation() {
+        assert_eq!(tokenize_all("1e3 2.5e-2 4E+1"), vec![
+            Token::Float(1000.0),
+            Token::Float(0.025),
+            Token::Float(40.0),
+            Token::InputEnd,
+        ]);
+        assert_eq!(tokenize_all("e * 2e"), vec![
+            Token::Identifier("e".to_string()),
+            Token::Operator(Op::Star),
+            Token::Malformed("2e".to_string()),
+            Token::InputEnd,
+        ]);
+        assert_eq!(tokenize_all("3e-x")[0], Token::Malformed("3e-".to_string()));
+    }
+
+    #[test]
+    fn test_tokenize_digit_separators() {
+        assert_eq!(tokenize_all("1_000_000 1_0.2_5e1_0"), vec![
+            Token::Int(1_000_000.into()),
+            Token::Float(10.25e10),
+            Token::InputEnd,
+        ]);
+        assert_eq!(tokenize_all("1__0")[0], Token::Malformed("1__0".to_string()));
+        assert_eq!(tokenize_all("1_ + 2")[0], Token::Malformed("1_".to_string()));
+        assert_eq!(tokenize_all("1_.5")[0], Token::Malformed("1_.5".to_string()));
+        // A number never continues past whitespace.
+        assert_eq!(tokenize_all("1 2"), vec![Token::Int(1.into()), Token::Int(2.into()), Token::InputEnd]);
+        assert_eq!(tokenize_all("1\t2.5"), vec![Token::Int(1.into()), Token::Float(2.5), Token::InputEnd]);
+        assert_eq!(tokenize_all("1_ 000")[0], Token::Malformed("1_".to_string()));
+        assert_eq!(tokenize_all("_1 x_2")[..2], [
+            Token::Identifier("_1".to_string()),
+            Token::Identifier("x_2".to_string()),
+        ]);
+    }
+
+    #[test]
+    fn test_tokenize_leading_zeros_and_octal() {
+        assert_eq!(tokenize_all("0123 007 0 00 0.5 010e1"), vec![
+            Token::Int(123.into()), Token::Int(7.into()), Token::Int(0.into()), Token::Int(0.into()), Token::Float(0.5), Token::Float(100.0), Token::InputEnd,
+        ]);
+        assert_eq!(tokenize_all("0o17 0o0 0o7_777 0o017"), vec![
+            Token::Int(15.into()), Token::Int(0.into()), Token::Int(4095.into()), Token::Int(15.into()), Token::InputEnd,
+        ]);
+        for malformed in ["0o", "0o8", "0o19", "0o_7", "0o7_", "0o1__0"] {
+            assert_eq!(tokenize_all(malformed)[0], Token::Malformed(malformed.to_string()), "{}", malformed);
+        }
+    }
+
+    #[test]
+    #[cfg(not(feature = "bignum"))]
+    fn test_tokenize_out_of_range_integer() {
+        assert_eq!(tokenize_all("9223372036854775808")[0], Token::Malformed("9223372036854775808".to_string()));
+        assert_eq!(tokenize_all("0o1000000000000000000000")[0], Token::Malformed("0o1000000000000000000000".to_string()));
+    }
+
+    #[test]
+    fn test_token_hash() {
+        use std::collections::hash_map::DefaultHasher;
+        let hash = |token: Token| {
+            let mut hasher = DefaultHasher::new();
+            token.hash(&mut hasher);
+            hasher.finish()
+        };
+        assert_eq!(hash(Token::Float(1.5)), hash(Token::Float(1.5)));
+        assert_eq!(hash(Token::Float(0.0)), hash(Token::Float(-0.0)));
+        assert_ne!(hash(Token::Float(1.0)), hash(Token::Int(1.into())));
+        assert_ne!(hash(Token::Operator(Op::Plus)), hash(Token::Operator(Op::Minus)));
+        assert_ne!(hash(Token::Identifier("x".to_string())), hash(Token::Malformed("x".to_string())));
+    }
+
+    #[test]
+    fn test_tokenize_comments() {
+        assert_eq!(tokenize_all("x = 1 # set x\n# alone\nx / 2 #"), vec![
+            Token::Identifier("x".to_string()), Token::Operator(Op::Assign), Token::Int(1.into()),
+            Token::Identifier("x".to_string()), Token::Operator(Op::Slash), Token::Int(2.into()), Token::InputEnd,
+        ]);
+        // Two adjacent slashes are floor division, never a comment.
+        assert_eq!(tokenize_all("6 // 2 / 1"), vec![
+            Token::Int(6.into()), Token::Operator(Op::DoubleSlash), Token::Int(2.into()), Token::Operator(Op::Slash), Token::Int(1.into()), Token::InputEnd,
+        ]);
+        assert_eq!(tokenize_all("6 / / 2")[1..3], [Token::Operator(Op::Slash), Token::Operator(Op::Slash)]);
+        assert_eq!(tokenize_with_positions("# c\n  7"), vec![(Token::Int(7.into()), 6), (Token::InputEnd, 7)]);
+        assert_eq!(tokenize_all("# only a comment"), vec![Token::InputEnd]);
+    }
+
+    #[test]
+    fn test_tokenize_kept_comments() {
+        let keep = TokenizerOptions { keep_comments: true };
+        assert_eq!(tokenize_with_options("1 + 2 # note", keep), vec![
+            Token::Int(1.into()), Token::Operator(Op::Plus), Token::Int(2.into()), Token::Comment(" note".to_string()), Token::InputEnd,
+        ]);
+        assert_eq!(tokenize_with_options("#a\n#\nx", keep), vec![
+            Token::Comment("a".to_string()), Token::Comment(String::new()), Token::Identifier("x".to_string()), Token::InputEnd,
+        ]);
+        assert_eq!(Token::Comment(" note".to_string()).to_source(), "# note");
+        assert_eq!(tokenize_with_options("1 + 2 # note", TokenizerOptions::default()), tokenize_all("1 + 2"));
+    }
+
+    #[test]
+    fn test_to_source_round_trip() {
+        let reassemble = |input| tokenize_all(input).iter().map(Token::to_source).collect::<Vec<_>>().join(" ");
+        for input in ["(1 + 2.5) * x", "def f(a, b) = { a <<= b; a ? b : 3e }", "x >= 1 && !(y // 2 != 0)", "1 $ 2"] {
+            assert_eq!(reassemble(input).replace(' ', ""), input.replace(' ', ""));
+        }
+        assert_eq!(Token::Int(3.into()).to_source(), "3");
+        assert_eq!(Token::Operator(Op::Plus).to_source(), "+");
+        assert_eq!(Token::Int(3.into()).to_string(), "Int(3)");
+        assert_eq!(Token::Float(1.0).to_string(), "Float(1.0)");
+        assert_eq!(tokenize_all("1e3 1_000").iter().map(Token::to_source).collect::<Vec<_>>(), vec!["1000.0", "1000", ""]);
+    }
+
+    #[test]
+    fn test_tokenize_with_positions() {
+        assert_eq!(tokenize_with_positions(" x >=  12"), vec![
+            (Token::Identifier("x".to_string()), 1),
+            (Token::Operator(Op::GreaterEqual), 3),
+            (Token::Int(12.into()), 7),
+            (Token::InputEnd, 9),
+        ]);
+        // Positions count characters, not bytes.
+        assert_eq!(tokenize_with_positions("6 × 7 \u{2212} x").iter().map(|(_, position)| *position).collect::<Vec<_>>(), vec![0, 2, 4, 6, 8, 9]);
+    }
+
+    #[test]
+    fn test_tokenizer_iterator() {
+        let tokens: Vec<Token> = TokenizerImpl::new("x = 1 + 2").collect();
+        assert_eq!(tokens, vec![
+            Token::Identifier("x".to_string()),
+            Token::Operator(Op::Assign),
+            Token::Int(1.into()),
+            Token::Operator(Op::Plus),
+            Token::Int(2.into()),
+        ]);
+        let mut tokenizer = TokenizerImpl::new("");
+        assert_eq!(tokenizer.next(), None);
+        assert_eq!(tokenizer.next(), None);
+    }
+
+    #[test]
+    fn test_tokenizer_iterator_is_lazy() {
+        // `$` is not a valid character, but it is never reached.
+        let tokens: Vec<Token> = TokenizerImpl::new("1 + $").take(2).collect();
+        assert_eq!(tokens, vec![Token::Int(1.into()), Token::Operator(Op::Plus)]);
+    }
+}
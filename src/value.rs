@@ -0,0 +1,110 @@
+use std::fmt;
+use std::rc::Rc;
+
+use crate::ast::AstNode;
+use crate::Integer;
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct Function {
+    pub name: String,
+    pub parameters: Vec<String>,
+    pub body: Rc<AstNode>,
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum Value {
+    Int(Integer),
+    Float(f64),
+    // What comparisons and logical operators produce, and what conditions require. Booleans are
+    // not numbers: arithmetic on them is a type error, as is a number used as a condition.
+    Bool(bool),
+    Function(Rc<Function>),
+}
+
+impl Value {
+    pub fn from_bool(value: bool) -> Value {
+        Value::Bool(value)
+    }
+}
+
+#[cfg(not(feature = "bignum"))]
+pub(crate) fn integer_to_f64(value: &Integer) -> f64 {
+    *value as f64
+}
+
+// Out-of-range big integers come back as infinities rather than failing.
+#[cfg(feature = "bignum")]
+pub(crate) fn integer_to_f64(value: &Integer) -> f64 {
+    num_traits::ToPrimitive::to_f64(value).unwrap_or(match value.sign() {
+        num_bigint::Sign::Minus => f64::NEG_INFINITY,
+        _ => f64::INFINITY,
+    })
+}
+
+// The digits after the point a float gets when no precision is asked for: enough for everyday
+// results, while hiding rounding noise such as that of `0.1 + 0.2`.
+const DEFAULT_PRECISION: usize = 10;
+
+// Floats round to the precision asked for, as in `{:.4}`, with trailing zeros dropped but always
+// a digit after the point, so a float never reads as an integer: 1/3 is `0.3333` and 7 is `7.0`.
+// Without a precision they round to `DEFAULT_PRECISION` digits, except that magnitudes too small
+// or too large to show well that way print exactly, in the shortest form that reads back the
+// same, as in `1e-12` and `1e300`.
+fn format_float(value: f64, precision: usize) -> String {
+    let mut text = format!("{:.*}", precision, value);
+    if !value.is_finite() {
+        return text;
+    }
+    if text.contains('.') {
+        text.truncate(text.trim_end_matches('0').len());
+    }
+    if !text.contains('.') {
+        text.push('.');
+    }
+    if text.ends_with('.') {
+        text.push('0');
+    }
+    text
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Value::Int(value) => write!(f, "{}", value),
+            Value::Float(value) => match f.precision() {
+                Some(precision) => write!(f, "{}", format_float(*value, precision)),
+                None if *value != 0.0 && !(1e-4..1e16).contains(&value.abs()) => write!(f, "{:?}", value),
+                None => write!(f, "{}", format_float(*value, DEFAULT_PRECISION)),
+            },
+            Value::Bool(value) => write!(f, "{}", value),
+            Value::Function(function) => write!(f, "<function {}>", function.name),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_display() {
+        assert_eq!(Value::Int(7.into()).to_string(), "7");
+        assert_eq!(format!("{:.3}", Value::Int(7.into())), "7");
+        assert_eq!(Value::Float(7.0).to_string(), "7.0");
+        assert_eq!(Value::Float(3.5).to_string(), "3.5");
+        assert_eq!(Value::Float(1.0 / 3.0).to_string(), "0.3333333333");
+        assert_eq!(Value::Float(0.1 + 0.2).to_string(), "0.3");
+        assert_eq!(Value::Float(-2.0 / 3.0).to_string(), "-0.6666666667");
+        assert_eq!(Value::Float(1e-12).to_string(), "1e-12");
+        assert_eq!(Value::Float(1e300).to_string(), "1e300");
+        assert_eq!(Value::Float(f64::NAN).to_string(), "NaN");
+        assert_eq!(format!("{:.4}", Value::Float(1.0 / 3.0)), "0.3333");
+        assert_eq!(format!("{:.4}", Value::Float(2.0 / 3.0)), "0.6667");
+        assert_eq!(format!("{:.4}", Value::Float(3.5)), "3.5");
+        assert_eq!(format!("{:.4}", Value::Float(7.0)), "7.0");
+        assert_eq!(format!("{:.2}", Value::Float(6.999)), "7.0");
+        assert_eq!(format!("{:.0}", Value::Float(-2.5)), "-2.0");
+        assert_eq!(format!("{:.3}", Value::Float(f64::INFINITY)), "inf");
+        assert_eq!(format!("{:.3}", Value::Bool(true)), "true");
+    }
+}